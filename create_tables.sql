@@ -1,9 +1,35 @@
 PRAGMA foreign_keys = ON;
 
+-- Safe to run again on an existing database, it only adds the missing tables. The app keeps the schema version
+-- in user_version and adds missing columns when it opens the database, so the version isn't set here
+
 CREATE TABLE IF NOT EXISTS budgets (
     id TEXT PRIMARY KEY,
     name TEXT NOT NULL,
-    total REAL
+    total REAL,
+    -- Bumped on every save, a save based on an older version is rejected
+    version INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS payees (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS payee_aliases (
+    payee_id TEXT NOT NULL,
+    alias TEXT NOT NULL,
+    PRIMARY KEY (payee_id, alias),
+    FOREIGN KEY (payee_id)
+       REFERENCES payees (id)
+       ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS accounts (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    kind TEXT NOT NULL,
+    opening_balance REAL NOT NULL DEFAULT 0
 );
 
 CREATE TABLE IF NOT EXISTS transactions (
@@ -11,16 +37,78 @@ CREATE TABLE IF NOT EXISTS transactions (
     name TEXT NOT NULL,
     value REAL,
     budget_id TEXT NOT NULL,
+    date TEXT NOT NULL,
+    payee_id TEXT,
+    account_id TEXT,
+    status TEXT NOT NULL DEFAULT 'pending',
+    paid_by TEXT,
+    -- Id the bank gave the transaction, e.g., an OFX FITID
+    external_id TEXT,
+    FOREIGN KEY (budget_id)
+       REFERENCES budgets (id)
+       ON DELETE CASCADE,
+    FOREIGN KEY (payee_id)
+       REFERENCES payees (id)
+       ON DELETE SET NULL,
+    FOREIGN KEY (account_id)
+       REFERENCES accounts (id)
+       ON DELETE SET NULL
+);
+
+CREATE TABLE IF NOT EXISTS budget_members (
+    budget_id TEXT NOT NULL,
+    member TEXT NOT NULL,
+    PRIMARY KEY (budget_id, member),
     FOREIGN KEY (budget_id)
        REFERENCES budgets (id)
        ON DELETE CASCADE
 );
 
+CREATE TABLE IF NOT EXISTS transaction_shares (
+    transaction_id TEXT NOT NULL,
+    member TEXT NOT NULL,
+    amount REAL NOT NULL,
+    PRIMARY KEY (transaction_id, member),
+    FOREIGN KEY (transaction_id)
+       REFERENCES transactions (id)
+       ON DELETE CASCADE
+);
+
+-- last_fired is the start of the period the threshold last fired in
+CREATE TABLE IF NOT EXISTS budget_alerts (
+    budget_id TEXT NOT NULL,
+    threshold INTEGER NOT NULL,
+    last_fired TEXT,
+    PRIMARY KEY (budget_id, threshold),
+    FOREIGN KEY (budget_id)
+       REFERENCES budgets (id)
+       ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS income (
+    id TEXT PRIMARY KEY,
+    source TEXT NOT NULL,
+    amount REAL NOT NULL
+);
+
+-- A single row, its version is bumped on every save of the income and assignments like a budget's
+CREATE TABLE IF NOT EXISTS income_pool (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    version INTEGER NOT NULL
+);
+
+-- Assignments are kept when a budget is deleted, the money was still given a job
+CREATE TABLE IF NOT EXISTS assignments (
+    id TEXT PRIMARY KEY,
+    budget_id TEXT NOT NULL,
+    amount REAL NOT NULL
+);
+
 -- INSERT INTO budgets (id, name, total)
 -- VALUES ("576bc364-7574-40ce-92ca-f488c613b7ea", "my-budget", 200.00);
 
--- INSERT INTO transactions (id, name, value, budget_id)
--- VALUES ("621dba84-399b-4846-8a9e-76a2d1692683", "cheeseborger", 3.99, "576bc364-7574-40ce-92ca-f488c613b7ea")
+-- INSERT INTO transactions (id, name, value, budget_id, date)
+-- VALUES ("621dba84-399b-4846-8a9e-76a2d1692683", "cheeseborger", 3.99, "576bc364-7574-40ce-92ca-f488c613b7ea", "2023-09-01")
 
 -- select * from budgets as b
 -- join transactions as t ON b.id == t.budget_id
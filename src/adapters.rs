@@ -1 +1,18 @@
+pub mod async_repository;
+pub mod bank_csv;
+pub mod backups;
+#[cfg(test)]
+mod conformance;
+pub mod csv_export;
+pub mod export;
+pub mod file_repository;
+pub mod in_memory_repository;
+pub mod json_file_repository;
+pub mod ledger;
+pub mod ofx;
+pub mod qif;
 pub mod repository;
+#[cfg(feature = "sqlcipher")]
+pub mod sqlcipher;
+pub mod statement;
+pub mod toml_dir_repository;
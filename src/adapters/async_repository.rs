@@ -0,0 +1,472 @@
+//! Async variants of the repository traits, for embedding the handlers in an async service
+//! Budgets are handed over by value since a `BudgetManager` can't be shared between threads
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic;
+use std::sync::Arc;
+
+use tokio::task;
+
+use crate::adapters::repository::{
+    AccountRepository, ConcurrencyConflict, IncomeRepository, PayeeRepository, Repository, SQLiteRepository,
+    SnapshotRepository, ViewRepository,
+};
+use crate::domain::models;
+
+pub trait AsyncRepository: Send + Sync {
+    /// Saves the budget and bumps its version, unless the stored version differs from the one it was loaded with,
+    /// gives the saved budget back
+    fn add(
+        &self,
+        item: models::BudgetManager,
+    ) -> impl Future<Output = Result<models::BudgetManager, ConcurrencyConflict>> + Send;
+    /// Saves the budgets like `add` would, adapters that can save them together write nothing when one is in conflict,
+    /// gives the saved budgets back
+    fn add_all(
+        &self,
+        items: Vec<models::BudgetManager>,
+    ) -> impl Future<Output = Result<Vec<models::BudgetManager>, ConcurrencyConflict>> + Send {
+        async move {
+            let mut saved = Vec::with_capacity(items.len());
+            for item in items {
+                saved.push(self.add(item).await?);
+            }
+
+            Ok(saved)
+        }
+    }
+    fn get(&self, id: &models::BudgetId) -> impl Future<Output = models::BudgetManager> + Send;
+    /// Budgets in the same order as `ids`, `None` for an id that isn't stored
+    fn get_many(&self, ids: &[models::BudgetId]) -> impl Future<Output = Vec<Option<models::BudgetManager>>> + Send;
+    fn delete(&self, id: &models::BudgetId) -> impl Future<Output = ()> + Send;
+}
+
+pub trait AsyncPayeeRepository: Send + Sync {
+    fn add_payee(&self, payee: &models::Payee) -> impl Future<Output = ()> + Send;
+    fn get_payee(&self, id: &str) -> impl Future<Output = Option<models::Payee>> + Send;
+    fn list_payees(&self) -> impl Future<Output = Vec<models::Payee>> + Send;
+}
+
+pub trait AsyncAccountRepository: Send + Sync {
+    fn add_account(&self, account: &models::Account) -> impl Future<Output = ()> + Send;
+    fn get_account(&self, id: &str) -> impl Future<Output = Option<models::Account>> + Send;
+    fn list_accounts(&self) -> impl Future<Output = Vec<models::Account>> + Send;
+    /// Ids of every budget holding at least one transaction paid from the account
+    fn account_budget_ids(&self, account_id: &str) -> impl Future<Output = Vec<models::BudgetId>> + Send;
+    /// Account id and external id of every transaction in any budget that has an external id
+    fn external_ids(&self) -> impl Future<Output = Vec<(Option<String>, String)>> + Send;
+}
+
+/// The income pool is a single aggregate per database, handed over by value like a budget
+pub trait AsyncIncomeRepository: Send + Sync {
+    /// Saves the pool and bumps its version, unless the stored version differs from the one it was loaded with,
+    /// gives the saved pool back
+    fn add_income_pool(
+        &self,
+        pool: models::IncomePool,
+    ) -> impl Future<Output = Result<models::IncomePool, ConcurrencyConflict>> + Send;
+    fn get_income_pool(&self) -> impl Future<Output = models::IncomePool> + Send;
+    /// Saves budgets together with the pool that funds them like `AsyncRepository::add` and `add_income_pool` would,
+    /// nothing is written when one of them is in conflict, gives the saved budgets and pool back
+    fn add_funded(
+        &self,
+        items: Vec<models::BudgetManager>,
+        pool: models::IncomePool,
+    ) -> impl Future<Output = Result<(Vec<models::BudgetManager>, models::IncomePool), ConcurrencyConflict>> + Send;
+}
+
+/// A whole snapshot saved at once, e.g., for an import
+pub trait AsyncSnapshotRepository: Send + Sync {
+    /// Saves the payees and accounts like `add_payee` and `add_account` would, together with the budgets and pool
+    /// like `AsyncIncomeRepository::add_funded` would, nothing is written when a budget or the pool is in conflict,
+    /// gives the saved snapshot back
+    fn add_snapshot(
+        &self,
+        snapshot: models::Snapshot,
+    ) -> impl Future<Output = Result<models::Snapshot, ConcurrencyConflict>> + Send;
+}
+
+/// Read-only queries behind the views, answered without loading whole budgets
+pub trait AsyncViewRepository: Send + Sync {
+    /// Every budget with its members but without its transactions, oldest first
+    fn list_budgets(&self) -> impl Future<Output = Vec<models::Budget>> + Send;
+    /// A budget's transactions by date, in the order they were added within a day
+    fn list_transactions(&self, budget_id: &models::BudgetId) -> impl Future<Output = Vec<models::Transaction>> + Send;
+    /// What was spent from an account as (cleared, pending), reconciled transactions count as cleared
+    fn account_spend(&self, account_id: &str) -> impl Future<Output = (f64, f64)> + Send;
+    /// Total and number of transactions per payee id, payees without transactions are left out
+    fn payee_spend(&self) -> impl Future<Output = HashMap<String, (f64, i64)>> + Send;
+}
+
+/// Runs the queries of a `SQLiteRepository` on tokio's blocking pool so they don't stall the runtime,
+/// needs to be used from within a tokio runtime
+#[derive(Clone)]
+pub struct AsyncSQLiteRepository {
+    inner: Arc<SQLiteRepository>,
+}
+
+impl AsyncSQLiteRepository {
+    pub fn new(filename: String) -> Self {
+        AsyncSQLiteRepository::from(SQLiteRepository::new(filename))
+    }
+
+    /// The blocking repository underneath, e.g., for the views
+    pub fn blocking(&self) -> &SQLiteRepository {
+        &self.inner
+    }
+
+    /// Runs `query` against the blocking repository on the blocking pool
+    async fn run<R, F>(&self, query: F) -> R
+    where
+        F: FnOnce(&SQLiteRepository) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+
+        match task::spawn_blocking(move || query(&inner)).await {
+            Ok(result) => result,
+            // Panic in the caller as the blocking repository would have, e.g., when a budget isn't found
+            Err(e) => panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+impl From<SQLiteRepository> for AsyncSQLiteRepository {
+    fn from(repo: SQLiteRepository) -> Self {
+        AsyncSQLiteRepository { inner: Arc::new(repo) }
+    }
+}
+
+impl AsyncRepository for AsyncSQLiteRepository {
+    async fn add(&self, item: models::BudgetManager) -> Result<models::BudgetManager, ConcurrencyConflict> {
+        self.run(move |repo| repo.add(&item).map(|_| item)).await
+    }
+
+    async fn add_all(&self, items: Vec<models::BudgetManager>) -> Result<Vec<models::BudgetManager>, ConcurrencyConflict> {
+        self.run(move |repo| repo.add_all(&items).map(|_| items)).await
+    }
+
+    async fn get(&self, id: &models::BudgetId) -> models::BudgetManager {
+        let id = *id;
+
+        self.run(move |repo| repo.get(&id)).await
+    }
+
+    async fn get_many(&self, ids: &[models::BudgetId]) -> Vec<Option<models::BudgetManager>> {
+        let ids = ids.to_vec();
+
+        self.run(move |repo| repo.get_many(&ids)).await
+    }
+
+    async fn delete(&self, id: &models::BudgetId) {
+        let id = *id;
+
+        self.run(move |repo| repo.delete(&id)).await
+    }
+}
+
+impl AsyncPayeeRepository for AsyncSQLiteRepository {
+    async fn add_payee(&self, payee: &models::Payee) {
+        let payee = payee.clone();
+
+        self.run(move |repo| repo.add_payee(&payee)).await
+    }
+
+    async fn get_payee(&self, id: &str) -> Option<models::Payee> {
+        let id = id.to_string();
+
+        self.run(move |repo| repo.get_payee(&id)).await
+    }
+
+    async fn list_payees(&self) -> Vec<models::Payee> {
+        self.run(|repo| repo.list_payees()).await
+    }
+}
+
+impl AsyncAccountRepository for AsyncSQLiteRepository {
+    async fn add_account(&self, account: &models::Account) {
+        let account = account.clone();
+
+        self.run(move |repo| repo.add_account(&account)).await
+    }
+
+    async fn get_account(&self, id: &str) -> Option<models::Account> {
+        let id = id.to_string();
+
+        self.run(move |repo| repo.get_account(&id)).await
+    }
+
+    async fn list_accounts(&self) -> Vec<models::Account> {
+        self.run(|repo| repo.list_accounts()).await
+    }
+
+    async fn account_budget_ids(&self, account_id: &str) -> Vec<models::BudgetId> {
+        let account_id = account_id.to_string();
+
+        self.run(move |repo| repo.account_budget_ids(&account_id)).await
+    }
+
+    async fn external_ids(&self) -> Vec<(Option<String>, String)> {
+        self.run(|repo| repo.external_ids()).await
+    }
+}
+
+impl AsyncIncomeRepository for AsyncSQLiteRepository {
+    async fn add_income_pool(&self, pool: models::IncomePool) -> Result<models::IncomePool, ConcurrencyConflict> {
+        self.run(move |repo| repo.add_income_pool(&pool).map(|_| pool)).await
+    }
+
+    async fn get_income_pool(&self) -> models::IncomePool {
+        self.run(|repo| repo.get_income_pool()).await
+    }
+
+    async fn add_funded(
+        &self,
+        items: Vec<models::BudgetManager>,
+        pool: models::IncomePool,
+    ) -> Result<(Vec<models::BudgetManager>, models::IncomePool), ConcurrencyConflict> {
+        self.run(move |repo| repo.add_funded(&items, &pool).map(|_| (items, pool))).await
+    }
+}
+
+impl AsyncSnapshotRepository for AsyncSQLiteRepository {
+    async fn add_snapshot(&self, snapshot: models::Snapshot) -> Result<models::Snapshot, ConcurrencyConflict> {
+        self.run(move |repo| repo.add_snapshot(&snapshot).map(|_| snapshot)).await
+    }
+}
+
+impl AsyncViewRepository for AsyncSQLiteRepository {
+    async fn list_budgets(&self) -> Vec<models::Budget> {
+        self.run(|repo| repo.list_budgets()).await
+    }
+
+    async fn list_transactions(&self, budget_id: &models::BudgetId) -> Vec<models::Transaction> {
+        let budget_id = *budget_id;
+        self.run(move |repo| repo.list_transactions(&budget_id)).await
+    }
+
+    async fn account_spend(&self, account_id: &str) -> (f64, f64) {
+        let account_id = account_id.to_string();
+        self.run(move |repo| repo.account_spend(&account_id)).await
+    }
+
+    async fn payee_spend(&self) -> HashMap<String, (f64, i64)> {
+        self.run(|repo| repo.payee_spend()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use tempfile::TempDir;
+    use tokio::runtime::{self, Runtime};
+
+    use super::*;
+    use crate::domain::clock::{Clock, SystemClock};
+    use crate::domain::ids::RandomIds;
+    use crate::adapters::conformance::conformance_tests;
+
+    fn temp_repo() -> (AsyncSQLiteRepository, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = AsyncSQLiteRepository::new(dir.path().join("budgets.db").to_string_lossy().to_string());
+        repo.blocking().create_tables();
+
+        (repo, dir)
+    }
+
+    /// Waits on the async methods so the conformance suite can run against them
+    struct BlockingOn {
+        repo: AsyncSQLiteRepository,
+        runtime: Runtime,
+    }
+
+    impl Repository for BlockingOn {
+        fn add(&self, item: &models::BudgetManager) -> Result<(), ConcurrencyConflict> {
+            let saved = self.runtime.block_on(AsyncRepository::add(&self.repo, item.clone()))?;
+            item.set_version(saved.version());
+
+            Ok(())
+        }
+
+        fn add_all(&self, items: &[models::BudgetManager]) -> Result<(), ConcurrencyConflict> {
+            let saved = self.runtime.block_on(AsyncRepository::add_all(&self.repo, items.to_vec()))?;
+            for (item, saved) in items.iter().zip(saved) {
+                item.set_version(saved.version());
+            }
+
+            Ok(())
+        }
+
+        fn get(&self, id: &models::BudgetId) -> models::BudgetManager {
+            self.runtime.block_on(AsyncRepository::get(&self.repo, id))
+        }
+
+        fn get_many(&self, ids: &[models::BudgetId]) -> Vec<Option<models::BudgetManager>> {
+            self.runtime.block_on(AsyncRepository::get_many(&self.repo, ids))
+        }
+
+        fn delete(&self, id: &models::BudgetId) {
+            self.runtime.block_on(AsyncRepository::delete(&self.repo, id))
+        }
+    }
+
+    impl PayeeRepository for BlockingOn {
+        fn add_payee(&self, payee: &models::Payee) {
+            self.runtime.block_on(AsyncPayeeRepository::add_payee(&self.repo, payee))
+        }
+
+        fn get_payee(&self, id: &str) -> Option<models::Payee> {
+            self.runtime.block_on(AsyncPayeeRepository::get_payee(&self.repo, id))
+        }
+
+        fn list_payees(&self) -> Vec<models::Payee> {
+            self.runtime.block_on(AsyncPayeeRepository::list_payees(&self.repo))
+        }
+    }
+
+    impl AccountRepository for BlockingOn {
+        fn add_account(&self, account: &models::Account) {
+            self.runtime.block_on(AsyncAccountRepository::add_account(&self.repo, account))
+        }
+
+        fn get_account(&self, id: &str) -> Option<models::Account> {
+            self.runtime.block_on(AsyncAccountRepository::get_account(&self.repo, id))
+        }
+
+        fn list_accounts(&self) -> Vec<models::Account> {
+            self.runtime.block_on(AsyncAccountRepository::list_accounts(&self.repo))
+        }
+
+        fn account_budget_ids(&self, account_id: &str) -> Vec<models::BudgetId> {
+            self.runtime.block_on(AsyncAccountRepository::account_budget_ids(&self.repo, account_id))
+        }
+
+        fn external_ids(&self) -> Vec<(Option<String>, String)> {
+            self.runtime.block_on(AsyncAccountRepository::external_ids(&self.repo))
+        }
+    }
+
+    impl IncomeRepository for BlockingOn {
+        fn add_income_pool(&self, pool: &models::IncomePool) -> Result<(), ConcurrencyConflict> {
+            let saved = self.runtime.block_on(AsyncIncomeRepository::add_income_pool(&self.repo, pool.clone()))?;
+            pool.set_version(saved.version());
+
+            Ok(())
+        }
+
+        fn get_income_pool(&self) -> models::IncomePool {
+            self.runtime.block_on(AsyncIncomeRepository::get_income_pool(&self.repo))
+        }
+
+        fn add_funded(&self, items: &[models::BudgetManager], pool: &models::IncomePool) -> Result<(), ConcurrencyConflict> {
+            let (saved, saved_pool) = self
+                .runtime
+                .block_on(AsyncIncomeRepository::add_funded(&self.repo, items.to_vec(), pool.clone()))?;
+            for (item, saved) in items.iter().zip(saved) {
+                item.set_version(saved.version());
+            }
+            pool.set_version(saved_pool.version());
+
+            Ok(())
+        }
+    }
+
+    impl SnapshotRepository for BlockingOn {
+        fn add_snapshot(&self, snapshot: &models::Snapshot) -> Result<(), ConcurrencyConflict> {
+            let saved = self
+                .runtime
+                .block_on(AsyncSnapshotRepository::add_snapshot(&self.repo, snapshot.clone()))?;
+            for (item, saved) in snapshot.budgets.iter().zip(saved.budgets) {
+                item.set_version(saved.version());
+            }
+            snapshot.income_pool.set_version(saved.income_pool.version());
+
+            Ok(())
+        }
+    }
+
+    impl ViewRepository for BlockingOn {
+        fn list_budgets(&self) -> Vec<models::Budget> {
+            self.runtime.block_on(AsyncViewRepository::list_budgets(&self.repo))
+        }
+
+        fn list_transactions(&self, budget_id: &models::BudgetId) -> Vec<models::Transaction> {
+            self.runtime.block_on(AsyncViewRepository::list_transactions(&self.repo, budget_id))
+        }
+
+        fn account_spend(&self, account_id: &str) -> (f64, f64) {
+            self.runtime.block_on(AsyncViewRepository::account_spend(&self.repo, account_id))
+        }
+
+        fn payee_spend(&self) -> HashMap<String, (f64, i64)> {
+            self.runtime.block_on(AsyncViewRepository::payee_spend(&self.repo))
+        }
+    }
+
+    fn blocking_temp_repo() -> (BlockingOn, TempDir) {
+        let (repo, dir) = temp_repo();
+        let runtime = runtime::Builder::new_current_thread().build().unwrap();
+
+        (BlockingOn { repo, runtime }, dir)
+    }
+
+    conformance_tests!(blocking_temp_repo());
+
+    fn build_budget_manager_with_tx() -> models::BudgetManager {
+        let budget = models::Budget::new(&RandomIds, String::from("Groceries"), 200.00_f64);
+        let tx = models::Transaction::new(&RandomIds, String::from("Apples"), 3.50_f64, *budget.id(), SystemClock.today());
+
+        models::BudgetManager::new(budget, RefCell::new(vec![tx]))
+    }
+
+    #[tokio::test]
+    async fn can_add_retrieve_budget_manager_aggregate_async() {
+        // Given
+        let (repo, _dir) = temp_repo();
+        let budget_manager = build_budget_manager_with_tx();
+        let id = *budget_manager.budget().id();
+
+        // When
+        let saved = repo.add(budget_manager).await.unwrap();
+        let retrieved = repo.get(&id).await;
+
+        // Then
+        assert_eq!(saved.version(), 1);
+        assert_eq!(retrieved.version(), 1);
+        assert_eq!(retrieved.transactions().borrow().len(), 1);
+        assert_eq!(retrieved.budget().name(), "Groceries");
+    }
+
+    #[tokio::test]
+    async fn saving_stale_budget_manager_async_is_a_conflict() {
+        // Given
+        let (repo, _dir) = temp_repo();
+        let budget_manager = repo.add(build_budget_manager_with_tx()).await.unwrap();
+        let id = *budget_manager.budget().id();
+        let stale = repo.get(&id).await;
+        repo.add(budget_manager).await.unwrap();
+
+        // When
+        let result = repo.add(stale).await;
+
+        // Then
+        assert_eq!(
+            result.err(),
+            Some(ConcurrencyConflict {
+                budget_id: Some(id),
+                expected: 1,
+                found: 2,
+            })
+        );
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "Budget not found")]
+    async fn missing_budget_panics_in_the_caller() {
+        let (repo, _dir) = temp_repo();
+
+        repo.get(&models::BudgetId::new()).await;
+    }
+}
@@ -0,0 +1,227 @@
+//! Timestamped backups of the SQLite database kept in a directory, the oldest go once there are too many
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+
+use crate::adapters::repository::SQLiteRepository;
+
+/// Sorts the same as the time it stands for, so backups sort by name
+const TIMESTAMP: &str = "%Y%m%dT%H%M%S";
+
+/// Writes `budgets-<timestamp>.db` into `dir` and removes the oldest backups past `keep`
+pub fn backup(repo: &SQLiteRepository, dir: &Path, keep: usize, now: NaiveDateTime) -> PathBuf {
+    if let Err(e) = fs::create_dir_all(dir) {
+        panic!("Error creating {}: {}", dir.display(), e);
+    }
+
+    let path = dir.join(format!("budgets-{}.db", now.format(TIMESTAMP)));
+    repo.backup_to(&path);
+
+    let backups = list(dir);
+    for old in &backups[..backups.len().saturating_sub(keep)] {
+        fs::remove_file(old).unwrap();
+    }
+
+    path
+}
+
+/// Backups in `dir`, oldest first, other files are left out
+pub fn list(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut backups: Vec<PathBuf> = entries
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str()?.strip_prefix("budgets-")?.strip_suffix(".db"))
+                .is_some_and(|timestamp| NaiveDateTime::parse_from_str(timestamp, TIMESTAMP).is_ok())
+        })
+        .collect();
+    backups.sort();
+
+    backups
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use chrono::NaiveDate;
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::adapters::repository::{IncomeRepository, Repository, RestoreError, ViewRepository, SCHEMA_VERSION};
+    use crate::domain::ids::RandomIds;
+    use crate::domain::models;
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 9, 1).unwrap().and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    fn budget_manager(name: &str) -> models::BudgetManager {
+        models::BudgetManager::new(models::Budget::new(&RandomIds, String::from(name), 100.00_f64), RefCell::new(vec![]))
+    }
+
+    #[test]
+    fn keeps_only_the_newest_backups() {
+        // Given
+        let dir = tempfile::tempdir().unwrap();
+        let repo = SQLiteRepository::new(dir.path().join("budgets.db").to_string_lossy().to_string());
+        repo.create_tables();
+        let backups = dir.path().join("backups");
+        fs::create_dir(&backups).unwrap();
+        fs::write(backups.join("notes.txt"), "not a backup").unwrap();
+
+        // When
+        for hour in 1..=4 {
+            backup(&repo, &backups, 2, at(hour));
+        }
+
+        // Then
+        assert_eq!(
+            list(&backups),
+            vec![backups.join("budgets-20230901T030000.db"), backups.join("budgets-20230901T040000.db")]
+        );
+        assert!(backups.join("notes.txt").exists());
+    }
+
+    #[test]
+    fn restores_the_database_from_a_backup() {
+        // Given
+        let dir = tempfile::tempdir().unwrap();
+        let repo = SQLiteRepository::new(dir.path().join("budgets.db").to_string_lossy().to_string());
+        repo.create_tables();
+        let kept = budget_manager("kept");
+        repo.add(&kept).unwrap();
+
+        let path = backup(&repo, dir.path(), 1, at(1));
+        let lost = budget_manager("lost");
+        repo.add(&lost).unwrap();
+        repo.delete(kept.id());
+
+        // When
+        let result = repo.restore_from(&path);
+
+        // Then
+        assert_eq!(result, Ok(()));
+        assert_eq!(repo.list_budgets(), vec![kept.budget().clone()]);
+    }
+
+    #[test]
+    fn restores_and_migrates_a_backup_of_an_older_schema() {
+        // Given
+        let dir = tempfile::tempdir().unwrap();
+        let repo = SQLiteRepository::new(dir.path().join("budgets.db").to_string_lossy().to_string());
+        repo.create_tables();
+        let kept = budget_manager("kept");
+        repo.add(&kept).unwrap();
+
+        let path = backup(&repo, dir.path(), 1, at(1));
+        // As if the backup was made before the pool was versioned
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch("DROP TABLE income_pool; PRAGMA user_version = 2;")
+            .unwrap();
+        repo.delete(kept.id());
+
+        // When
+        let result = repo.restore_from(&path);
+
+        // Then
+        assert_eq!(result, Ok(()));
+        assert_eq!(repo.get(kept.id()).budget(), kept.budget());
+        repo.add_income_pool(&models::IncomePool::new()).unwrap();
+
+        let version: i64 = repo
+            .conn()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn refuses_a_backup_of_a_newer_schema_version() {
+        // Given
+        let dir = tempfile::tempdir().unwrap();
+        let repo = SQLiteRepository::new(dir.path().join("budgets.db").to_string_lossy().to_string());
+        repo.create_tables();
+        let kept = budget_manager("kept");
+        repo.add(&kept).unwrap();
+
+        let path = backup(&repo, dir.path(), 1, at(1));
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        repo.delete(kept.id());
+
+        // When
+        let result = repo.restore_from(&path);
+
+        // Then
+        assert_eq!(
+            result,
+            Err(RestoreError::SchemaVersion {
+                found: SCHEMA_VERSION + 1,
+                newest: SCHEMA_VERSION,
+            })
+        );
+        assert_eq!(repo.list_budgets(), vec![]);
+    }
+
+    #[test]
+    fn refuses_a_file_that_is_not_a_database() {
+        // Given
+        let dir = tempfile::tempdir().unwrap();
+        let repo = SQLiteRepository::new(dir.path().join("budgets.db").to_string_lossy().to_string());
+        repo.create_tables();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "definitely not a database, just some notes").unwrap();
+
+        // When
+        let missing = repo.restore_from(&dir.path().join("missing.db"));
+        let garbage = repo.restore_from(&path);
+
+        // Then
+        assert!(matches!(missing, Err(RestoreError::Unreadable(_))));
+        assert!(matches!(garbage, Err(RestoreError::Unreadable(_))));
+    }
+
+    #[test]
+    fn refuses_a_database_that_is_empty_or_from_another_app() {
+        // Given
+        let dir = tempfile::tempdir().unwrap();
+        let repo = SQLiteRepository::new(dir.path().join("budgets.db").to_string_lossy().to_string());
+        repo.create_tables();
+        let kept = budget_manager("kept");
+        repo.add(&kept).unwrap();
+
+        let empty = dir.path().join("empty.db");
+        Connection::open(&empty).unwrap().execute_batch("VACUUM").unwrap();
+        let other = dir.path().join("other.db");
+        Connection::open(&other)
+            .unwrap()
+            .execute_batch("CREATE TABLE budgets (id TEXT PRIMARY KEY, title TEXT)")
+            .unwrap();
+
+        // When
+        let from_empty = repo.restore_from(&empty);
+        let from_other = repo.restore_from(&other);
+
+        // Then
+        assert_eq!(
+            from_empty,
+            Err(RestoreError::MissingTables(vec![String::from("budgets"), String::from("transactions")]))
+        );
+        assert_eq!(
+            from_other.unwrap_err().to_string(),
+            "the backup isn't a budget database, it has no transactions table"
+        );
+        assert_eq!(repo.list_budgets(), vec![kept.budget().clone()]);
+    }
+}
@@ -0,0 +1,320 @@
+//! Reads the CSV files banks export into rows that can become transactions
+//! Every bank lays its file out differently, a `CsvMapping` says which columns to read and how.
+//! Mappings can be saved under a name, e.g., one per bank, in a TOML file of presets
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::adapters::file_repository;
+use crate::adapters::statement::{self, BankRow, Statement, StatementError};
+
+/// Which sign the bank gives money leaving the account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpendingSign {
+    /// What checking accounts usually export
+    Negative,
+    /// What credit cards usually export
+    Positive,
+}
+
+impl FromStr for SpendingSign {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "negative" => Ok(SpendingSign::Negative),
+            "positive" => Ok(SpendingSign::Positive),
+            _ => Err(format!("Unknown spending sign {}, expected negative or positive", s)),
+        }
+    }
+}
+
+/// How to read a bank's CSV file, a column is its header or its position counting from 1
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CsvMapping {
+    pub date: String,
+    pub description: String,
+    pub amount: String,
+    pub spending_sign: SpendingSign,
+    /// As chrono formats it, e.g., %Y-%m-%d or %d/%m/%Y
+    pub date_format: String,
+    pub delimiter: char,
+    /// Amounts are written like 1.234,56
+    pub decimal_comma: bool,
+}
+
+impl Default for CsvMapping {
+    fn default() -> Self {
+        CsvMapping {
+            date: String::from("Date"),
+            description: String::from("Description"),
+            amount: String::from("Amount"),
+            spending_sign: SpendingSign::Negative,
+            date_format: String::from("%Y-%m-%d"),
+            delimiter: ',',
+            decimal_comma: false,
+        }
+    }
+}
+
+/// Reads every row of the file, stops at the first row that doesn't fit the mapping
+pub fn read(input: impl Read, mapping: &CsvMapping) -> Result<Statement, StatementError> {
+    if !mapping.delimiter.is_ascii() {
+        return Err(StatementError::new(None, format!("the delimiter {} isn't a single byte", mapping.delimiter)));
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(mapping.delimiter as u8)
+        .flexible(true)
+        .from_reader(input);
+
+    let headers = reader.headers().map_err(|e| StatementError::new(None, e.to_string()))?.clone();
+    let date = column(&headers, &mapping.date)?;
+    let description = column(&headers, &mapping.description)?;
+    let amount = column(&headers, &mapping.amount)?;
+
+    let mut statement = Statement::default();
+    let mut row_ids = statement::RowIds::default();
+
+    for record in reader.records() {
+        let record = record.map_err(|e| StatementError::new(e.position().map(|p| p.line()), e.to_string()))?;
+        let line = record.position().map_or(0, |p| p.line());
+
+        let field = |idx: usize, name: &str| {
+            record
+                .get(idx)
+                .map(str::trim)
+                .ok_or(StatementError::new(Some(line), format!("no {} column", name)))
+        };
+
+        let raw_amount = field(amount, "amount")?;
+        let value = parse_amount(raw_amount, mapping.decimal_comma)
+            .ok_or(StatementError::new(Some(line), format!("{} is not an amount", raw_amount)))?;
+
+        let spent = match mapping.spending_sign {
+            SpendingSign::Negative => -value,
+            SpendingSign::Positive => value,
+        };
+        if spent <= 0_f64 {
+            statement.skipped.push(line);
+            continue;
+        }
+
+        let raw_date = field(date, "date")?;
+        let date = NaiveDate::parse_from_str(raw_date, &mapping.date_format).map_err(|_| {
+            StatementError::new(Some(line), format!("{} is not a date like {}", raw_date, mapping.date_format))
+        })?;
+
+        let description = statement::normalize_description(field(description, "description")?);
+        let external_id = row_ids.next(date, &description, spent);
+
+        statement.spending.push(BankRow {
+            line,
+            date,
+            description,
+            amount: spent,
+            external_id: Some(external_id),
+        });
+    }
+
+    Ok(statement)
+}
+
+fn column(headers: &csv::StringRecord, name: &str) -> Result<usize, StatementError> {
+    if let Ok(position) = name.parse::<usize>() {
+        return match position {
+            1.. if position <= headers.len() => Ok(position - 1),
+            _ => Err(StatementError::new(None, format!("there is no column {}", position))),
+        };
+    }
+
+    headers
+        .iter()
+        .position(|header| header.trim_start_matches('\u{feff}').trim().eq_ignore_ascii_case(name.trim()))
+        .ok_or(StatementError::new(None, format!("there is no column named {}", name)))
+}
+
+/// Understands currency symbols, thousands separators and accounting style negatives like (12.50)
+fn parse_amount(raw: &str, decimal_comma: bool) -> Option<f64> {
+    let cleaned: String = raw
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '$' | '€' | '£'))
+        .collect();
+
+    let (negative, digits) = match cleaned.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+        Some(digits) => (true, digits),
+        None => (false, cleaned.as_str()),
+    };
+
+    let digits = match decimal_comma {
+        true => digits.replace('.', "").replace(',', "."),
+        false => digits.replace(',', ""),
+    };
+
+    let amount: f64 = digits.parse().ok().filter(|amount: &f64| amount.is_finite())?;
+
+    Some(if negative { -amount } else { amount })
+}
+
+/// Mappings saved by name
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Presets {
+    presets: BTreeMap<String, CsvMapping>,
+}
+
+impl Presets {
+    /// No presets when the file doesn't exist yet
+    pub fn load(path: &Path) -> Self {
+        match file_repository::read_file(path) {
+            Some(contents) => match toml::from_str(&contents) {
+                Ok(presets) => presets,
+                Err(e) => panic!("Error reading {}: {}", path.display(), e),
+            },
+            None => Presets::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) {
+        file_repository::write_file(path, &toml::to_string(self).unwrap());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CsvMapping> {
+        self.presets.get(name)
+    }
+
+    pub fn insert(&mut self, name: String, mapping: CsvMapping) {
+        self.presets.insert(name, mapping);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 9, day).unwrap()
+    }
+
+    #[test]
+    fn reads_spending_and_skips_money_coming_in() {
+        // Given
+        let csv = "Date,Description,Amount\n\
+                   2023-09-01,  Corner   Grocer ,-12.50\n\
+                   2023-09-02,Salary,\"2,000.00\"\n\
+                   2023-09-03,Book shop,(7.25)\n";
+
+        // When
+        let statement = read(csv.as_bytes(), &CsvMapping::default()).unwrap();
+
+        // Then
+        assert_eq!(
+            statement.spending,
+            vec![
+                BankRow {
+                    line: 2,
+                    date: date(1),
+                    description: String::from("Corner Grocer"),
+                    amount: 12.50_f64,
+                    external_id: Some(statement::row_id(date(1), "Corner Grocer", 12.50_f64, 0)),
+                },
+                BankRow {
+                    line: 4,
+                    date: date(3),
+                    description: String::from("Book shop"),
+                    amount: 7.25_f64,
+                    external_id: Some(statement::row_id(date(3), "Book shop", 7.25_f64, 0)),
+                },
+            ]
+        );
+        assert_eq!(statement.skipped, vec![3]);
+    }
+
+    #[test]
+    fn reads_columns_by_position_with_other_conventions() {
+        // Given
+        let csv = "Buchung;Betrag;Text\n03.09.2023;1.234,56 €;Miete\n04.09.2023;-5,00;Gutschrift\n";
+        let mapping = CsvMapping {
+            date: String::from("1"),
+            description: String::from("text"),
+            amount: String::from("2"),
+            spending_sign: SpendingSign::Positive,
+            date_format: String::from("%d.%m.%Y"),
+            delimiter: ';',
+            decimal_comma: true,
+        };
+
+        // When
+        let statement = read(csv.as_bytes(), &mapping).unwrap();
+
+        // Then
+        assert_eq!(statement.spending.len(), 1);
+        assert_eq!(statement.spending[0].amount, 1234.56_f64);
+        assert_eq!(statement.spending[0].date, date(3));
+        assert_eq!(statement.spending[0].description, "Miete");
+        assert_eq!(statement.skipped, vec![3]);
+    }
+
+    #[test]
+    fn rows_keep_their_id_in_an_overlapping_export() {
+        // Given
+        let first = "Date,Description,Amount\n\
+                     2023-09-01,Coffee,-3.00\n\
+                     2023-09-01,Coffee,-3.00\n";
+        let overlapping = "Date,Description,Amount\n\
+                           2023-08-31,Rent,-900.00\n\
+                           2023-09-01,Coffee,-3.00\n\
+                           2023-09-01,Coffee,-3.00\n";
+
+        // When
+        let first = read(first.as_bytes(), &CsvMapping::default()).unwrap();
+        let overlapping = read(overlapping.as_bytes(), &CsvMapping::default()).unwrap();
+
+        // Then
+        let ids = |statement: &Statement| -> Vec<Option<String>> {
+            statement.spending.iter().map(|row| row.external_id.clone()).collect()
+        };
+        assert_ne!(first.spending[0].external_id, first.spending[1].external_id);
+        assert_eq!(ids(&overlapping)[1..], ids(&first)[..]);
+    }
+
+    #[test]
+    fn reports_the_line_that_does_not_fit() {
+        // Given
+        let csv = "Date,Description,Amount\n2023-09-01,Grocer,-1.00\n09/02/2023,Grocer,-2.00\n";
+
+        // When
+        let missing = read(csv.as_bytes(), &CsvMapping { amount: String::from("Value"), ..CsvMapping::default() });
+        let bad_date = read(csv.as_bytes(), &CsvMapping::default());
+
+        // Then
+        assert_eq!(missing.unwrap_err().to_string(), "there is no column named Value");
+        assert_eq!(bad_date.unwrap_err().to_string(), "line 3: 09/02/2023 is not a date like %Y-%m-%d");
+    }
+
+    #[test]
+    fn presets_are_saved_by_name() {
+        // Given
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("csv-presets.toml");
+        let mapping = CsvMapping {
+            date_format: String::from("%d/%m/%Y"),
+            ..CsvMapping::default()
+        };
+
+        // When
+        let mut presets = Presets::load(&path);
+        presets.insert(String::from("my-bank"), mapping.clone());
+        presets.save(&path);
+
+        // Then
+        assert_eq!(Presets::load(&path).get("my-bank"), Some(&mapping));
+        assert_eq!(Presets::load(&path).get("other-bank"), None);
+    }
+}
@@ -0,0 +1,616 @@
+//! Behaviour every storage adapter has to share, so the app works the same whichever one it runs on
+//! An adapter's tests run the whole suite with `conformance_tests!`, given an expression that builds
+//! a fresh, empty repository together with anything that has to outlive it, e.g., its temporary directory
+
+use std::cell::RefCell;
+
+use chrono::NaiveDate;
+
+use crate::adapters::repository::{ConcurrencyConflict, Storage};
+use crate::domain::clock::{Clock, SystemClock};
+use crate::domain::ids::RandomIds;
+use crate::domain::models;
+
+/// Writes a `#[test]` per conformance check for the repository `$make` builds,
+/// `$make` gives back a `(repository, guard)` pair and is evaluated once per test
+macro_rules! conformance_tests {
+    ($make:expr) => {
+        mod conformance {
+            use super::*;
+
+            $crate::adapters::conformance::conformance_tests!(@test $make;
+                can_add_and_get_budget,
+                can_get_many_budgets_in_order,
+                getting_many_gives_none_for_a_missing_budget,
+                can_save_budget_after_changes,
+                can_update_and_remove_transactions,
+                can_round_trip_shared_budget,
+                keeps_external_ids,
+                saving_stale_budget_is_a_conflict,
+                delete_cascades_to_transactions,
+                deleting_missing_budget_does_nothing,
+                can_add_and_get_payees,
+                can_add_and_get_accounts,
+                can_add_and_get_income_pool,
+                saving_the_pool_overwrites_changed_entries,
+                saves_funded_budgets_with_the_pool,
+                funded_save_with_a_stale_budget_writes_nothing,
+                saves_a_snapshot_at_once,
+                snapshot_with_a_stale_budget_writes_nothing,
+                saving_stale_income_pool_is_a_conflict,
+                saving_many_with_a_stale_budget_writes_nothing,
+                lists_budgets_in_the_order_they_were_added,
+                lists_transactions_by_date,
+                sums_spend_by_account_and_payee
+            );
+
+            $crate::adapters::conformance::conformance_tests!(@panics $make;
+                getting_missing_budget_panics,
+                getting_deleted_budget_panics
+            );
+        }
+    };
+    (@test $make:expr; $($name:ident),*) => {
+        $(
+            #[test]
+            fn $name() {
+                // The pair drops in order, so the repository is closed before its guard cleans up
+                let fixture = $make;
+
+                $crate::adapters::conformance::$name(&fixture.0);
+            }
+        )*
+    };
+    (@panics $make:expr; $($name:ident),*) => {
+        $(
+            #[test]
+            #[should_panic(expected = "Budget not found")]
+            fn $name() {
+                let fixture = $make;
+
+                $crate::adapters::conformance::$name(&fixture.0);
+            }
+        )*
+    };
+}
+
+pub(crate) use conformance_tests;
+
+fn date(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2023, 9, day).unwrap()
+}
+
+fn build_budget_manager_with_tx() -> models::BudgetManager {
+    let budget = models::Budget::new(&RandomIds, String::from("my-budget"), 200.00_f64);
+    let mut bm = models::BudgetManager::new(budget, RefCell::new(vec![]));
+    bm.add_tx(&RandomIds, String::from("cheeseborger"), 3.99_f64, SystemClock.today());
+    bm.set_alert_thresholds(vec![50, 80]);
+
+    bm
+}
+
+fn build_empty_budget_manager(name: &str) -> models::BudgetManager {
+    models::BudgetManager::new(models::Budget::new(&RandomIds, name.to_string(), 50.00_f64), RefCell::new(vec![]))
+}
+
+pub fn can_add_and_get_budget<T: Storage>(repo: &T) {
+    // Given
+    let bm = build_budget_manager_with_tx();
+
+    // When
+    repo.add(&bm).unwrap();
+
+    // Then
+    let retrieved_bm = repo.get(bm.id());
+
+    assert_eq!(retrieved_bm, bm);
+    assert_eq!(retrieved_bm.version(), 1);
+}
+
+pub fn can_get_many_budgets_in_order<T: Storage>(repo: &T) {
+    // Given
+    let with_tx = build_budget_manager_with_tx();
+    let empty = build_empty_budget_manager("empty");
+    repo.add(&with_tx).unwrap();
+    repo.add(&empty).unwrap();
+
+    // When
+    let retrieved = repo.get_many(&[*empty.id(), *with_tx.id()]);
+
+    // Then
+    assert_eq!(retrieved, vec![Some(empty), Some(with_tx)]);
+}
+
+pub fn can_save_budget_after_changes<T: Storage>(repo: &T) {
+    // Given
+    let mut bm = build_budget_manager_with_tx();
+    repo.add(&bm).unwrap();
+
+    // When
+    let tx_id = bm.add_tx(&RandomIds, String::from("fries"), 2.49_f64, SystemClock.today());
+    repo.add(&bm).unwrap();
+    bm.remove_tx(&tx_id);
+    repo.add(&bm).unwrap();
+
+    // Then
+    let retrieved_bm = repo.get(bm.id());
+
+    assert_eq!(retrieved_bm.available_funds(), bm.available_funds());
+    assert_eq!(retrieved_bm.transactions(), bm.transactions());
+    assert_eq!(retrieved_bm.version(), 3);
+}
+
+pub fn can_update_and_remove_transactions<T: Storage>(repo: &T) {
+    // Given
+    let mut bm = build_budget_manager_with_tx();
+    let kept = bm.add_tx(&RandomIds, String::from("fries"), 2.49_f64, SystemClock.today());
+    let removed = bm.add_tx(&RandomIds, String::from("shake"), 4.99_f64, SystemClock.today());
+    repo.add(&bm).unwrap();
+
+    // When
+    let mut loaded = repo.get(bm.id());
+    loaded.update_tx(&kept, 5.00_f64);
+    loaded.set_tx_status(&kept, models::TransactionStatus::Cleared);
+    loaded.remove_tx(&removed);
+    repo.add(&loaded).unwrap();
+
+    // Then
+    let retrieved_bm = repo.get(bm.id());
+    let txs = retrieved_bm.transactions().borrow();
+    let tx = txs.iter().find(|tx| *tx.id() == kept).unwrap();
+
+    assert_eq!(txs.len(), 2);
+    assert!(txs.iter().all(|tx| *tx.id() != removed));
+    assert_eq!(*tx.value(), 5.00_f64);
+    assert_eq!(tx.status(), models::TransactionStatus::Cleared);
+    assert_eq!(retrieved_bm.available_funds(), loaded.available_funds());
+}
+
+pub fn can_round_trip_shared_budget<T: Storage>(repo: &T) {
+    // Given
+    let mut bm = build_budget_manager_with_tx();
+    bm.set_members(vec![String::from("ana"), String::from("ben")]);
+
+    let shares = bm.split(30.00_f64, &models::Split::Equal);
+    bm.add_transaction(
+        models::Transaction::new(&RandomIds, String::from("internet"), 30.00_f64, *bm.id(), SystemClock.today())
+            .with_paid_by(Some(String::from("ana")))
+            .with_shares(shares),
+    );
+
+    // When
+    repo.add(&bm).unwrap();
+
+    // Then
+    let retrieved_bm = repo.get(bm.id());
+
+    assert_eq!(retrieved_bm.budget(), bm.budget());
+    assert_eq!(retrieved_bm.transactions(), bm.transactions());
+}
+
+pub fn keeps_external_ids<T: Storage>(repo: &T) {
+    // Given
+    let mut bm = build_budget_manager_with_tx();
+    bm.add_transaction(
+        models::Transaction::load(models::TransactionId::new(), String::from("grocer"), 12.50_f64, *bm.id(), date(4))
+            .with_external_id(Some(String::from("20230904-1"))),
+    );
+
+    // When
+    repo.add(&bm).unwrap();
+
+    // Then
+    let retrieved_bm = repo.get(bm.id());
+    let retrieved = retrieved_bm.transactions().borrow();
+    let grocer = retrieved.iter().find(|tx| tx.name() == "grocer").unwrap();
+    assert_eq!(grocer.external_id(), Some("20230904-1"));
+
+    let listed = repo.list_transactions(bm.id());
+    assert_eq!(listed[0].external_id(), Some("20230904-1"));
+    assert_eq!(listed[1].external_id(), None);
+
+    assert_eq!(repo.external_ids(), vec![(None, String::from("20230904-1"))]);
+}
+
+pub fn saving_stale_budget_is_a_conflict<T: Storage>(repo: &T) {
+    // Given
+    let bm = build_budget_manager_with_tx();
+    repo.add(&bm).unwrap();
+
+    let mut first = repo.get(bm.id());
+    let mut second = repo.get(bm.id());
+
+    // When
+    first.add_tx(&RandomIds, String::from("fries"), 2.49_f64, SystemClock.today());
+    repo.add(&first).unwrap();
+
+    second.add_tx(&RandomIds, String::from("shake"), 4.99_f64, SystemClock.today());
+    let result = repo.add(&second);
+
+    // Then
+    assert_eq!(
+        result,
+        Err(ConcurrencyConflict {
+            budget_id: Some(*bm.id()),
+            expected: 1,
+            found: 2,
+        })
+    );
+
+    let retrieved_bm = repo.get(bm.id());
+    assert_eq!(retrieved_bm.version(), 2);
+    assert_eq!(retrieved_bm.transactions(), first.transactions());
+}
+
+pub fn delete_cascades_to_transactions<T: Storage>(repo: &T) {
+    // Given
+    let account = models::Account::new(&RandomIds, String::from("checking"), models::AccountKind::Checking, 100.00_f64);
+    repo.add_account(&account);
+
+    let mut bm = build_budget_manager_with_tx();
+    bm.add_transaction(
+        models::Transaction::new(&RandomIds, String::from("fries"), 2.49_f64, *bm.id(), SystemClock.today()).with_account(Some(account.id().to_string())),
+    );
+    repo.add(&bm).unwrap();
+
+    let other = build_empty_budget_manager("other");
+    repo.add(&other).unwrap();
+
+    // When
+    repo.delete(bm.id());
+
+    // Then
+    assert!(repo.list_transactions(bm.id()).is_empty());
+    assert!(repo.account_budget_ids(account.id()).is_empty());
+    assert_eq!(repo.account_spend(account.id()), (0_f64, 0_f64));
+    assert_eq!(repo.list_budgets(), vec![other.budget().clone()]);
+}
+
+pub fn deleting_missing_budget_does_nothing<T: Storage>(repo: &T) {
+    // Given
+    let bm = build_budget_manager_with_tx();
+    repo.add(&bm).unwrap();
+
+    // When
+    repo.delete(&models::BudgetId::new());
+
+    // Then
+    assert_eq!(repo.get(bm.id()), bm);
+}
+
+pub fn getting_missing_budget_panics<T: Storage>(repo: &T) {
+    repo.get(&models::BudgetId::new());
+}
+
+pub fn getting_many_gives_none_for_a_missing_budget<T: Storage>(repo: &T) {
+    // Given
+    let bm = build_budget_manager_with_tx();
+    repo.add(&bm).unwrap();
+
+    // When
+    let retrieved = repo.get_many(&[models::BudgetId::new(), *bm.id()]);
+
+    // Then
+    assert_eq!(retrieved, vec![None, Some(bm)]);
+}
+
+pub fn getting_deleted_budget_panics<T: Storage>(repo: &T) {
+    // Given
+    let bm = build_budget_manager_with_tx();
+    repo.add(&bm).unwrap();
+
+    // When
+    repo.delete(bm.id());
+
+    // Then
+    repo.get(bm.id());
+}
+
+pub fn can_add_and_get_payees<T: Storage>(repo: &T) {
+    // Given
+    let mut payee = models::Payee::new(
+        &RandomIds,
+        String::from("Cheeseburger Palace"),
+        vec![String::from("cheeseborger"), String::from("CBP")],
+    );
+    let other = models::Payee::new(&RandomIds, String::from("Apple Store"), vec![]);
+    repo.add_payee(&payee);
+    repo.add_payee(&other);
+
+    // When
+    payee.add_alias(String::from("palace"));
+    repo.add_payee(&payee);
+
+    // Then
+    assert_eq!(repo.get_payee(payee.id()), Some(payee.clone()));
+    assert_eq!(repo.get_payee("missing"), None);
+    assert_eq!(repo.list_payees(), vec![other, payee]);
+}
+
+pub fn can_add_and_get_accounts<T: Storage>(repo: &T) {
+    // Given
+    let savings = models::Account::new(&RandomIds, String::from("savings"), models::AccountKind::Savings, 500.00_f64);
+    let checking = models::Account::new(&RandomIds, String::from("checking"), models::AccountKind::Checking, 100.00_f64);
+    repo.add_account(&savings);
+    repo.add_account(&checking);
+
+    let mut bm = build_budget_manager_with_tx();
+    bm.add_transaction(
+        models::Transaction::new(&RandomIds, String::from("fries"), 2.49_f64, *bm.id(), SystemClock.today())
+            .with_account(Some(checking.id().to_string()))
+            .with_status(models::TransactionStatus::Cleared),
+    );
+
+    // When
+    repo.add(&bm).unwrap();
+
+    // Then
+    assert_eq!(repo.get_account(checking.id()), Some(checking.clone()));
+    assert_eq!(repo.get_account("missing"), None);
+    assert_eq!(repo.list_accounts(), vec![checking.clone(), savings.clone()]);
+    assert_eq!(repo.account_budget_ids(checking.id()), vec![*bm.id()]);
+    assert!(repo.account_budget_ids(savings.id()).is_empty());
+}
+
+pub fn can_add_and_get_income_pool<T: Storage>(repo: &T) {
+    // Given
+    assert_eq!(repo.get_income_pool(), models::IncomePool::new());
+
+    let mut pool = models::IncomePool::new();
+    pool.add_income(models::Income::new(&RandomIds, String::from("paycheck"), 1000.00_f64));
+    repo.add_income_pool(&pool).unwrap();
+
+    // When
+    pool.assign(&RandomIds, models::BudgetId::new(), 400.00_f64).unwrap();
+    repo.add_income_pool(&pool).unwrap();
+
+    // Then
+    assert_eq!(repo.get_income_pool(), pool);
+}
+
+pub fn saving_the_pool_overwrites_changed_entries<T: Storage>(repo: &T) {
+    // Given
+    let budget_id = models::BudgetId::new();
+    let pool = models::IncomePool::load(
+        vec![models::Income::load(String::from("income-1"), String::from("paycheck"), 1000.00_f64)],
+        vec![models::Assignment::load(String::from("assignment-1"), budget_id, 400.00_f64)],
+    );
+    repo.add_income_pool(&pool).unwrap();
+
+    // When
+    let changed = models::IncomePool::load(
+        vec![models::Income::load(String::from("income-1"), String::from("salary"), 1200.00_f64)],
+        vec![models::Assignment::load(String::from("assignment-1"), budget_id, 500.00_f64)],
+    )
+    .with_version(pool.version());
+    repo.add_income_pool(&changed).unwrap();
+
+    // Then
+    assert_eq!(repo.get_income_pool(), changed);
+}
+
+pub fn saves_funded_budgets_with_the_pool<T: Storage>(repo: &T) {
+    // Given
+    let mut pool = models::IncomePool::new();
+    pool.add_income(models::Income::new(&RandomIds, String::from("paycheck"), 1000.00_f64));
+    let bm = build_empty_budget_manager("Groceries");
+    pool.assign(&RandomIds, *bm.id(), 200.00_f64).unwrap();
+
+    // When
+    repo.add_funded(std::slice::from_ref(&bm), &pool).unwrap();
+
+    // Then
+    assert_eq!(bm.version(), 1);
+    assert_eq!(repo.get(bm.id()), bm);
+    assert_eq!(repo.get_income_pool(), pool);
+}
+
+pub fn funded_save_with_a_stale_budget_writes_nothing<T: Storage>(repo: &T) {
+    // Given
+    let stale = build_budget_manager_with_tx();
+    repo.add(&stale).unwrap();
+    repo.add(&repo.get(stale.id())).unwrap();
+
+    let mut pool = models::IncomePool::new();
+    pool.add_income(models::Income::new(&RandomIds, String::from("paycheck"), 1000.00_f64));
+    let fresh = build_empty_budget_manager("Rent");
+
+    // When
+    let result = repo.add_funded(&[fresh.clone(), stale.clone()], &pool);
+
+    // Then
+    assert_eq!(
+        result,
+        Err(ConcurrencyConflict {
+            budget_id: Some(*stale.id()),
+            expected: 1,
+            found: 2,
+        })
+    );
+    assert_eq!(fresh.version(), 0);
+    assert_eq!(repo.list_budgets().len(), 1);
+    assert_eq!(repo.get_income_pool(), models::IncomePool::new());
+}
+
+/// A snapshot holding a budget paid at a payee from an account, funded from the pool
+fn build_snapshot() -> models::Snapshot {
+    let payee = models::Payee::new(&RandomIds, String::from("Cheeseburger Palace"), vec![String::from("CBP")]);
+    let account = models::Account::new(&RandomIds, String::from("checking"), models::AccountKind::Checking, 100.00_f64);
+
+    let mut bm = build_empty_budget_manager("Groceries");
+    bm.add_transaction(
+        models::Transaction::new(&RandomIds, String::from("fries"), 2.49_f64, *bm.id(), SystemClock.today())
+            .with_payee(Some(payee.id().to_string()))
+            .with_account(Some(account.id().to_string())),
+    );
+
+    let mut pool = models::IncomePool::new();
+    pool.add_income(models::Income::new(&RandomIds, String::from("paycheck"), 1000.00_f64));
+    pool.assign(&RandomIds, *bm.id(), 50.00_f64).unwrap();
+
+    models::Snapshot {
+        budgets: vec![bm],
+        payees: vec![payee],
+        accounts: vec![account],
+        income_pool: pool,
+    }
+}
+
+pub fn saves_a_snapshot_at_once<T: Storage>(repo: &T) {
+    // Given
+    let snapshot = build_snapshot();
+
+    // When
+    repo.add_snapshot(&snapshot).unwrap();
+
+    // Then
+    let bm = &snapshot.budgets[0];
+    assert_eq!(bm.version(), 1);
+    assert_eq!(repo.get(bm.id()), *bm);
+    assert_eq!(repo.list_payees(), snapshot.payees);
+    assert_eq!(repo.list_accounts(), snapshot.accounts);
+    assert_eq!(repo.get_income_pool(), snapshot.income_pool);
+}
+
+pub fn snapshot_with_a_stale_budget_writes_nothing<T: Storage>(repo: &T) {
+    // Given
+    let stale = build_budget_manager_with_tx();
+    repo.add(&stale).unwrap();
+    repo.add(&repo.get(stale.id())).unwrap();
+
+    let mut snapshot = build_snapshot();
+    snapshot.budgets.push(stale.clone());
+
+    // When
+    let result = repo.add_snapshot(&snapshot);
+
+    // Then
+    assert_eq!(
+        result,
+        Err(ConcurrencyConflict {
+            budget_id: Some(*stale.id()),
+            expected: 1,
+            found: 2,
+        })
+    );
+    assert_eq!(snapshot.budgets[0].version(), 0);
+    assert_eq!(repo.list_budgets().len(), 1);
+    assert!(repo.list_payees().is_empty());
+    assert!(repo.list_accounts().is_empty());
+    assert_eq!(repo.get_income_pool(), models::IncomePool::new());
+}
+
+pub fn saving_stale_income_pool_is_a_conflict<T: Storage>(repo: &T) {
+    // Given
+    let mut pool = repo.get_income_pool();
+    pool.add_income(models::Income::new(&RandomIds, String::from("paycheck"), 1000.00_f64));
+    let mut stale = repo.get_income_pool();
+    repo.add_income_pool(&pool).unwrap();
+
+    // When
+    stale.add_income(models::Income::new(&RandomIds, String::from("bonus"), 200.00_f64));
+    let result = repo.add_income_pool(&stale);
+
+    // Then
+    assert_eq!(
+        result,
+        Err(ConcurrencyConflict {
+            budget_id: None,
+            expected: 0,
+            found: 1,
+        })
+    );
+    assert_eq!(repo.get_income_pool().ready_to_assign(), 1000.00_f64);
+}
+
+pub fn saving_many_with_a_stale_budget_writes_nothing<T: Storage>(repo: &T) {
+    // Given
+    let stale = build_budget_manager_with_tx();
+    repo.add(&stale).unwrap();
+    repo.add(&repo.get(stale.id())).unwrap();
+    let fresh = build_empty_budget_manager("Rent");
+
+    // When
+    let result = repo.add_all(&[fresh.clone(), stale.clone()]);
+
+    // Then
+    assert_eq!(
+        result,
+        Err(ConcurrencyConflict {
+            budget_id: Some(*stale.id()),
+            expected: 1,
+            found: 2,
+        })
+    );
+    assert_eq!(fresh.version(), 0);
+    assert_eq!(repo.list_budgets().len(), 1);
+}
+
+pub fn lists_budgets_in_the_order_they_were_added<T: Storage>(repo: &T) {
+    // Given
+    let mut first = build_budget_manager_with_tx();
+    first.set_members(vec![String::from("ana")]);
+    let second = build_empty_budget_manager("second");
+    repo.add(&first).unwrap();
+    repo.add(&second).unwrap();
+
+    // When
+    first.add_tx(&RandomIds, String::from("fries"), 2.49_f64, SystemClock.today());
+    repo.add(&first).unwrap();
+
+    // Then
+    assert_eq!(repo.list_budgets(), vec![first.budget().clone(), second.budget().clone()]);
+}
+
+pub fn lists_transactions_by_date<T: Storage>(repo: &T) {
+    // Given
+    let budget = models::Budget::new(&RandomIds, String::from("Groceries"), 200.00_f64);
+    let id = *budget.id();
+    let txs = vec![
+        models::Transaction::load(models::TransactionId::new(), String::from("late"), 1.00_f64, id, date(20)),
+        models::Transaction::load(models::TransactionId::new(), String::from("first"), 1.00_f64, id, date(3)),
+        models::Transaction::load(models::TransactionId::new(), String::from("second"), 1.00_f64, id, date(3)),
+    ];
+    repo.add(&models::BudgetManager::new(budget, RefCell::new(txs))).unwrap();
+
+    // When
+    let listed = repo.list_transactions(&id);
+
+    // Then
+    let names: Vec<&str> = listed.iter().map(|tx| tx.name()).collect();
+    assert_eq!(names, vec!["first", "second", "late"]);
+    assert!(repo.list_transactions(&models::BudgetId::new()).is_empty());
+}
+
+pub fn sums_spend_by_account_and_payee<T: Storage>(repo: &T) {
+    // Given
+    let budget = models::Budget::new(&RandomIds, String::from("Groceries"), 200.00_f64);
+    let id = *budget.id();
+    let account = models::Account::new(&RandomIds, String::from("checking"), models::AccountKind::Checking, 100.00_f64);
+    let payee = models::Payee::new(&RandomIds, String::from("shop"), vec![]);
+    repo.add_account(&account);
+    repo.add_payee(&payee);
+
+    let account = account.id().to_string();
+    let payee = payee.id().to_string();
+    let txs = vec![
+        models::Transaction::load(models::TransactionId::new(), String::from("a"), 2.00_f64, id, date(1))
+            .with_account(Some(account.clone()))
+            .with_payee(Some(payee.clone()))
+            .with_status(models::TransactionStatus::Reconciled),
+        models::Transaction::load(models::TransactionId::new(), String::from("b"), 3.00_f64, id, date(2))
+            .with_account(Some(account.clone()))
+            .with_payee(Some(payee.clone())),
+        models::Transaction::load(models::TransactionId::new(), String::from("c"), 4.00_f64, id, date(3)),
+    ];
+    repo.add(&models::BudgetManager::new(budget, RefCell::new(txs))).unwrap();
+
+    // When
+    let account_spend = repo.account_spend(&account);
+    let payee_spend = repo.payee_spend();
+
+    // Then
+    assert_eq!(account_spend, (2.00_f64, 3.00_f64));
+    assert_eq!(payee_spend.get(&payee), Some(&(5.00_f64, 2)));
+    assert_eq!(payee_spend.len(), 1);
+}
@@ -0,0 +1,269 @@
+//! Writes transactions and budget summaries as CSV tables to open in a spreadsheet
+//! Fields are quoted as RFC 4180 asks, only when they hold a comma, a quote or a line break, and lines end in CRLF
+
+use std::str::FromStr;
+
+use chrono::NaiveDate;
+
+use crate::domain::models::{self, Snapshot};
+
+/// Dates from and to, both included, an open end has no limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DateRange {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl DateRange {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.from.map_or(true, |from| from <= date) && self.to.map_or(true, |to| date <= to)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionColumn {
+    Id,
+    Date,
+    Budget,
+    Name,
+    Amount,
+    Status,
+    /// Name of the payee
+    Payee,
+    /// Name of the account
+    Account,
+}
+
+impl TransactionColumn {
+    /// Every column, in the order they're written when none are chosen
+    pub const ALL: [TransactionColumn; 8] = [
+        TransactionColumn::Id,
+        TransactionColumn::Date,
+        TransactionColumn::Budget,
+        TransactionColumn::Name,
+        TransactionColumn::Amount,
+        TransactionColumn::Status,
+        TransactionColumn::Payee,
+        TransactionColumn::Account,
+    ];
+
+    fn header(&self) -> &'static str {
+        match self {
+            TransactionColumn::Id => "id",
+            TransactionColumn::Date => "date",
+            TransactionColumn::Budget => "budget",
+            TransactionColumn::Name => "name",
+            TransactionColumn::Amount => "amount",
+            TransactionColumn::Status => "status",
+            TransactionColumn::Payee => "payee",
+            TransactionColumn::Account => "account",
+        }
+    }
+}
+
+impl FromStr for TransactionColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TransactionColumn::ALL
+            .into_iter()
+            .find(|column| column.header() == s)
+            .ok_or(format!("Unknown column {}, expected one of {}", s, TransactionColumn::ALL.map(|c| c.header()).join(", ")))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetColumn {
+    Id,
+    Name,
+    /// What's left to spend
+    Total,
+    /// Spent in the date range
+    Spent,
+    /// Number of transactions in the date range
+    Transactions,
+}
+
+impl BudgetColumn {
+    /// Every column, in the order they're written when none are chosen
+    pub const ALL: [BudgetColumn; 5] = [
+        BudgetColumn::Id,
+        BudgetColumn::Name,
+        BudgetColumn::Total,
+        BudgetColumn::Spent,
+        BudgetColumn::Transactions,
+    ];
+
+    fn header(&self) -> &'static str {
+        match self {
+            BudgetColumn::Id => "id",
+            BudgetColumn::Name => "name",
+            BudgetColumn::Total => "total",
+            BudgetColumn::Spent => "spent",
+            BudgetColumn::Transactions => "transactions",
+        }
+    }
+}
+
+impl FromStr for BudgetColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BudgetColumn::ALL
+            .into_iter()
+            .find(|column| column.header() == s)
+            .ok_or(format!("Unknown column {}, expected one of {}", s, BudgetColumn::ALL.map(|c| c.header()).join(", ")))
+    }
+}
+
+/// Amounts keep their cents even when they're whole, spreadsheets read them as numbers either way
+fn amount(value: f64) -> String {
+    format!("{:.2}", models::round_cents(value))
+}
+
+fn writer() -> csv::Writer<Vec<u8>> {
+    csv::WriterBuilder::new().terminator(csv::Terminator::CRLF).from_writer(vec![])
+}
+
+fn finish(writer: csv::Writer<Vec<u8>>) -> String {
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+/// One row per transaction in the range, of one budget or of every budget, oldest first
+pub fn transactions(
+    snapshot: &Snapshot,
+    budget_id: Option<&models::BudgetId>,
+    columns: &[TransactionColumn],
+    range: &DateRange,
+) -> String {
+    let mut rows: Vec<(&models::Budget, models::Transaction)> = snapshot
+        .budgets
+        .iter()
+        .filter(|budget_manager| budget_id.map_or(true, |id| budget_manager.id() == id))
+        .flat_map(|budget_manager| {
+            let transactions = budget_manager.transactions().borrow().clone();
+            transactions.into_iter().map(|tx| (budget_manager.budget(), tx))
+        })
+        .filter(|(_, tx)| range.contains(tx.date()))
+        .collect();
+    rows.sort_by_key(|(_, tx)| tx.date());
+
+    let payee = |id: &str| snapshot.payees.iter().find(|payee| payee.id() == id).map(|payee| payee.name());
+    let account = |id: &str| snapshot.accounts.iter().find(|account| account.id() == id).map(|account| account.name());
+
+    let mut writer = writer();
+    writer.write_record(columns.iter().map(TransactionColumn::header)).unwrap();
+
+    for (budget, tx) in &rows {
+        let record = columns.iter().map(|column| match column {
+            TransactionColumn::Id => tx.id().to_string(),
+            TransactionColumn::Date => tx.date().to_string(),
+            TransactionColumn::Budget => budget.name().to_string(),
+            TransactionColumn::Name => tx.name().to_string(),
+            TransactionColumn::Amount => amount(*tx.value()),
+            TransactionColumn::Status => tx.status().to_string(),
+            TransactionColumn::Payee => tx.payee_id().and_then(payee).unwrap_or_default().to_string(),
+            TransactionColumn::Account => tx.account_id().and_then(account).unwrap_or_default().to_string(),
+        });
+        writer.write_record(record).unwrap();
+    }
+
+    finish(writer)
+}
+
+/// One row per budget, with what was spent in the range
+pub fn budgets(snapshot: &Snapshot, columns: &[BudgetColumn], range: &DateRange) -> String {
+    let mut writer = writer();
+    writer.write_record(columns.iter().map(BudgetColumn::header)).unwrap();
+
+    for budget_manager in &snapshot.budgets {
+        let transactions = budget_manager.transactions().borrow();
+        let in_range: Vec<&models::Transaction> = transactions.iter().filter(|tx| range.contains(tx.date())).collect();
+
+        let record = columns.iter().map(|column| match column {
+            BudgetColumn::Id => budget_manager.id().to_string(),
+            BudgetColumn::Name => budget_manager.name().to_string(),
+            BudgetColumn::Total => amount(*budget_manager.budget().total()),
+            BudgetColumn::Spent => amount(in_range.iter().map(|tx| tx.value()).sum()),
+            BudgetColumn::Transactions => in_range.len().to_string(),
+        });
+        writer.write_record(record).unwrap();
+    }
+
+    finish(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::domain::ids::{IdGenerator, RandomIds, SequentialIds};
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 9, day).unwrap()
+    }
+
+    fn snapshot() -> Snapshot {
+        let ids = SequentialIds::new();
+        let budget = models::Budget::new(&RandomIds, String::from("Food, drinks"), 200.00_f64);
+        let transactions = vec![
+            models::Transaction::load(ids.transaction_id(), String::from("Wine \"red\""), 12.00_f64, *budget.id(), date(5)),
+            models::Transaction::load(ids.transaction_id(), String::from("Bread\nand butter"), 3.5_f64, *budget.id(), date(1)),
+            models::Transaction::load(ids.transaction_id(), String::from("Cheese"), 7.25_f64, *budget.id(), date(20)),
+        ];
+
+        Snapshot {
+            budgets: vec![models::BudgetManager::new(budget, RefCell::new(transactions))],
+            ..Snapshot::default()
+        }
+    }
+
+    #[test]
+    fn writes_transactions_in_the_range_with_rfc_4180_quoting() {
+        // Given
+        let snapshot = snapshot();
+        let range = DateRange {
+            from: None,
+            to: Some(date(10)),
+        };
+        let columns = [TransactionColumn::Date, TransactionColumn::Budget, TransactionColumn::Name, TransactionColumn::Amount];
+
+        // When
+        let csv = transactions(&snapshot, None, &columns, &range);
+
+        // Then
+        assert_eq!(
+            csv,
+            "date,budget,name,amount\r\n\
+             2023-09-01,\"Food, drinks\",\"Bread\nand butter\",3.50\r\n\
+             2023-09-05,\"Food, drinks\",\"Wine \"\"red\"\"\",12.00\r\n"
+        );
+    }
+
+    #[test]
+    fn writes_budget_summaries_for_the_range() {
+        // Given
+        let snapshot = snapshot();
+        let range = DateRange {
+            from: Some(date(2)),
+            to: None,
+        };
+
+        // When
+        let csv = budgets(&snapshot, &[BudgetColumn::Name, BudgetColumn::Spent, BudgetColumn::Transactions], &range);
+
+        // Then
+        assert_eq!(csv, "name,spent,transactions\r\n\"Food, drinks\",19.25,2\r\n");
+    }
+
+    #[test]
+    fn reads_column_names() {
+        assert_eq!("payee".parse(), Ok(TransactionColumn::Payee));
+        assert_eq!("spent".parse(), Ok(BudgetColumn::Spent));
+        assert_eq!(
+            "cost".parse::<BudgetColumn>(),
+            Err(String::from("Unknown column cost, expected one of id, name, total, spent, transactions"))
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! A whole dataset as one JSON document, e.g., to move it to another database or storage backend
+//! The document carries a format version so older exports keep importing once the format changes
+
+use std::{error, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::domain::models::Snapshot;
+
+/// Version of the documents written by `to_json`, bumped whenever their shape changes
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Document {
+    version: u32,
+    #[serde(flatten)]
+    snapshot: Snapshot,
+}
+
+/// The document can't be imported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportError {
+    /// Not JSON, or not shaped like an export
+    Invalid(String),
+    /// Written by a newer version of the app
+    UnsupportedVersion { found: u32, supported: u32 },
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Invalid(reason) => write!(f, "not an export: {}", reason),
+            ExportError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "the export has version {} but only versions up to {} can be imported",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl error::Error for ExportError {}
+
+pub fn to_json(snapshot: &Snapshot) -> String {
+    let document = Document {
+        version: EXPORT_VERSION,
+        snapshot: snapshot.clone(),
+    };
+
+    serde_json::to_string_pretty(&document).unwrap()
+}
+
+pub fn from_json(json: &str) -> Result<Snapshot, ExportError> {
+    // The version is checked first, a newer document may not parse as the current shape
+    #[derive(Deserialize)]
+    struct Version {
+        version: u32,
+    }
+
+    let invalid = |e: serde_json::Error| ExportError::Invalid(e.to_string());
+
+    let Version { version } = serde_json::from_str(json).map_err(invalid)?;
+    if version > EXPORT_VERSION {
+        return Err(ExportError::UnsupportedVersion {
+            found: version,
+            supported: EXPORT_VERSION,
+        });
+    }
+
+    let document: Document = serde_json::from_str(json).map_err(invalid)?;
+
+    Ok(document.snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::domain::clock::{Clock, SystemClock};
+    use crate::domain::ids::RandomIds;
+    use crate::domain::models;
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        // Given
+        let budget = models::Budget::new(&RandomIds, String::from("Groceries"), 200.00_f64);
+        let tx = models::Transaction::new(&RandomIds, String::from("Apples"), 3.50_f64, *budget.id(), SystemClock.today());
+        let snapshot = Snapshot {
+            budgets: vec![models::BudgetManager::new(budget, RefCell::new(vec![tx]))],
+            payees: vec![models::Payee::new(&RandomIds, String::from("Grocer"), vec![String::from("Apples")])],
+            ..Snapshot::default()
+        };
+
+        // When
+        let json = to_json(&snapshot);
+
+        // Then
+        assert!(json.contains("\"version\": 1"));
+        assert_eq!(from_json(&json), Ok(snapshot));
+    }
+
+    #[test]
+    fn refuses_documents_from_newer_versions() {
+        let result = from_json(r#"{"version": 2, "something": "else"}"#);
+
+        assert_eq!(
+            result,
+            Err(ExportError::UnsupportedVersion {
+                found: 2,
+                supported: EXPORT_VERSION,
+            })
+        );
+    }
+
+    #[test]
+    fn refuses_documents_without_a_version() {
+        let result = from_json(r#"{"budgets": []}"#);
+
+        assert!(matches!(result, Err(ExportError::Invalid(_))));
+    }
+}
@@ -0,0 +1,192 @@
+//! Repositories that keep everything in plain files, the file format is up to a `SnapshotStore`
+//! Every call reads the whole snapshot, a change writes only the files whose contents changed,
+//! each to a temporary file next to it first that is renamed over the original, so no file is ever left half written
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use fs4::FileExt;
+
+use crate::adapters::in_memory_repository::InMemoryRepository;
+use crate::adapters::repository::{
+    AccountRepository, ConcurrencyConflict, IncomeRepository, PayeeRepository, Repository, SnapshotRepository,
+    ViewRepository,
+};
+use crate::domain::models::{self, Snapshot};
+
+/// Reads and writes a whole snapshot in some file layout
+pub trait SnapshotStore {
+    /// The data files are replaced on every write, so the lock lives on a file of its own
+    fn lock_path(&self) -> PathBuf;
+    /// An empty snapshot when nothing was written yet
+    fn load(&self) -> Snapshot;
+    fn save(&self, snapshot: &Snapshot);
+}
+
+/// Several processes can share the files, a change holds an exclusive lock from reading the files
+/// until the new ones are in place while reads share a lock
+pub struct FileRepository<S: SnapshotStore> {
+    store: S,
+}
+
+impl<S: SnapshotStore> FileRepository<S> {
+    pub fn with_store(store: S) -> Self {
+        FileRepository { store }
+    }
+
+    fn lock_file(&self) -> File {
+        let path = self.store.lock_path();
+
+        match OpenOptions::new().create(true).truncate(false).write(true).open(&path) {
+            Ok(file) => file,
+            Err(e) => panic!("Error opening lock file {}: {}", path.display(), e),
+        }
+    }
+
+    fn read<R>(&self, query: impl FnOnce(&InMemoryRepository) -> R) -> R {
+        let lock = self.lock_file();
+        // Called through the trait, std's own File::lock_shared needs a newer Rust than the crate supports
+        FileExt::lock_shared(&lock).unwrap();
+
+        query(&InMemoryRepository::from_snapshot(self.store.load()))
+    }
+
+    fn write<R>(&self, change: impl FnOnce(&InMemoryRepository) -> R) -> R {
+        let lock = self.lock_file();
+        FileExt::lock(&lock).unwrap();
+
+        let repo = InMemoryRepository::from_snapshot(self.store.load());
+        let result = change(&repo);
+        self.store.save(&repo.snapshot());
+
+        result
+    }
+}
+
+/// The contents of a file, `None` when it doesn't exist
+pub(crate) fn read_file(path: &Path) -> Option<String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Some(contents),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => panic!("Error reading {}: {}", path.display(), e),
+    }
+}
+
+/// Replaces the file through a rename, leaves it untouched when it already holds `contents`
+pub(crate) fn write_file(path: &Path, contents: &str) {
+    if read_file(path).as_deref() == Some(contents) {
+        return;
+    }
+
+    let mut tmp_path = path.to_path_buf().into_os_string();
+    tmp_path.push(".tmp");
+
+    let result = File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path));
+
+    if let Err(e) = result {
+        panic!("Error writing {}: {}", path.display(), e);
+    }
+}
+
+impl<S: SnapshotStore> Repository for FileRepository<S> {
+    fn add(&self, item: &models::BudgetManager) -> Result<(), ConcurrencyConflict> {
+        self.write(|repo| Repository::add(repo, item))
+    }
+
+    fn add_all(&self, items: &[models::BudgetManager]) -> Result<(), ConcurrencyConflict> {
+        self.write(|repo| Repository::add_all(repo, items))
+    }
+
+    fn get(&self, id: &models::BudgetId) -> models::BudgetManager {
+        self.read(|repo| Repository::get(repo, id))
+    }
+
+    fn get_many(&self, ids: &[models::BudgetId]) -> Vec<Option<models::BudgetManager>> {
+        self.read(|repo| Repository::get_many(repo, ids))
+    }
+
+    fn delete(&self, id: &models::BudgetId) {
+        self.write(|repo| Repository::delete(repo, id))
+    }
+}
+
+impl<S: SnapshotStore> PayeeRepository for FileRepository<S> {
+    fn add_payee(&self, payee: &models::Payee) {
+        self.write(|repo| PayeeRepository::add_payee(repo, payee))
+    }
+
+    fn get_payee(&self, id: &str) -> Option<models::Payee> {
+        self.read(|repo| PayeeRepository::get_payee(repo, id))
+    }
+
+    fn list_payees(&self) -> Vec<models::Payee> {
+        self.read(PayeeRepository::list_payees)
+    }
+}
+
+impl<S: SnapshotStore> AccountRepository for FileRepository<S> {
+    fn add_account(&self, account: &models::Account) {
+        self.write(|repo| AccountRepository::add_account(repo, account))
+    }
+
+    fn get_account(&self, id: &str) -> Option<models::Account> {
+        self.read(|repo| AccountRepository::get_account(repo, id))
+    }
+
+    fn list_accounts(&self) -> Vec<models::Account> {
+        self.read(AccountRepository::list_accounts)
+    }
+
+    fn account_budget_ids(&self, account_id: &str) -> Vec<models::BudgetId> {
+        self.read(|repo| AccountRepository::account_budget_ids(repo, account_id))
+    }
+
+    fn external_ids(&self) -> Vec<(Option<String>, String)> {
+        self.read(AccountRepository::external_ids)
+    }
+}
+
+impl<S: SnapshotStore> IncomeRepository for FileRepository<S> {
+    fn add_income_pool(&self, pool: &models::IncomePool) -> Result<(), ConcurrencyConflict> {
+        self.write(|repo| IncomeRepository::add_income_pool(repo, pool))
+    }
+
+    fn get_income_pool(&self) -> models::IncomePool {
+        self.read(IncomeRepository::get_income_pool)
+    }
+
+    fn add_funded(&self, items: &[models::BudgetManager], pool: &models::IncomePool) -> Result<(), ConcurrencyConflict> {
+        self.write(|repo| IncomeRepository::add_funded(repo, items, pool))
+    }
+}
+
+impl<S: SnapshotStore> SnapshotRepository for FileRepository<S> {
+    fn add_snapshot(&self, snapshot: &Snapshot) -> Result<(), ConcurrencyConflict> {
+        self.write(|repo| SnapshotRepository::add_snapshot(repo, snapshot))
+    }
+}
+
+impl<S: SnapshotStore> ViewRepository for FileRepository<S> {
+    fn list_budgets(&self) -> Vec<models::Budget> {
+        self.read(|repo| repo.list_budgets())
+    }
+
+    fn list_transactions(&self, budget_id: &models::BudgetId) -> Vec<models::Transaction> {
+        self.read(|repo| repo.list_transactions(budget_id))
+    }
+
+    fn account_spend(&self, account_id: &str) -> (f64, f64) {
+        self.read(|repo| repo.account_spend(account_id))
+    }
+
+    fn payee_spend(&self) -> HashMap<String, (f64, i64)> {
+        self.read(|repo| repo.payee_spend())
+    }
+}
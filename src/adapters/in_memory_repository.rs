@@ -0,0 +1,436 @@
+//! Keeps everything in memory, e.g., for tests or trying the app out without a database file
+//! Nothing is written anywhere, the data is gone once the repository is dropped
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::adapters::async_repository::{
+    AsyncAccountRepository, AsyncIncomeRepository, AsyncPayeeRepository, AsyncRepository, AsyncSnapshotRepository,
+    AsyncViewRepository,
+};
+use crate::adapters::repository::{
+    AccountRepository, ConcurrencyConflict, IncomeRepository, PayeeRepository, Repository, SnapshotRepository,
+    ViewRepository,
+};
+use crate::domain::models::{self, Snapshot};
+
+/// Can be shared between threads, every call locks the part of the data it touches
+#[derive(Debug, Default)]
+pub struct InMemoryRepository {
+    /// In the order they were first saved, like the rows of a table
+    budgets: Mutex<Vec<models::BudgetManager>>,
+    payees: Mutex<HashMap<String, models::Payee>>,
+    accounts: Mutex<HashMap<String, models::Account>>,
+    income_pool: Mutex<models::IncomePool>,
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        InMemoryRepository::default()
+    }
+
+    pub fn from_snapshot(snapshot: Snapshot) -> Self {
+        InMemoryRepository {
+            budgets: Mutex::new(snapshot.budgets),
+            payees: Mutex::new(snapshot.payees.into_iter().map(|p| (p.id().to_string(), p)).collect()),
+            accounts: Mutex::new(snapshot.accounts.into_iter().map(|a| (a.id().to_string(), a)).collect()),
+            income_pool: Mutex::new(snapshot.income_pool),
+        }
+    }
+
+    /// A copy of everything, payees and accounts sorted by name so the same data always looks the same
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            budgets: self.budgets.lock().unwrap().clone(),
+            payees: PayeeRepository::list_payees(self),
+            accounts: AccountRepository::list_accounts(self),
+            income_pool: IncomeRepository::get_income_pool(self),
+        }
+    }
+
+    fn transactions(&self) -> Vec<models::Transaction> {
+        self.budgets
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|bm| bm.transactions().borrow().clone())
+            .collect()
+    }
+}
+
+/// Where the budget is stored, unless the stored version differs from the one it was loaded with
+fn check_version(
+    budgets: &[models::BudgetManager],
+    item: &models::BudgetManager,
+) -> Result<Option<usize>, ConcurrencyConflict> {
+    let stored = budgets.iter().position(|bm| bm.id() == item.id());
+    let found = stored.map_or(0, |idx| budgets[idx].version());
+    if found != item.version() {
+        return Err(ConcurrencyConflict {
+            budget_id: Some(*item.id()),
+            expected: item.version(),
+            found,
+        });
+    }
+
+    Ok(stored)
+}
+
+fn save_budget(budgets: &mut Vec<models::BudgetManager>, stored: Option<usize>, item: &models::BudgetManager) {
+    item.set_version(item.version() + 1);
+    match stored {
+        Some(idx) => budgets[idx] = item.clone(),
+        None => budgets.push(item.clone()),
+    }
+}
+
+impl Repository for InMemoryRepository {
+    fn add(&self, item: &models::BudgetManager) -> Result<(), ConcurrencyConflict> {
+        let mut budgets = self.budgets.lock().unwrap();
+
+        let stored = check_version(&budgets, item)?;
+        save_budget(&mut budgets, stored, item);
+
+        Ok(())
+    }
+
+    fn add_all(&self, items: &[models::BudgetManager]) -> Result<(), ConcurrencyConflict> {
+        let mut budgets = self.budgets.lock().unwrap();
+
+        // Every budget is checked before any of them is saved
+        let stored = items
+            .iter()
+            .map(|item| check_version(&budgets, item))
+            .collect::<Result<Vec<Option<usize>>, ConcurrencyConflict>>()?;
+
+        for (item, stored) in items.iter().zip(stored) {
+            save_budget(&mut budgets, stored, item);
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, id: &models::BudgetId) -> models::BudgetManager {
+        // Let go of the lock before panicking so it isn't poisoned, an ephemeral session carries on after a panic
+        let found = self.budgets.lock().unwrap().iter().find(|bm| bm.id() == id).cloned();
+
+        match found {
+            Some(budget_manager) => budget_manager,
+            _ => panic!("Budget not found"),
+        }
+    }
+
+    fn get_many(&self, ids: &[models::BudgetId]) -> Vec<Option<models::BudgetManager>> {
+        let budgets = self.budgets.lock().unwrap();
+
+        ids.iter().map(|id| budgets.iter().find(|bm| bm.id() == id).cloned()).collect()
+    }
+
+    fn delete(&self, id: &models::BudgetId) {
+        self.budgets.lock().unwrap().retain(|bm| bm.id() != id);
+    }
+}
+
+impl PayeeRepository for InMemoryRepository {
+    fn add_payee(&self, payee: &models::Payee) {
+        self.payees
+            .lock()
+            .unwrap()
+            .insert(payee.id().to_string(), payee.clone());
+    }
+
+    fn get_payee(&self, id: &str) -> Option<models::Payee> {
+        self.payees.lock().unwrap().get(id).cloned()
+    }
+
+    fn list_payees(&self) -> Vec<models::Payee> {
+        let mut payees: Vec<models::Payee> = self.payees.lock().unwrap().values().cloned().collect();
+
+        payees.sort_by(|a, b| a.name().cmp(b.name()));
+
+        payees
+    }
+}
+
+impl AccountRepository for InMemoryRepository {
+    fn add_account(&self, account: &models::Account) {
+        self.accounts
+            .lock()
+            .unwrap()
+            .insert(account.id().to_string(), account.clone());
+    }
+
+    fn get_account(&self, id: &str) -> Option<models::Account> {
+        self.accounts.lock().unwrap().get(id).cloned()
+    }
+
+    fn list_accounts(&self) -> Vec<models::Account> {
+        let mut accounts: Vec<models::Account> = self.accounts.lock().unwrap().values().cloned().collect();
+
+        accounts.sort_by(|a, b| a.name().cmp(b.name()));
+
+        accounts
+    }
+
+    fn account_budget_ids(&self, account_id: &str) -> Vec<models::BudgetId> {
+        self.budgets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|bm| {
+                bm.transactions()
+                    .borrow()
+                    .iter()
+                    .any(|tx| tx.account_id() == Some(account_id))
+            })
+            .map(|bm| *bm.id())
+            .collect()
+    }
+
+    fn external_ids(&self) -> Vec<(Option<String>, String)> {
+        self.transactions()
+            .iter()
+            .filter_map(|tx| Some((tx.account_id().map(String::from), tx.external_id()?.to_string())))
+            .collect()
+    }
+}
+
+/// The stored pool's version, unless it differs from the one the pool was loaded with
+fn check_pool_version(stored: &models::IncomePool, pool: &models::IncomePool) -> Result<(), ConcurrencyConflict> {
+    if stored.version() != pool.version() {
+        return Err(ConcurrencyConflict {
+            budget_id: None,
+            expected: pool.version(),
+            found: stored.version(),
+        });
+    }
+
+    Ok(())
+}
+
+fn save_income_pool(stored: &mut models::IncomePool, pool: &models::IncomePool) {
+    pool.set_version(pool.version() + 1);
+    *stored = pool.clone();
+}
+
+impl IncomeRepository for InMemoryRepository {
+    fn add_income_pool(&self, pool: &models::IncomePool) -> Result<(), ConcurrencyConflict> {
+        let mut stored = self.income_pool.lock().unwrap();
+
+        check_pool_version(&stored, pool)?;
+        save_income_pool(&mut stored, pool);
+
+        Ok(())
+    }
+
+    fn get_income_pool(&self) -> models::IncomePool {
+        self.income_pool.lock().unwrap().clone()
+    }
+
+    fn add_funded(&self, items: &[models::BudgetManager], pool: &models::IncomePool) -> Result<(), ConcurrencyConflict> {
+        let mut budgets = self.budgets.lock().unwrap();
+        let mut stored_pool = self.income_pool.lock().unwrap();
+
+        // Everything is checked before any of it is saved
+        let stored = items
+            .iter()
+            .map(|item| check_version(&budgets, item))
+            .collect::<Result<Vec<Option<usize>>, ConcurrencyConflict>>()?;
+        check_pool_version(&stored_pool, pool)?;
+
+        for (item, stored) in items.iter().zip(stored) {
+            save_budget(&mut budgets, stored, item);
+        }
+        save_income_pool(&mut stored_pool, pool);
+
+        Ok(())
+    }
+}
+
+impl SnapshotRepository for InMemoryRepository {
+    fn add_snapshot(&self, snapshot: &Snapshot) -> Result<(), ConcurrencyConflict> {
+        let mut budgets = self.budgets.lock().unwrap();
+        let mut stored_pool = self.income_pool.lock().unwrap();
+        let mut payees = self.payees.lock().unwrap();
+        let mut accounts = self.accounts.lock().unwrap();
+
+        // Everything is checked before any of it is saved
+        let stored = snapshot
+            .budgets
+            .iter()
+            .map(|item| check_version(&budgets, item))
+            .collect::<Result<Vec<Option<usize>>, ConcurrencyConflict>>()?;
+        check_pool_version(&stored_pool, &snapshot.income_pool)?;
+
+        for payee in &snapshot.payees {
+            payees.insert(payee.id().to_string(), payee.clone());
+        }
+        for account in &snapshot.accounts {
+            accounts.insert(account.id().to_string(), account.clone());
+        }
+        for (item, stored) in snapshot.budgets.iter().zip(stored) {
+            save_budget(&mut budgets, stored, item);
+        }
+        save_income_pool(&mut stored_pool, &snapshot.income_pool);
+
+        Ok(())
+    }
+}
+
+impl ViewRepository for InMemoryRepository {
+    fn list_budgets(&self) -> Vec<models::Budget> {
+        self.budgets
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|bm| bm.budget().clone())
+            .collect()
+    }
+
+    fn list_transactions(&self, budget_id: &models::BudgetId) -> Vec<models::Transaction> {
+        let budgets = self.budgets.lock().unwrap();
+
+        let mut txs = match budgets.iter().find(|bm| bm.id() == budget_id) {
+            Some(budget_manager) => budget_manager.transactions().borrow().clone(),
+            _ => vec![],
+        };
+
+        // Stable, so transactions on the same day stay in the order they were added
+        txs.sort_by_key(|tx| tx.date());
+
+        txs
+    }
+
+    fn account_spend(&self, account_id: &str) -> (f64, f64) {
+        self.transactions()
+            .iter()
+            .filter(|tx| tx.account_id() == Some(account_id))
+            .fold((0_f64, 0_f64), |(cleared, pending), tx| match tx.status() {
+                models::TransactionStatus::Pending => (cleared, pending + tx.value()),
+                _ => (cleared + tx.value(), pending),
+            })
+    }
+
+    fn payee_spend(&self) -> HashMap<String, (f64, i64)> {
+        let mut spend: HashMap<String, (f64, i64)> = HashMap::new();
+
+        for tx in self.transactions() {
+            if let Some(payee_id) = tx.payee_id() {
+                let (total, count) = spend.entry(payee_id.to_string()).or_default();
+                *total += tx.value();
+                *count += 1;
+            }
+        }
+
+        spend
+    }
+}
+
+// Nothing blocks in memory, the async side answers straight from the blocking one
+impl AsyncRepository for InMemoryRepository {
+    async fn add(&self, item: models::BudgetManager) -> Result<models::BudgetManager, ConcurrencyConflict> {
+        Repository::add(self, &item).map(|_| item)
+    }
+
+    async fn add_all(&self, items: Vec<models::BudgetManager>) -> Result<Vec<models::BudgetManager>, ConcurrencyConflict> {
+        Repository::add_all(self, &items).map(|_| items)
+    }
+
+    async fn get(&self, id: &models::BudgetId) -> models::BudgetManager {
+        Repository::get(self, id)
+    }
+
+    async fn get_many(&self, ids: &[models::BudgetId]) -> Vec<Option<models::BudgetManager>> {
+        Repository::get_many(self, ids)
+    }
+
+    async fn delete(&self, id: &models::BudgetId) {
+        Repository::delete(self, id)
+    }
+}
+
+impl AsyncPayeeRepository for InMemoryRepository {
+    async fn add_payee(&self, payee: &models::Payee) {
+        PayeeRepository::add_payee(self, payee)
+    }
+
+    async fn get_payee(&self, id: &str) -> Option<models::Payee> {
+        PayeeRepository::get_payee(self, id)
+    }
+
+    async fn list_payees(&self) -> Vec<models::Payee> {
+        PayeeRepository::list_payees(self)
+    }
+}
+
+impl AsyncAccountRepository for InMemoryRepository {
+    async fn add_account(&self, account: &models::Account) {
+        AccountRepository::add_account(self, account)
+    }
+
+    async fn get_account(&self, id: &str) -> Option<models::Account> {
+        AccountRepository::get_account(self, id)
+    }
+
+    async fn list_accounts(&self) -> Vec<models::Account> {
+        AccountRepository::list_accounts(self)
+    }
+
+    async fn account_budget_ids(&self, account_id: &str) -> Vec<models::BudgetId> {
+        AccountRepository::account_budget_ids(self, account_id)
+    }
+
+    async fn external_ids(&self) -> Vec<(Option<String>, String)> {
+        AccountRepository::external_ids(self)
+    }
+}
+
+impl AsyncIncomeRepository for InMemoryRepository {
+    async fn add_income_pool(&self, pool: models::IncomePool) -> Result<models::IncomePool, ConcurrencyConflict> {
+        IncomeRepository::add_income_pool(self, &pool).map(|_| pool)
+    }
+
+    async fn get_income_pool(&self) -> models::IncomePool {
+        IncomeRepository::get_income_pool(self)
+    }
+
+    async fn add_funded(
+        &self,
+        items: Vec<models::BudgetManager>,
+        pool: models::IncomePool,
+    ) -> Result<(Vec<models::BudgetManager>, models::IncomePool), ConcurrencyConflict> {
+        IncomeRepository::add_funded(self, &items, &pool).map(|_| (items, pool))
+    }
+}
+
+impl AsyncSnapshotRepository for InMemoryRepository {
+    async fn add_snapshot(&self, snapshot: Snapshot) -> Result<Snapshot, ConcurrencyConflict> {
+        SnapshotRepository::add_snapshot(self, &snapshot).map(|_| snapshot)
+    }
+}
+
+impl AsyncViewRepository for InMemoryRepository {
+    async fn list_budgets(&self) -> Vec<models::Budget> {
+        ViewRepository::list_budgets(self)
+    }
+
+    async fn list_transactions(&self, budget_id: &models::BudgetId) -> Vec<models::Transaction> {
+        ViewRepository::list_transactions(self, budget_id)
+    }
+
+    async fn account_spend(&self, account_id: &str) -> (f64, f64) {
+        ViewRepository::account_spend(self, account_id)
+    }
+
+    async fn payee_spend(&self) -> HashMap<String, (f64, i64)> {
+        ViewRepository::payee_spend(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryRepository;
+    use crate::adapters::conformance::conformance_tests;
+
+    conformance_tests!((InMemoryRepository::new(), ()));
+}
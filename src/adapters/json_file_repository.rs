@@ -0,0 +1,106 @@
+//! Keeps everything in a single human-readable JSON file, e.g., to sync it with Dropbox
+
+use std::path::PathBuf;
+
+use crate::adapters::file_repository::{self, FileRepository, SnapshotStore};
+use crate::domain::models::Snapshot;
+
+pub type JsonFileRepository = FileRepository<JsonFile>;
+
+impl JsonFileRepository {
+    /// The file is created with the first change, until then the repository is empty
+    pub fn new(filename: String) -> Self {
+        FileRepository::with_store(JsonFile {
+            path: PathBuf::from(filename),
+        })
+    }
+}
+
+/// The whole snapshot as one JSON document
+pub struct JsonFile {
+    path: PathBuf,
+}
+
+impl SnapshotStore for JsonFile {
+    fn lock_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".lock");
+
+        PathBuf::from(path)
+    }
+
+    fn load(&self) -> Snapshot {
+        match file_repository::read_file(&self.path) {
+            Some(contents) => match serde_json::from_str(&contents) {
+                Ok(snapshot) => snapshot,
+                Err(e) => panic!("Error reading {}: {}", self.path.display(), e),
+            },
+            None => Snapshot::default(),
+        }
+    }
+
+    fn save(&self, snapshot: &Snapshot) {
+        file_repository::write_file(&self.path, &serde_json::to_string_pretty(snapshot).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::JsonFileRepository;
+    use crate::adapters::conformance::conformance_tests;
+    use crate::adapters::repository::Repository;
+    use crate::domain::models;
+    use crate::domain::clock::{Clock, SystemClock};
+    use crate::domain::ids::RandomIds;
+
+    fn temp_repo() -> (JsonFileRepository, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = JsonFileRepository::new(dir.path().join("budgets.json").to_string_lossy().to_string());
+
+        (repo, dir)
+    }
+
+    conformance_tests!(temp_repo());
+
+    #[test]
+    fn writes_readable_json_without_leftovers() {
+        // Given
+        let (repo, dir) = temp_repo();
+        let budget = models::Budget::new(&RandomIds, String::from("Groceries"), 200.00_f64);
+        let bm = models::BudgetManager::new(budget, RefCell::new(vec![]));
+
+        // When
+        repo.add(&bm).unwrap();
+
+        // Then
+        let contents = fs::read_to_string(dir.path().join("budgets.json")).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(json["budgets"][0]["budget"]["name"], "Groceries");
+        assert_eq!(json["budgets"][0]["version"], 1);
+        assert!(!dir.path().join("budgets.json.tmp").exists());
+    }
+
+    #[test]
+    fn repositories_on_the_same_file_see_each_others_changes() {
+        // Given
+        let (repo, dir) = temp_repo();
+        let other = JsonFileRepository::new(dir.path().join("budgets.json").to_string_lossy().to_string());
+        let budget = models::Budget::new(&RandomIds, String::from("Groceries"), 200.00_f64);
+        let bm = models::BudgetManager::new(budget, RefCell::new(vec![]));
+        repo.add(&bm).unwrap();
+
+        // When
+        let mut loaded = other.get(bm.id());
+        loaded.add_tx(&RandomIds, String::from("apples"), 3.50_f64, SystemClock.today());
+        other.add(&loaded).unwrap();
+
+        // Then
+        assert_eq!(repo.get(bm.id()), loaded);
+        assert!(repo.add(&bm).is_err());
+    }
+}
@@ -0,0 +1,329 @@
+//! Writes a journal for Ledger and hledger, e.g., to reconcile the year in plain text accounting
+//! Every budget is an expense account under `expenses:budget` and every transaction an entry that moves
+//! its amount there from the account that paid it. Optionally the budgets themselves are written as a
+//! periodic transaction, which hledger compares the actual spending with using `hledger balance --budget`
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Datelike, NaiveDate};
+
+use crate::domain::models::{self, Snapshot};
+
+/// Parent of the budget accounts
+const BUDGET_ACCOUNT: &str = "expenses:budget";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedgerOptions {
+    /// Pays for transactions without an account, and funds the budgets in the periodic transaction
+    pub funding_account: String,
+    /// Period of the periodic transaction, none is written without one
+    pub budget_period: Option<BudgetPeriod>,
+}
+
+impl Default for LedgerOptions {
+    fn default() -> Self {
+        LedgerOptions {
+            funding_account: String::from("assets:checking"),
+            budget_period: None,
+        }
+    }
+}
+
+/// How often the periodic transaction repeats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetPeriod {
+    Daily,
+    Weekly,
+    Monthly,
+    Quarterly,
+    Yearly,
+}
+
+impl BudgetPeriod {
+    /// Counts the periods from some fixed start, weeks start on Monday like hledger's
+    fn index(&self, date: NaiveDate) -> i64 {
+        let month = i64::from(date.year()) * 12 + i64::from(date.month0());
+        match self {
+            BudgetPeriod::Daily => i64::from(date.num_days_from_ce()),
+            BudgetPeriod::Weekly => i64::from(date.num_days_from_ce() - date.weekday().num_days_from_monday() as i32) / 7,
+            BudgetPeriod::Monthly => month,
+            BudgetPeriod::Quarterly => month / 3,
+            BudgetPeriod::Yearly => i64::from(date.year()),
+        }
+    }
+
+    /// How many periods the dates touch, one when there are none
+    fn count(&self, dates: impl Iterator<Item = NaiveDate>) -> i64 {
+        let (first, last) = dates.fold((None, None), |(first, last): (Option<NaiveDate>, Option<NaiveDate>), date| {
+            (Some(first.map_or(date, |first| first.min(date))), Some(last.map_or(date, |last| last.max(date))))
+        });
+
+        match (first, last) {
+            (Some(first), Some(last)) => self.index(last) - self.index(first) + 1,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for BudgetPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let period = match self {
+            BudgetPeriod::Daily => "daily",
+            BudgetPeriod::Weekly => "weekly",
+            BudgetPeriod::Monthly => "monthly",
+            BudgetPeriod::Quarterly => "quarterly",
+            BudgetPeriod::Yearly => "yearly",
+        };
+        write!(f, "{}", period)
+    }
+}
+
+impl FromStr for BudgetPeriod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(BudgetPeriod::Daily),
+            "weekly" => Ok(BudgetPeriod::Weekly),
+            "monthly" => Ok(BudgetPeriod::Monthly),
+            "quarterly" => Ok(BudgetPeriod::Quarterly),
+            "yearly" => Ok(BudgetPeriod::Yearly),
+            _ => Err(format!("Unknown budget period {}, expected daily, weekly, monthly, quarterly or yearly", s)),
+        }
+    }
+}
+
+/// Lowercase with dashes between words, colons would start a subaccount
+fn slug(name: &str) -> String {
+    let slug = name
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+
+    match slug.is_empty() {
+        true => String::from("unnamed"),
+        false => slug,
+    }
+}
+
+fn budget_account(budget: &models::Budget) -> String {
+    format!("{}:{}", BUDGET_ACCOUNT, slug(budget.name()))
+}
+
+/// Credit cards are owed money, everything else holds it
+fn account(account: &models::Account) -> String {
+    match account.kind() {
+        models::AccountKind::Credit => format!("liabilities:{}", slug(account.name())),
+        _ => format!("assets:{}", slug(account.name())),
+    }
+}
+
+struct Entry {
+    header: String,
+    postings: Vec<(String, Option<f64>)>,
+}
+
+pub fn write(snapshot: &Snapshot, options: &LedgerOptions) -> String {
+    let mut entries: Vec<Entry> = vec![];
+
+    if let Some(period) = options.budget_period {
+        // The rule repeats every period, so what was given is spread over the periods the transactions span
+        let periods = period.count(
+            snapshot
+                .budgets
+                .iter()
+                .flat_map(|budget_manager| budget_manager.transactions().borrow().iter().map(|tx| tx.date()).collect::<Vec<_>>()),
+        );
+
+        // What was given to a budget is what's left plus what was spent from it
+        let mut postings: Vec<(String, Option<f64>)> = snapshot
+            .budgets
+            .iter()
+            .map(|budget_manager| {
+                let spent: f64 = budget_manager.transactions().borrow().iter().map(|tx| tx.value()).sum();
+                let allocated = models::round_cents((budget_manager.budget().total() + spent) / periods as f64);
+                (budget_account(budget_manager.budget()), Some(allocated))
+            })
+            .collect();
+        postings.push((options.funding_account.clone(), None));
+
+        entries.push(Entry {
+            header: format!("~ {}", period),
+            postings,
+        });
+    }
+
+    let mut transactions: Vec<(&models::Budget, models::Transaction)> = snapshot
+        .budgets
+        .iter()
+        .flat_map(|budget_manager| {
+            let transactions = budget_manager.transactions().borrow().clone();
+            transactions.into_iter().map(|tx| (budget_manager.budget(), tx))
+        })
+        .collect();
+    transactions.sort_by_key(|(_, tx)| tx.date());
+    entries.extend(transactions.iter().map(|(budget, tx)| entry(snapshot, budget, tx, options)));
+
+    let width = entries
+        .iter()
+        .flat_map(|entry| entry.postings.iter().map(|(account, _)| account.len()))
+        .max()
+        .unwrap_or_default();
+
+    let mut journal = String::new();
+    for entry in &entries {
+        if !journal.is_empty() {
+            journal.push('\n');
+        }
+        journal.push_str(&entry.header);
+        journal.push('\n');
+
+        for (account, amount) in &entry.postings {
+            match amount {
+                Some(amount) => journal.push_str(&format!("    {:<width$}  {:>10.2}\n", account, amount)),
+                None => journal.push_str(&format!("    {}\n", account)),
+            }
+        }
+    }
+
+    journal
+}
+
+fn entry(snapshot: &Snapshot, budget: &models::Budget, tx: &models::Transaction, options: &LedgerOptions) -> Entry {
+    let status = match tx.status() {
+        models::TransactionStatus::Pending => "",
+        // ! is pending to Ledger, a cleared transaction is as settled as a reconciled one there
+        models::TransactionStatus::Cleared | models::TransactionStatus::Reconciled => " *",
+    };
+
+    // hledger reads the part before a | as the payee
+    let payee = tx
+        .payee_id()
+        .and_then(|id| snapshot.payees.iter().find(|payee| payee.id() == id));
+    let description = match payee {
+        Some(payee) if payee.name() != tx.name() => format!("{} | {}", payee.name(), tx.name()),
+        _ => tx.name().to_string(),
+    };
+
+    let paid_from = tx
+        .account_id()
+        .and_then(|id| snapshot.accounts.iter().find(|account| account.id() == id))
+        .map_or(options.funding_account.clone(), account);
+
+    let value = models::round_cents(*tx.value());
+
+    Entry {
+        header: format!("{}{} {}  ; id:{}", tx.date().format("%Y-%m-%d"), status, description, tx.id()),
+        postings: vec![(budget_account(budget), Some(value)), (paid_from, Some(-value))],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::domain::ids::{IdGenerator, SequentialIds};
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 9, day).unwrap()
+    }
+
+    /// Groceries with 150 left after spending 50, and rent paid from a checking account
+    fn snapshot() -> Snapshot {
+        let ids = SequentialIds::new();
+        let payee = models::Payee::load(String::from("p1"), String::from("Corner Grocer"), vec![]);
+        let account = models::Account::load(String::from("a1"), String::from("Main Checking"), models::AccountKind::Checking, 0_f64);
+
+        let groceries = models::Budget::load(ids.budget_id(), String::from("Groceries"), 150.00_f64);
+        let rent = models::Budget::load(ids.budget_id(), String::from("Rent: Flat"), 0.00_f64);
+        let apples = models::Transaction::load(ids.transaction_id(), String::from("apples"), 50.00_f64, *groceries.id(), date(2))
+            .with_payee(Some(String::from("p1")))
+            .with_status(models::TransactionStatus::Reconciled);
+        let september = models::Transaction::load(ids.transaction_id(), String::from("September"), 900.00_f64, *rent.id(), date(1))
+            .with_account(Some(String::from("a1")))
+            .with_status(models::TransactionStatus::Cleared);
+
+        Snapshot {
+            budgets: vec![
+                models::BudgetManager::new(groceries, RefCell::new(vec![apples])),
+                models::BudgetManager::new(rent, RefCell::new(vec![september])),
+            ],
+            payees: vec![payee],
+            accounts: vec![account],
+            ..Snapshot::default()
+        }
+    }
+
+    #[test]
+    fn writes_transactions_as_balanced_entries() {
+        // When
+        let journal = write(&snapshot(), &LedgerOptions::default());
+
+        // Then
+        assert_eq!(
+            journal,
+            "2023-09-01 * September  ; id:00000000-0000-0000-0000-000000000004\n\
+             \x20   expenses:budget:rent-flat      900.00\n\
+             \x20   assets:main-checking          -900.00\n\
+             \n\
+             2023-09-02 * Corner Grocer | apples  ; id:00000000-0000-0000-0000-000000000003\n\
+             \x20   expenses:budget:groceries       50.00\n\
+             \x20   assets:checking                -50.00\n"
+        );
+    }
+
+    #[test]
+    fn writes_what_budgets_were_given_as_a_periodic_transaction() {
+        // Given
+        let options = LedgerOptions {
+            budget_period: Some(BudgetPeriod::Monthly),
+            ..LedgerOptions::default()
+        };
+
+        // When
+        let journal = write(&snapshot(), &options);
+
+        // Then
+        assert!(journal.starts_with(
+            "~ monthly\n\
+             \x20   expenses:budget:groceries      200.00\n\
+             \x20   expenses:budget:rent-flat      900.00\n\
+             \x20   assets:checking\n\
+             \n\
+             2023-09-01"
+        ));
+    }
+
+    #[test]
+    fn spreads_what_budgets_were_given_over_the_periods_of_the_transactions() {
+        // Given
+        let options = LedgerOptions {
+            budget_period: Some(BudgetPeriod::Daily),
+            ..LedgerOptions::default()
+        };
+
+        // When
+        let journal = write(&snapshot(), &options);
+
+        // Then
+        assert!(journal.starts_with(
+            "~ daily\n\
+             \x20   expenses:budget:groceries      100.00\n\
+             \x20   expenses:budget:rent-flat      450.00\n"
+        ));
+    }
+
+    #[test]
+    fn counts_the_periods_dates_touch() {
+        let dates = || [date(30), date(2), NaiveDate::from_ymd_opt(2023, 10, 1).unwrap()].into_iter();
+
+        assert_eq!(BudgetPeriod::Monthly.count(dates()), 2);
+        assert_eq!(BudgetPeriod::Weekly.count(dates()), 5);
+        assert_eq!(BudgetPeriod::Yearly.count(dates()), 1);
+        assert_eq!(BudgetPeriod::Quarterly.count(std::iter::empty()), 1);
+    }
+}
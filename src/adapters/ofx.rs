@@ -0,0 +1,228 @@
+//! Reads the OFX and QFX statements banks offer for download into rows that can become transactions
+//! OFX 1.x is SGML where leaf elements are usually left unclosed, OFX 2.x is XML. Both are read
+//! by the same tolerant scan, which only cares about the `STMTTRN` elements and the values in them
+
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+
+use crate::adapters::statement::{self, BankRow, Statement, StatementError};
+
+/// An element being read, with the leaf values found in it
+struct StatementTransaction {
+    line: u64,
+    values: HashMap<String, String>,
+}
+
+impl StatementTransaction {
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    fn required(&self, name: &str) -> Result<&str, StatementError> {
+        self.value(name)
+            .ok_or(StatementError::new(Some(self.line), format!("the transaction has no {}", name)))
+    }
+}
+
+/// Reads every transaction of the statement, the line of a row is where its `STMTTRN` starts
+pub fn read(contents: &str) -> Result<Statement, StatementError> {
+    // Everything before the root element is a header, key:value lines in 1.x and a prolog in 2.x
+    let start = contents
+        .to_ascii_uppercase()
+        .find("<OFX>")
+        .ok_or(StatementError::new(None, "not an OFX file, there is no <OFX> element"))?;
+
+    let mut statement = Statement::default();
+    let mut current: Option<StatementTransaction> = None;
+    // FITIDs are only unique within an account, a file can hold the statements of several
+    let mut account: Option<String> = None;
+    let mut line = 1 + contents[..start].matches('\n').count() as u64;
+    let mut rest = &contents[start..];
+
+    while let Some(open) = rest.find('<') {
+        line += rest[..open].matches('\n').count() as u64;
+        let after = &rest[open + 1..];
+        let close = after
+            .find('>')
+            .ok_or(StatementError::new(Some(line), "an element is never closed with >"))?;
+        let tag = after[..close].trim().to_ascii_uppercase();
+        let body = &after[close + 1..];
+        let text_end = body.find('<').unwrap_or(body.len());
+        let text = body[..text_end].trim();
+
+        line += after[..close].matches('\n').count() as u64;
+        rest = body;
+
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+
+        match tag.strip_prefix('/') {
+            Some("STMTTRN") => {
+                if let Some(tx) = current.take() {
+                    add(&mut statement, tx, account.as_deref())?;
+                }
+            }
+            Some(_) => {}
+            None if tag == "STMTTRN" => {
+                // A missing end tag only shows when the next transaction starts
+                if let Some(tx) = current.replace(StatementTransaction {
+                    line,
+                    values: HashMap::new(),
+                }) {
+                    add(&mut statement, tx, account.as_deref())?;
+                }
+            }
+            None => match current.as_mut().filter(|_| !text.is_empty()) {
+                Some(tx) => {
+                    tx.values.entry(tag).or_insert_with(|| decode(text));
+                }
+                // The account comes before the transactions, in BANKACCTFROM or CCACCTFROM
+                None if tag == "ACCTID" && !text.is_empty() => account = Some(decode(text)),
+                None => {}
+            },
+        }
+    }
+
+    if let Some(tx) = current {
+        add(&mut statement, tx, account.as_deref())?;
+    }
+
+    Ok(statement)
+}
+
+/// Adds the transaction when it spent money, its FITID is prefixed with the `account` it's from when that's known
+fn add(statement: &mut Statement, tx: StatementTransaction, account: Option<&str>) -> Result<(), StatementError> {
+    let raw_amount = tx.required("TRNAMT")?;
+    let value: f64 = raw_amount
+        .replace(',', ".")
+        .parse()
+        .ok()
+        .filter(|amount: &f64| amount.is_finite())
+        .ok_or(StatementError::new(Some(tx.line), format!("{} is not an amount", raw_amount)))?;
+
+    // Money leaving the account is negative in every OFX statement, checking and credit card alike
+    let spent = -value;
+    if spent <= 0_f64 {
+        statement.skipped.push(tx.line);
+        return Ok(());
+    }
+
+    // Dates are YYYYMMDD optionally followed by a time and a time zone, the day is all that's kept
+    let raw_date = tx.required("DTPOSTED")?;
+    let date = raw_date
+        .get(..8)
+        .and_then(|day| NaiveDate::parse_from_str(day, "%Y%m%d").ok())
+        .ok_or(StatementError::new(Some(tx.line), format!("{} is not a date like YYYYMMDD", raw_date)))?;
+
+    let description = tx
+        .value("NAME")
+        .or(tx.value("MEMO"))
+        .ok_or(StatementError::new(Some(tx.line), "the transaction has no NAME or MEMO"))?;
+
+    statement.spending.push(BankRow {
+        line: tx.line,
+        date,
+        description: statement::normalize_description(description),
+        amount: spent,
+        external_id: tx.value("FITID").map(|fitid| match account {
+            Some(account) => format!("{}:{}", account, fitid),
+            None => fitid.to_string(),
+        }),
+    });
+
+    Ok(())
+}
+
+/// The entities SGML and XML statements escape their text with
+fn decode(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 9, day).unwrap()
+    }
+
+    #[test]
+    fn reads_sgml_statements_with_unclosed_elements() {
+        // Given
+        let ofx = "OFXHEADER:100\r\nDATA:OFXSGML\r\nVERSION:102\r\n\r\n\
+                   <OFX>\r\n<BANKMSGSRSV1><STMTTRNRS><STMTRS>\r\n\
+                   <BANKACCTFROM><BANKID>121000248<ACCTID>000123456<ACCTTYPE>CHECKING</BANKACCTFROM>\r\n\
+                   <BANKTRANLIST>\r\n\
+                   <STMTTRN>\r\n<TRNTYPE>DEBIT\r\n<DTPOSTED>20230901120000.000[-5:EST]\r\n\
+                   <TRNAMT>-12.50\r\n<FITID>20230901-1\r\n<NAME>Corner   Grocer &amp; Deli\r\n</STMTTRN>\r\n\
+                   <STMTTRN>\r\n<TRNTYPE>CREDIT\r\n<DTPOSTED>20230902\r\n<TRNAMT>2000.00\r\n\
+                   <FITID>20230902-1\r\n<NAME>Salary\r\n</STMTTRN>\r\n\
+                   </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1>\r\n</OFX>\r\n";
+
+        // When
+        let statement = read(ofx).unwrap();
+
+        // Then
+        assert_eq!(
+            statement.spending,
+            vec![BankRow {
+                line: 9,
+                date: date(1),
+                description: String::from("Corner Grocer & Deli"),
+                amount: 12.50_f64,
+                external_id: Some(String::from("000123456:20230901-1")),
+            }]
+        );
+        assert_eq!(statement.skipped, vec![16]);
+    }
+
+    #[test]
+    fn reads_xml_statements() {
+        // Given
+        let ofx = r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>
+<OFX>
+  <CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS><BANKTRANLIST>
+    <STMTTRN>
+      <TRNTYPE>DEBIT</TRNTYPE>
+      <DTPOSTED>20230903</DTPOSTED>
+      <TRNAMT>-7.25</TRNAMT>
+      <FITID>A-3</FITID>
+      <MEMO>Book shop</MEMO>
+    </STMTTRN>
+  </BANKTRANLIST></CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1>
+</OFX>"#;
+
+        // When
+        let statement = read(ofx).unwrap();
+
+        // Then
+        assert_eq!(statement.spending.len(), 1);
+        assert_eq!(statement.spending[0].line, 5);
+        assert_eq!(statement.spending[0].date, date(3));
+        assert_eq!(statement.spending[0].description, "Book shop");
+        assert_eq!(statement.spending[0].amount, 7.25_f64);
+        assert_eq!(statement.spending[0].external_id.as_deref(), Some("A-3"));
+    }
+
+    #[test]
+    fn reports_the_transaction_that_can_not_be_read() {
+        // Given
+        let ofx = "<OFX>\n<STMTTRN>\n<DTPOSTED>2023-09-01\n<TRNAMT>-1.00\n<NAME>Grocer\n</STMTTRN>\n</OFX>\n";
+
+        // When
+        let not_ofx = read("Date,Description,Amount\n");
+        let bad_date = read(ofx);
+
+        // Then
+        assert_eq!(not_ofx.unwrap_err().to_string(), "not an OFX file, there is no <OFX> element");
+        assert_eq!(bad_date.unwrap_err().to_string(), "line 2: 2023-09-01 is not a date like YYYYMMDD");
+    }
+}
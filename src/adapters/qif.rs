@@ -0,0 +1,348 @@
+//! Reads and writes QIF, the format older desktop finance apps exchange accounts in
+//! Only the bank-like sections are read: `!Type:Bank`, `!Type:Cash` and `!Type:CCard`. A record's
+//! category says which budget it belongs to, a record split across categories becomes one row per split
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::adapters::statement::{self, StatementError};
+use crate::domain::models;
+
+/// Budgets are written as categories of a single bank account
+const HEADER: &str = "!Type:Bank";
+
+/// A record, or a split of one, that spent money
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QifRow {
+    /// Line in the file where the record starts
+    pub line: u64,
+    pub date: NaiveDate,
+    pub description: String,
+    /// Always positive
+    pub amount: f64,
+    /// Without the class after a /, `None` for uncategorized records
+    pub category: Option<String>,
+    /// QIF has no ids, it's made from the record like a CSV row's, see `statement::row_id`
+    pub external_id: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct QifStatement {
+    pub spending: Vec<QifRow>,
+    /// Lines of the records, or splits, where money came in or moved between accounts
+    pub skipped: Vec<u64>,
+}
+
+#[derive(Default)]
+struct Split {
+    category: Option<String>,
+    memo: Option<String>,
+    amount: Option<String>,
+}
+
+/// The fields of a record being read
+#[derive(Default)]
+struct Record {
+    line: u64,
+    date: Option<String>,
+    amount: Option<String>,
+    payee: Option<String>,
+    memo: Option<String>,
+    category: Option<String>,
+    splits: Vec<Split>,
+}
+
+/// Reads every bank-like record, dates are month first like 09/01'23 unless `day_first`
+pub fn read(contents: &str, day_first: bool) -> Result<QifStatement, StatementError> {
+    let mut statement = QifStatement::default();
+    let mut row_ids = statement::RowIds::default();
+    let mut in_bank = false;
+    let mut record = Record::default();
+
+    for (idx, raw) in contents.lines().enumerate() {
+        let line = idx as u64 + 1;
+        let raw = raw.trim_start_matches('\u{feff}').trim_end();
+        if raw.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = raw.strip_prefix('!') {
+            // Options like !Option:AutoSwitch don't start a section
+            if let Some(kind) = header.strip_prefix("Type:") {
+                in_bank = ["bank", "cash", "ccard"].contains(&kind.trim().to_ascii_lowercase().as_str());
+            } else if !header.starts_with("Option:") && !header.starts_with("Clear:") {
+                in_bank = false;
+            }
+            record = Record::default();
+            continue;
+        }
+
+        if !in_bank {
+            continue;
+        }
+
+        if record.line == 0 {
+            record.line = line;
+        }
+
+        let (code, value) = raw.split_at(raw.chars().next().map_or(0, char::len_utf8));
+        let value = Some(value.trim().to_string()).filter(|value| !value.is_empty());
+
+        match code {
+            "D" => record.date = value,
+            "T" | "U" => record.amount = record.amount.take().or(value),
+            "P" => record.payee = value,
+            "M" => record.memo = value,
+            "L" => record.category = value,
+            "S" => record.splits.push(Split {
+                category: value,
+                ..Split::default()
+            }),
+            "E" => split(&mut record, line)?.memo = value,
+            "$" => split(&mut record, line)?.amount = value,
+            "^" => add(&mut statement, &mut row_ids, std::mem::take(&mut record), day_first)?,
+            // Check numbers, cleared flags, addresses and the like don't make it into a budget
+            _ => {}
+        }
+    }
+
+    if record.line != 0 {
+        add(&mut statement, &mut row_ids, record, day_first)?;
+    }
+
+    Ok(statement)
+}
+
+/// The split an E or $ line continues
+fn split(record: &mut Record, line: u64) -> Result<&mut Split, StatementError> {
+    record
+        .splits
+        .last_mut()
+        .ok_or(StatementError::new(Some(line), "a split memo or amount comes before its S line"))
+}
+
+fn add(
+    statement: &mut QifStatement,
+    row_ids: &mut statement::RowIds,
+    record: Record,
+    day_first: bool,
+) -> Result<(), StatementError> {
+    let line = record.line;
+    let raw_date = record
+        .date
+        .as_deref()
+        .ok_or(StatementError::new(Some(line), "the record has no date"))?;
+    let date = parse_date(raw_date, day_first)
+        .ok_or(StatementError::new(Some(line), format!("{} is not a date", raw_date)))?;
+
+    let parts = match record.splits.is_empty() {
+        true => vec![(record.category.clone(), record.memo.clone(), record.amount.clone())],
+        false => record
+            .splits
+            .into_iter()
+            .map(|split| (split.category, split.memo.or(record.memo.clone()), split.amount))
+            .collect(),
+    };
+
+    for (category, memo, amount) in parts {
+        let raw_amount = amount.ok_or(StatementError::new(Some(line), "the record has no amount"))?;
+        let value = parse_amount(&raw_amount).ok_or(StatementError::new(Some(line), format!("{} is not an amount", raw_amount)))?;
+
+        // Transfers name the other account in brackets, the money stays with the owner
+        let category = category.map(|category| category.split('/').next().unwrap().trim().to_string());
+        let transfer = category.as_deref().is_some_and(|category| category.starts_with('['));
+
+        if -value <= 0_f64 || transfer {
+            statement.skipped.push(line);
+            continue;
+        }
+
+        let description = record
+            .payee
+            .as_deref()
+            .or(memo.as_deref())
+            .or(category.as_deref())
+            .ok_or(StatementError::new(Some(line), "the record has no payee, memo or category"))?;
+
+        let description = statement::normalize_description(description);
+        let external_id = row_ids.next(date, &description, -value);
+
+        statement.spending.push(QifRow {
+            line,
+            date,
+            description,
+            amount: -value,
+            category: category.filter(|category| !category.is_empty()),
+            external_id,
+        });
+    }
+
+    Ok(())
+}
+
+/// Understands 09/01/2023, 9/ 1'23 (an apostrophe is a year after 2000), 09-01-23 and 2023-09-01,
+/// a two-digit year without an apostrophe is before 1970 only from 70 on
+fn parse_date(raw: &str, day_first: bool) -> Option<NaiveDate> {
+    let numbers: Vec<&str> = raw
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|number| !number.is_empty())
+        .collect();
+    let [first, second, third] = numbers[..] else {
+        return None;
+    };
+
+    let (year, month, day) = match (first.len(), day_first) {
+        (4, _) => (first, second, third),
+        (_, false) => (third, first, second),
+        (_, true) => (third, second, first),
+    };
+
+    let mut year: i32 = year.parse().ok()?;
+    if year < 100 {
+        year += if raw.contains('\'') || year < 70 { 2000 } else { 1900 };
+    }
+
+    NaiveDate::from_ymd_opt(year, month.parse().ok()?, day.parse().ok()?)
+}
+
+fn parse_amount(raw: &str) -> Option<f64> {
+    raw.replace(',', "").parse().ok().filter(|amount: &f64| amount.is_finite())
+}
+
+/// Writes the transactions of every budget as spending in one bank account, the budget is the category
+pub fn write(budgets: &[models::BudgetManager]) -> String {
+    let mut qif = format!("{}\n", HEADER);
+
+    for budget_manager in budgets {
+        for tx in budget_manager.transactions().borrow().iter() {
+            qif.push_str(&format!("D{}\n", tx.date().format("%m/%d/%Y")));
+            qif.push_str(&format!("T{:.2}\n", -tx.value()));
+            match tx.status() {
+                models::TransactionStatus::Pending => {}
+                models::TransactionStatus::Cleared => qif.push_str("C*\n"),
+                models::TransactionStatus::Reconciled => qif.push_str("CX\n"),
+            }
+            qif.push_str(&format!("P{}\n", tx.name()));
+            qif.push_str(&format!("L{}\n", budget_manager.name()));
+            qif.push_str("^\n");
+        }
+    }
+
+    qif
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::domain::clock::{Clock, SystemClock};
+    use crate::domain::ids::RandomIds;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 9, day).unwrap()
+    }
+
+    #[test]
+    fn reads_bank_records_and_their_splits() {
+        // Given
+        let qif = "!Account\nNChecking\nTBank\n^\n\
+                   !Type:Bank\n\
+                   D9/ 1'23\nT-1,012.50\nPLandlord\nLRent/Home\n^\n\
+                   D09/02/2023\nT2000.00\nPEmployer\nLSalary\n^\n\
+                   D09/03/2023\nT-60.00\nPCorner Grocer\nSGroceries\n$-45.00\nSHousehold\nESoap\n$-15.00\n^\n\
+                   D09/04/2023\nT-100.00\nL[Savings]\n^\n\
+                   !Type:Cat\nNGroceries\nE\n^\n";
+
+        // When
+        let statement = read(qif, false).unwrap();
+
+        // Then
+        let row = |line, day, description: &str, amount, category: &str, occurrence| QifRow {
+            line,
+            date: date(day),
+            description: String::from(description),
+            amount,
+            category: Some(String::from(category)),
+            external_id: statement::row_id(date(day), description, amount, occurrence),
+        };
+        assert_eq!(
+            statement.spending,
+            vec![
+                row(6, 1, "Landlord", 1012.50_f64, "Rent", 0),
+                row(16, 3, "Corner Grocer", 45.00_f64, "Groceries", 0),
+                row(16, 3, "Corner Grocer", 15.00_f64, "Household", 0),
+            ]
+        );
+        assert_eq!(statement.skipped, vec![11, 25]);
+    }
+
+    #[test]
+    fn reads_dates_in_other_orders() {
+        assert_eq!(parse_date("01/09/2023", true), Some(date(1)));
+        assert_eq!(parse_date("2023-09-01", true), Some(date(1)));
+        assert_eq!(parse_date("9/1/99", false), NaiveDate::from_ymd_opt(1999, 9, 1));
+        assert_eq!(parse_date("09/01/23", false), Some(date(1)));
+        assert_eq!(parse_date("13/01/2023", false), None);
+    }
+
+    #[test]
+    fn reports_the_record_that_can_not_be_read() {
+        // Given
+        let qif = "!Type:Bank\nD09/01/2023\nT-1.00\nPGrocer\n^\nT-2.00\nPGrocer\n^\n";
+
+        // When
+        let result = read(qif, false);
+
+        // Then
+        assert_eq!(result.unwrap_err().to_string(), "line 6: the record has no date");
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        // Given
+        let budget = models::Budget::new(&RandomIds, String::from("Groceries"), 200.00_f64);
+        let tx = models::Transaction::new(&RandomIds, String::from("Apples"), 3.50_f64, *budget.id(), SystemClock.today());
+        let budgets = vec![models::BudgetManager::new(budget, RefCell::new(vec![tx.clone()]))];
+
+        // When
+        let qif = write(&budgets);
+
+        // Then
+        assert!(qif.starts_with("!Type:Bank\n"));
+        assert_eq!(
+            read(&qif, false).unwrap().spending,
+            vec![QifRow {
+                line: 2,
+                date: tx.date(),
+                description: String::from("Apples"),
+                amount: 3.50_f64,
+                category: Some(String::from("Groceries")),
+                external_id: statement::row_id(tx.date(), "Apples", 3.50_f64, 0),
+            }]
+        );
+    }
+
+    #[test]
+    fn records_keep_their_id_in_an_overlapping_export() {
+        // Given
+        let september = "!Type:Bank\nD09/01/2023\nT-3.50\nPCafe\n^\nD09/01/2023\nT-3.50\nPCafe\n^\n";
+        let later = "!Type:Bank\nD08/31/2023\nT-9.00\nPGrocer\n^\n\
+                     D09/01/2023\nT-3.50\nPCafe\n^\nD09/01/2023\nT-3.50\nPCafe\n^\n";
+
+        // When
+        let ids = |qif| {
+            read(qif, false)
+                .unwrap()
+                .spending
+                .into_iter()
+                .map(|row| row.external_id)
+                .collect::<Vec<_>>()
+        };
+
+        // Then
+        let september = ids(september);
+        assert_ne!(september[0], september[1]);
+        assert_eq!(ids(later)[1..], september[..]);
+    }
+}
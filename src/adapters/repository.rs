@@ -1,213 +1,1490 @@
 #![allow(dead_code)]
 
 use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use std::{error, fmt};
 
-use rusqlite::{params, Connection, Transaction};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::backup::{Backup, StepResult};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use chrono::NaiveDate;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Transaction, TransactionBehavior};
 
 use crate::domain::models;
 
+/// The budget or the income pool was saved by someone else after it was loaded, nothing was written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConcurrencyConflict {
+    /// `None` when it was the income pool
+    pub budget_id: Option<models::BudgetId>,
+    /// Version it had when it was loaded
+    pub expected: u64,
+    /// Version that is stored now
+    pub found: u64,
+}
+
+impl fmt::Display for ConcurrencyConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.budget_id {
+            Some(budget_id) => write!(f, "budget {} was changed by someone else", budget_id)?,
+            None => write!(f, "the income pool was changed by someone else")?,
+        }
+
+        write!(f, ", expected version {} but found {}", self.expected, self.found)
+    }
+}
+
+impl error::Error for ConcurrencyConflict {}
+
 pub trait Repository {
-    fn add(&self, #[allow(unused)] item: &models::BudgetManager) {}
-    fn get(&self, #[allow(unused)] id: &str) -> models::BudgetManager {
+    /// Saves the budget and bumps its version, unless the stored version differs from the one it was loaded with
+    fn add(&self, #[allow(unused)] item: &models::BudgetManager) -> Result<(), ConcurrencyConflict> {
+        Ok(())
+    }
+    fn get(&self, #[allow(unused)] id: &models::BudgetId) -> models::BudgetManager {
         todo!("get must be implemented by implementer")
     }
-    fn delete(&self, #[allow(unused)] id: &str) {}
+    /// Budgets in the same order as `ids`, `None` for an id that isn't stored
+    fn get_many(&self, ids: &[models::BudgetId]) -> Vec<Option<models::BudgetManager>>;
+    /// Saves the budgets like `add` would, adapters that can save them together write nothing when one is in conflict
+    fn add_all(&self, items: &[models::BudgetManager]) -> Result<(), ConcurrencyConflict> {
+        items.iter().try_for_each(|item| self.add(item))
+    }
+    fn delete(&self, #[allow(unused)] id: &models::BudgetId) {}
+}
+
+pub trait PayeeRepository {
+    fn add_payee(&self, payee: &models::Payee);
+    fn get_payee(&self, id: &str) -> Option<models::Payee>;
+    fn list_payees(&self) -> Vec<models::Payee>;
+}
+
+pub trait AccountRepository {
+    fn add_account(&self, account: &models::Account);
+    fn get_account(&self, id: &str) -> Option<models::Account>;
+    fn list_accounts(&self) -> Vec<models::Account>;
+    /// Ids of every budget holding at least one transaction paid from the account
+    fn account_budget_ids(&self, account_id: &str) -> Vec<models::BudgetId>;
+    /// Account id and external id of every transaction in any budget that has an external id
+    fn external_ids(&self) -> Vec<(Option<String>, String)>;
+}
+
+/// The income pool is a single aggregate per database
+pub trait IncomeRepository {
+    /// Saves the pool and bumps its version, unless the stored version differs from the one it was loaded with
+    fn add_income_pool(&self, pool: &models::IncomePool) -> Result<(), ConcurrencyConflict>;
+    fn get_income_pool(&self) -> models::IncomePool;
+    /// Saves budgets together with the pool that funds them like `Repository::add` and `add_income_pool` would,
+    /// nothing is written when one of them is in conflict
+    fn add_funded(&self, items: &[models::BudgetManager], pool: &models::IncomePool) -> Result<(), ConcurrencyConflict>;
+}
+
+/// A whole snapshot saved at once, e.g., for an import
+pub trait SnapshotRepository {
+    /// Saves the payees and accounts like `add_payee` and `add_account` would, together with the budgets and pool
+    /// like `IncomeRepository::add_funded` would, nothing is written when a budget or the pool is in conflict
+    fn add_snapshot(&self, snapshot: &models::Snapshot) -> Result<(), ConcurrencyConflict>;
+}
+
+/// Read-only queries behind the views, answered without loading whole budgets
+pub trait ViewRepository {
+    /// Every budget with its members but without its transactions, oldest first
+    fn list_budgets(&self) -> Vec<models::Budget>;
+    /// A budget's transactions by date, in the order they were added within a day
+    fn list_transactions(&self, budget_id: &models::BudgetId) -> Vec<models::Transaction>;
+    /// What was spent from an account as (cleared, pending), reconciled transactions count as cleared
+    fn account_spend(&self, account_id: &str) -> (f64, f64);
+    /// Total and number of transactions per payee id, payees without transactions are left out
+    fn payee_spend(&self) -> HashMap<String, (f64, i64)>;
+}
+
+/// Everything the CLI needs from a storage backend
+pub trait Storage:
+    Repository + PayeeRepository + AccountRepository + IncomeRepository + SnapshotRepository + ViewRepository
+{
+}
+
+impl<T> Storage for T where
+    T: Repository + PayeeRepository + AccountRepository + IncomeRepository + SnapshotRepository + ViewRepository
+{
+}
+
+/// How long a connection waits for another writer to finish before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Version of the schema in `create_tables.sql`, kept in the database's `user_version` by `migrate` and `create_tables`
+pub const SCHEMA_VERSION: i64 = 3;
+
+/// A change to the schema of a database made before it
+enum Migration {
+    /// Statements that can run again without harm, e.g., `CREATE TABLE IF NOT EXISTS`
+    Sql(&'static str),
+    /// SQLite can't add a column only if it's missing, so it's looked up first
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        definition: &'static str,
+    },
+}
+
+/// Changes to the schema by the version they bring a database to, in the order they were made
+///
+/// Version 0 is from before the version was kept, such a database can have any of the version 1 changes,
+/// so every change has to be safe to apply to a database that already has it
+const MIGRATIONS: &[(i64, Migration)] = &[
+    (
+        1,
+        Migration::Sql(
+            "CREATE TABLE IF NOT EXISTS payees (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE
+            );
+            CREATE TABLE IF NOT EXISTS payee_aliases (
+                payee_id TEXT NOT NULL,
+                alias TEXT NOT NULL,
+                PRIMARY KEY (payee_id, alias),
+                FOREIGN KEY (payee_id) REFERENCES payees (id) ON DELETE CASCADE
+            );",
+        ),
+    ),
+    (
+        1,
+        Migration::AddColumn {
+            table: "transactions",
+            column: "payee_id",
+            definition: "TEXT REFERENCES payees (id) ON DELETE SET NULL",
+        },
+    ),
+    (
+        1,
+        Migration::Sql(
+            "CREATE TABLE IF NOT EXISTS accounts (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                kind TEXT NOT NULL,
+                opening_balance REAL NOT NULL DEFAULT 0
+            );",
+        ),
+    ),
+    (
+        1,
+        Migration::AddColumn {
+            table: "transactions",
+            column: "account_id",
+            definition: "TEXT REFERENCES accounts (id) ON DELETE SET NULL",
+        },
+    ),
+    (
+        1,
+        Migration::AddColumn {
+            table: "transactions",
+            column: "status",
+            definition: "TEXT NOT NULL DEFAULT 'pending'",
+        },
+    ),
+    (
+        1,
+        Migration::Sql(
+            "CREATE TABLE IF NOT EXISTS income (
+                id TEXT PRIMARY KEY,
+                source TEXT NOT NULL,
+                amount REAL NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS assignments (
+                id TEXT PRIMARY KEY,
+                budget_id TEXT NOT NULL,
+                amount REAL NOT NULL
+            );",
+        ),
+    ),
+    // Transactions from before dates were kept are dated the day the database is migrated
+    (
+        1,
+        Migration::AddColumn {
+            table: "transactions",
+            column: "date",
+            definition: "TEXT NOT NULL DEFAULT ''",
+        },
+    ),
+    (1, Migration::Sql("UPDATE transactions SET date = date('now') WHERE date = '';")),
+    (
+        1,
+        Migration::Sql(
+            "CREATE TABLE IF NOT EXISTS budget_alerts (
+                budget_id TEXT NOT NULL,
+                threshold INTEGER NOT NULL,
+                last_fired TEXT,
+                PRIMARY KEY (budget_id, threshold),
+                FOREIGN KEY (budget_id) REFERENCES budgets (id) ON DELETE CASCADE
+            );",
+        ),
+    ),
+    (
+        1,
+        Migration::AddColumn {
+            table: "transactions",
+            column: "paid_by",
+            definition: "TEXT",
+        },
+    ),
+    (
+        1,
+        Migration::Sql(
+            "CREATE TABLE IF NOT EXISTS budget_members (
+                budget_id TEXT NOT NULL,
+                member TEXT NOT NULL,
+                PRIMARY KEY (budget_id, member),
+                FOREIGN KEY (budget_id) REFERENCES budgets (id) ON DELETE CASCADE
+            );
+            CREATE TABLE IF NOT EXISTS transaction_shares (
+                transaction_id TEXT NOT NULL,
+                member TEXT NOT NULL,
+                amount REAL NOT NULL,
+                PRIMARY KEY (transaction_id, member),
+                FOREIGN KEY (transaction_id) REFERENCES transactions (id) ON DELETE CASCADE
+            );",
+        ),
+    ),
+    (
+        1,
+        Migration::AddColumn {
+            table: "budgets",
+            column: "version",
+            definition: "INTEGER NOT NULL DEFAULT 0",
+        },
+    ),
+    (
+        2,
+        Migration::AddColumn {
+            table: "transactions",
+            column: "external_id",
+            definition: "TEXT",
+        },
+    ),
+    (
+        3,
+        Migration::Sql(
+            "CREATE TABLE IF NOT EXISTS income_pool (
+                id INTEGER PRIMARY KEY CHECK (id = 0),
+                version INTEGER NOT NULL
+            );",
+        ),
+    ),
+];
+
+impl Migration {
+    fn apply(&self, tx: &Transaction) -> rusqlite::Result<()> {
+        match self {
+            Migration::Sql(sql) => tx.execute_batch(sql),
+            Migration::AddColumn { table, column, definition } => {
+                let exists: bool = tx.query_row(
+                    "SELECT count(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+                    params![table, column],
+                    |row| row.get(0),
+                )?;
+
+                if !exists {
+                    tx.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, definition))?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The backup can't replace the database, nothing was restored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoreError {
+    /// Missing, not a database, damaged or encrypted with another passphrase
+    Unreadable(String),
+    /// The backup was made by a newer version of the app, with a schema this one doesn't know
+    SchemaVersion { found: i64, newest: i64 },
+    /// A database without the tables every version of the app has, e.g., an empty one or another app's
+    MissingTables(Vec<String>),
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestoreError::Unreadable(reason) => write!(f, "the backup can't be read: {}", reason),
+            RestoreError::SchemaVersion { found, newest } => write!(
+                f,
+                "the backup has schema version {} but this version of the app only knows up to {}",
+                found, newest
+            ),
+            RestoreError::MissingTables(tables) => {
+                write!(f, "the backup isn't a budget database, it has no {} table", tables.join(" or "))
+            }
+        }
+    }
 }
 
+impl error::Error for RestoreError {}
+
+/// Can be shared between threads, each call borrows a connection from a pool
 pub struct SQLiteRepository {
-    pub conn: RefCell<Connection>,
+    pool: Pool<SqliteConnectionManager>,
+    /// Backups are encrypted with the same passphrase as the database
+    passphrase: Option<String>,
 }
 
 impl SQLiteRepository {
     pub fn new(filename: String) -> Self {
-        let db = Connection::open(filename);
+        SQLiteRepository::open(filename, None)
+    }
+
+    /// Opens a database encrypted with SQLCipher, panics right away when the passphrase doesn't unlock it
+    #[cfg(feature = "sqlcipher")]
+    pub fn encrypted(filename: String, passphrase: String) -> Self {
+        if super::sqlcipher::open(&filename, Some(&passphrase)).is_err() {
+            panic!("Could not open {}, is the passphrase right?", filename);
+        }
+
+        SQLiteRepository::open(filename, Some(passphrase))
+    }
+
+    fn open(filename: String, passphrase: Option<String>) -> Self {
+        let key = passphrase.clone();
+        let manager = SqliteConnectionManager::file(filename).with_init(move |conn| {
+            // The key has to come before anything else touches the database
+            if let Some(passphrase) = &key {
+                conn.pragma_update(None, "key", passphrase)?;
+            }
 
-        let conn = match db {
-            Ok(conn) => conn,
+            conn.busy_timeout(BUSY_TIMEOUT)?;
+
+            // Foreign keys are enforced per connection, cascades rely on it
+            // WAL lets readers carry on while another connection writes
+            conn.execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;")
+        });
+
+        // Open connections as they are needed, a CLI run only ever needs one
+        let pool = Pool::builder().min_idle(Some(1)).build(manager);
+
+        let repo = match pool {
+            Ok(pool) => SQLiteRepository { pool, passphrase },
             _ => panic!("Error opening db"),
         };
+        repo.migrate();
+
+        repo
+    }
+
+    /// Brings a database made with an older schema up to `SCHEMA_VERSION`, one without tables is left alone
+    fn migrate(&self) {
+        let mut conn = self.conn();
+        // Immediate, so two processes opening the same old database don't both migrate it
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
+
+        let version: i64 = tx.query_row("PRAGMA user_version", params![], |row| row.get(0)).unwrap();
+        let has_tables: bool = tx
+            .query_row(
+                "SELECT count(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'transactions'",
+                params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        if !has_tables || version >= SCHEMA_VERSION {
+            return;
+        }
+
+        for (to, migration) in MIGRATIONS {
+            if *to > version {
+                migration.apply(&tx).unwrap();
+            }
+        }
+        tx.pragma_update(None, "user_version", SCHEMA_VERSION).unwrap();
+
+        tx.commit().unwrap();
+    }
+
+    /// Borrows a connection from the pool, it goes back when dropped
+    pub fn conn(&self) -> PooledConnection<SqliteConnectionManager> {
+        self.pool.get().unwrap()
+    }
+
+    /// Creates any missing tables using the schema in `create_tables.sql`
+    pub fn create_tables(&self) {
+        // The schema only creates missing tables, the ones that exist need to be migrated first
+        self.migrate();
+
+        let conn = self.conn();
+
+        conn.execute_batch(SCHEMA).unwrap();
+        // Every table is now as the current schema has it, including those in a new database
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION).unwrap();
+    }
+
+    /// Opens another database file with the same passphrase as this one
+    fn open_file(&self, path: &Path) -> rusqlite::Result<Connection> {
+        let conn = Connection::open(path)?;
+
+        if let Some(passphrase) = &self.passphrase {
+            conn.pragma_update(None, "key", passphrase)?;
+        }
+
+        Ok(conn)
+    }
+
+    /// Copies the whole database to `path` in a single step, which holds a read lock throughout,
+    /// so the copy is consistent even while other connections write
+    pub fn backup_to(&self, path: &Path) {
+        let conn = self.conn();
+        let mut copy = match self.open_file(path) {
+            Ok(copy) => copy,
+            Err(e) => panic!("Error opening {}: {}", path.display(), e),
+        };
+
+        copy_database(&conn, &mut copy);
+    }
+
+    /// Replaces everything in the database with the backup at `path`, once it's known to be readable
+    /// and made with a schema this version knows, an older schema is migrated once restored
+    pub fn restore_from(&self, path: &Path) -> Result<(), RestoreError> {
+        // Opening a missing file would create an empty database
+        if !path.is_file() {
+            return Err(RestoreError::Unreadable(format!("{} doesn't exist", path.display())));
+        }
+
+        let unreadable = |e: rusqlite::Error| RestoreError::Unreadable(e.to_string());
+        let backup = self.open_file(path).map_err(unreadable)?;
 
-        SQLiteRepository {
-            conn: RefCell::new(conn),
+        let found: i64 = backup
+            .query_row("PRAGMA user_version", params![], |row| row.get(0))
+            .map_err(unreadable)?;
+        if found > SCHEMA_VERSION {
+            return Err(RestoreError::SchemaVersion {
+                found,
+                newest: SCHEMA_VERSION,
+            });
         }
+
+        let check: String = backup
+            .query_row("PRAGMA quick_check", params![], |row| row.get(0))
+            .map_err(unreadable)?;
+        if check != "ok" {
+            return Err(RestoreError::Unreadable(check));
+        }
+
+        // Any database passes the checks above with version 0, and `migrate` leaves one without tables alone,
+        // so restoring it would wipe everything
+        let mut missing = vec![];
+        for table in BACKUP_TABLES {
+            let found: bool = backup
+                .query_row(
+                    "SELECT count(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                    params![table],
+                    |row| row.get(0),
+                )
+                .map_err(unreadable)?;
+            if !found {
+                missing.push(table.to_string());
+            }
+        }
+        if !missing.is_empty() {
+            return Err(RestoreError::MissingTables(missing));
+        }
+
+        copy_database(&backup, &mut self.conn());
+        self.migrate();
+
+        Ok(())
     }
 }
 
-fn insert_transactions<'a>(tx: &'a Transaction, transactions: Ref<Vec<models::Transaction>>) {
+/// Tables a database of any schema version has, the rest may come from migrations
+const BACKUP_TABLES: [&str; 2] = ["budgets", "transactions"];
+
+/// How long a copy waits before trying again when one of the databases is locked
+const BACKUP_PAUSE: Duration = Duration::from_millis(250);
+
+/// Copies every page of `from` over `to` in a single step
+fn copy_database(from: &Connection, to: &mut Connection) {
+    let backup = Backup::new(from, to).unwrap();
+
+    while backup.step(-1).unwrap() != StepResult::Done {
+        thread::sleep(BACKUP_PAUSE);
+    }
+}
+
+const SCHEMA: &str = include_str!("../../create_tables.sql");
+
+impl ToSql for models::BudgetId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for models::BudgetId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+impl ToSql for models::TransactionId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for models::TransactionId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+impl ToSql for models::TransactionStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for models::TransactionStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e: String| FromSqlError::Other(e.into()))
+    }
+}
+
+impl ToSql for models::AccountKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for models::AccountKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e: String| FromSqlError::Other(e.into()))
+    }
+}
+
+fn insert_transactions(tx: &Transaction, budget_id: &models::BudgetId, transactions: Ref<Vec<models::Transaction>>) {
+    // The aggregate owns its transactions, so replace whatever was stored before
+    // this keeps removed transactions from lingering in the table
+    tx.execute(
+        "DELETE FROM transaction_shares WHERE transaction_id IN
+        (SELECT id FROM transactions WHERE budget_id = ?1)",
+        params![budget_id],
+    )
+    .unwrap();
+    tx.execute("DELETE FROM transactions WHERE budget_id = ?1", params![budget_id])
+        .unwrap();
+
     let mut statement = tx
         .prepare(
             "INSERT INTO transactions
-        (id, name, value, budget_id) VALUES
-        (?1, ?2, ?3, ?4)",
+        (id, name, value, budget_id, date, payee_id, account_id, status, paid_by, external_id) VALUES
+        (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
         )
         .unwrap();
 
+    let mut share_statement = tx
+        .prepare("INSERT INTO transaction_shares (transaction_id, member, amount) VALUES (?1, ?2, ?3)")
+        .unwrap();
+
     for tx in transactions.iter() {
         statement
-            .execute(params![tx.id(), tx.name(), tx.value(), tx.budget_id()])
+            .execute(params![
+                tx.id(),
+                tx.name(),
+                tx.value(),
+                tx.budget_id(),
+                tx.date(),
+                tx.payee_id(),
+                tx.account_id(),
+                tx.status(),
+                tx.paid_by(),
+                tx.external_id()
+            ])
+            .unwrap();
+
+        for share in tx.shares() {
+            share_statement
+                .execute(params![tx.id(), share.member(), share.amount()])
+                .unwrap();
+        }
+    }
+}
+
+fn insert_alerts(tx: &Transaction, budget_id: &models::BudgetId, alerts: &[models::AlertThreshold]) {
+    tx.execute("DELETE FROM budget_alerts WHERE budget_id = ?1", params![budget_id])
+        .unwrap();
+
+    let mut statement = tx
+        .prepare("INSERT INTO budget_alerts (budget_id, threshold, last_fired) VALUES (?1, ?2, ?3)")
+        .unwrap();
+
+    for alert in alerts {
+        statement
+            .execute(params![budget_id, alert.percent(), alert.last_fired()])
             .unwrap();
     }
 }
 
-fn insert_budget<'a>(tx: &'a Transaction, budget: &models::Budget) {
+fn insert_budget(tx: &Transaction, budget: &models::Budget, version: u64) {
     let mut statement = tx
         .prepare(
-            "INSERT INTO budgets (id, name, total) VALUES (?1, ?2, ?3) ON CONFLICT (id) DO UPDATE SET total=?3",
+            "INSERT INTO budgets (id, name, total, version) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (id) DO UPDATE SET total=?3, version=?4",
         )
         .unwrap();
 
     statement
-        .execute(params![budget.id(), budget.name(), budget.total()])
+        .execute(params![budget.id(), budget.name(), budget.total(), version])
         .unwrap();
+
+    tx.execute("DELETE FROM budget_members WHERE budget_id = ?1", params![budget.id()])
+        .unwrap();
+
+    let mut statement = tx
+        .prepare("INSERT INTO budget_members (budget_id, member) VALUES (?1, ?2)")
+        .unwrap();
+
+    for member in budget.members() {
+        statement.execute(params![budget.id(), member]).unwrap();
+    }
+}
+
+/// `?1, ?2, ...` for an `IN (...)` list of `n` values
+fn placeholders(n: usize) -> String {
+    (1..=n).map(|i| format!("?{}", i)).collect::<Vec<String>>().join(", ")
+}
+
+/// Members of each budget in the order they were added, keyed by budget id
+fn load_members(
+    conn: &Connection,
+    budget_ids: &[models::BudgetId],
+) -> HashMap<models::BudgetId, Vec<String>> {
+    let mut statement = conn
+        .prepare(&format!(
+            "SELECT budget_id, member FROM budget_members WHERE budget_id IN ({}) ORDER BY rowid",
+            placeholders(budget_ids.len())
+        ))
+        .unwrap();
+
+    let row_iter = statement
+        .query_map(params_from_iter(budget_ids), |row| {
+            let budget_id: models::BudgetId = row.get(0)?;
+            Ok((budget_id, row.get(1)?))
+        })
+        .unwrap();
+
+    let mut members: HashMap<models::BudgetId, Vec<String>> = HashMap::new();
+    for row in row_iter {
+        let (budget_id, member) = row.unwrap();
+        members.entry(budget_id).or_default().push(member);
+    }
+
+    members
+}
+
+/// Shares of every shared transaction in the budgets, keyed by transaction id
+fn load_shares(
+    conn: &Connection,
+    budget_ids: &[models::BudgetId],
+) -> HashMap<models::TransactionId, Vec<models::Share>> {
+    let mut statement = conn
+        .prepare(&format!(
+            "SELECT s.transaction_id, s.member, s.amount FROM transaction_shares AS s
+            JOIN transactions AS t ON t.id = s.transaction_id
+            WHERE t.budget_id IN ({})
+            ORDER BY s.rowid",
+            placeholders(budget_ids.len())
+        ))
+        .unwrap();
+
+    let row_iter = statement
+        .query_map(params_from_iter(budget_ids), |row| {
+            let tx_id: models::TransactionId = row.get(0)?;
+            Ok((tx_id, models::Share::new(row.get(1)?, row.get(2)?)))
+        })
+        .unwrap();
+
+    let mut shares: HashMap<models::TransactionId, Vec<models::Share>> = HashMap::new();
+    for row in row_iter {
+        let (tx_id, share) = row.unwrap();
+        shares.entry(tx_id).or_default().push(share);
+    }
+
+    shares
+}
+
+/// Alert thresholds of each budget from lowest to highest, keyed by budget id
+fn load_alerts(
+    conn: &Connection,
+    budget_ids: &[models::BudgetId],
+) -> HashMap<models::BudgetId, Vec<models::AlertThreshold>> {
+    let mut statement = conn
+        .prepare(&format!(
+            "SELECT budget_id, threshold, last_fired FROM budget_alerts
+            WHERE budget_id IN ({})
+            ORDER BY threshold",
+            placeholders(budget_ids.len())
+        ))
+        .unwrap();
+
+    let row_iter = statement
+        .query_map(params_from_iter(budget_ids), |row| {
+            let budget_id: models::BudgetId = row.get(0)?;
+            Ok((budget_id, models::AlertThreshold::load(row.get(1)?, row.get(2)?)))
+        })
+        .unwrap();
+
+    let mut alerts: HashMap<models::BudgetId, Vec<models::AlertThreshold>> = HashMap::new();
+    for row in row_iter {
+        let (budget_id, alert) = row.unwrap();
+        alerts.entry(budget_id).or_default().push(alert);
+    }
+
+    alerts
+}
+
+/// Budget columns first, then the columns of one of its transactions,
+/// the transaction columns are all NULL for a budget without transactions
+const AGGREGATE_QUERY: &str = "SELECT
+    b.id, b.name, b.total, b.version,
+    t.id, t.name, t.value, t.date, t.payee_id, t.account_id, t.status, t.paid_by, t.external_id
+    FROM budgets AS b
+    LEFT JOIN transactions AS t ON t.budget_id = b.id";
+
+/// Reads one row of `AGGREGATE_QUERY`
+fn map_aggregate_row(
+    row: &rusqlite::Row,
+) -> rusqlite::Result<((models::Budget, u64), Option<models::Transaction>)> {
+    let budget_id: models::BudgetId = row.get(0)?;
+    let budget = models::Budget::load(budget_id, row.get(1)?, row.get(2)?);
+    let version: u64 = row.get(3)?;
+
+    let tx_id: Option<models::TransactionId> = row.get(4)?;
+    let tx = match tx_id {
+        Some(tx_id) => {
+            let date: NaiveDate = row.get(7)?;
+
+            Some(
+                models::Transaction::load(tx_id, row.get(5)?, row.get(6)?, budget_id, date)
+                    .with_payee(row.get(8)?)
+                    .with_account(row.get(9)?)
+                    .with_status(row.get(10)?)
+                    .with_paid_by(row.get(11)?)
+                    .with_external_id(row.get(12)?),
+            )
+        }
+        None => None,
+    };
+
+    Ok(((budget, version), tx))
+}
+
+/// Writes the budget with the next version, unless the stored version differs from the one it was loaded with
+fn save_budget(tx: &Transaction, item: &models::BudgetManager) -> Result<u64, ConcurrencyConflict> {
+    // A budget that was deleted since it was loaded counts as version 0
+    let found: u64 = tx
+        .query_row("SELECT version FROM budgets WHERE id = ?1", params![item.id()], |row| row.get(0))
+        .optional()
+        .unwrap()
+        .unwrap_or(0);
+
+    if found != item.version() {
+        return Err(ConcurrencyConflict {
+            budget_id: Some(*item.id()),
+            expected: item.version(),
+            found,
+        });
+    }
+
+    insert_budget(tx, item.budget(), found + 1);
+    insert_transactions(tx, item.id(), item.transactions().borrow());
+    insert_alerts(tx, item.id(), item.alerts());
+
+    Ok(found + 1)
 }
 
 impl Repository for SQLiteRepository {
-    fn add(&self, item: &models::BudgetManager) {
-        let mut conn = self.conn.borrow_mut();
+    fn add(&self, item: &models::BudgetManager) -> Result<(), ConcurrencyConflict> {
+        let mut conn = self.conn();
 
-        let tx = conn.transaction().unwrap();
+        // Take the write lock before reading the version so nobody can save in between
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
 
-        insert_budget(&tx, item.budget());
-        insert_transactions(&tx, item.transactions().borrow());
+        let version = save_budget(&tx, item)?;
 
         let result = tx.commit();
         match result {
             Ok(_) => (),
             Err(e) => panic!("{}", e),
         }
+
+        item.set_version(version);
+
+        Ok(())
     }
 
-    fn get(&self, id: &str) -> models::BudgetManager {
-        // Get the budget - it sucks to do this in two queries
-        // But i don't feel like writing data mapping logic right now
-        let conn = self.conn.borrow();
+    fn add_all(&self, items: &[models::BudgetManager]) -> Result<(), ConcurrencyConflict> {
+        let mut conn = self.conn();
 
-        let mut budget_statement = conn.prepare("SELECT * FROM budgets where id = ?1").unwrap();
+        // Dropping the transaction on a conflict rolls back whatever was saved before it
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
 
-        let budget = budget_statement
-            .query_row(params![id], |row| {
-                let id: String = row.get(0).unwrap();
-                let name: String = row.get(1).unwrap();
-                let total: f64 = row.get(2).unwrap();
+        let versions = items
+            .iter()
+            .map(|item| save_budget(&tx, item))
+            .collect::<Result<Vec<u64>, ConcurrencyConflict>>()?;
 
-                let budget = models::Budget::load(id, name, total);
+        let result = tx.commit();
+        match result {
+            Ok(_) => (),
+            Err(e) => panic!("{}", e),
+        }
 
-                Ok(budget)
-            })
+        for (item, version) in items.iter().zip(versions) {
+            item.set_version(version);
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, id: &models::BudgetId) -> models::BudgetManager {
+        match self.get_many(&[*id]).pop().flatten() {
+            Some(budget_manager) => budget_manager,
+            _ => panic!("Budget not found"),
+        }
+    }
+
+    /// Budgets and their transactions come from one join, members, shares and alerts from one query each,
+    /// so it's four queries however many budgets are loaded
+    fn get_many(&self, ids: &[models::BudgetId]) -> Vec<Option<models::BudgetManager>> {
+        let conn = self.conn();
+
+        let mut members = load_members(&conn, ids);
+        let mut shares = load_shares(&conn, ids);
+        let mut alerts = load_alerts(&conn, ids);
+
+        let mut statement = conn
+            .prepare(&format!(
+                "{} WHERE b.id IN ({}) ORDER BY b.rowid, t.rowid",
+                AGGREGATE_QUERY,
+                placeholders(ids.len())
+            ))
             .unwrap();
 
-        // Get all the transactions - refactor later to use a join and get all the data in a single go
-        // This is fine for quick hacking...
+        let row_iter = statement.query_map(params_from_iter(ids), map_aggregate_row).unwrap();
+
+        // A budget comes back once per transaction, fold its rows into one aggregate
+        let mut loaded: HashMap<models::BudgetId, models::BudgetManager> = HashMap::new();
+        for row in row_iter {
+            let ((budget, version), tx) = row.unwrap();
+
+            let budget_manager = loaded.entry(*budget.id()).or_insert_with(|| {
+                let budget_members = members.remove(budget.id()).unwrap_or_default();
+                let budget_alerts = alerts.remove(budget.id()).unwrap_or_default();
+
+                models::BudgetManager::new(budget.with_members(budget_members), RefCell::new(vec![]))
+                    .with_alerts(budget_alerts)
+                    .with_version(version)
+            });
+
+            if let Some(tx) = tx {
+                let tx_shares = shares.remove(tx.id()).unwrap_or_default();
+                budget_manager.transactions().borrow_mut().push(tx.with_shares(tx_shares));
+            }
+        }
+
+        ids.iter().map(|id| loaded.get(id).cloned()).collect()
+    }
+
+    fn delete(&self, id: &models::BudgetId) {
+        let conn = self.conn();
+        let mut statement = conn.prepare("DELETE FROM budgets WHERE id = ?1").unwrap();
+        statement.execute(params![id]).unwrap();
+    }
+}
+
+/// Writes the payee over the stored one with the same id, aliases included
+fn insert_payee(tx: &Transaction, payee: &models::Payee) {
+    tx.execute(
+        "INSERT INTO payees (id, name) VALUES (?1, ?2) ON CONFLICT (id) DO UPDATE SET name=?2",
+        params![payee.id(), payee.name()],
+    )
+    .unwrap();
+
+    tx.execute("DELETE FROM payee_aliases WHERE payee_id = ?1", params![payee.id()])
+        .unwrap();
+
+    let mut statement = tx
+        .prepare("INSERT INTO payee_aliases (payee_id, alias) VALUES (?1, ?2)")
+        .unwrap();
+
+    for alias in payee.aliases() {
+        statement.execute(params![payee.id(), alias]).unwrap();
+    }
+}
+
+impl PayeeRepository for SQLiteRepository {
+    fn add_payee(&self, payee: &models::Payee) {
+        let mut conn = self.conn();
+
+        let tx = conn.transaction().unwrap();
+
+        insert_payee(&tx, payee);
+
+        let result = tx.commit();
+        match result {
+            Ok(_) => (),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn get_payee(&self, id: &str) -> Option<models::Payee> {
+        self.list_payees().into_iter().find(|p| p.id() == id)
+    }
+
+    fn list_payees(&self) -> Vec<models::Payee> {
+        let conn = self.conn();
+
         let mut statement = conn
             .prepare(
-                "SELECT * FROM transactions
-                WHERE budget_id = ?1",
+                "SELECT p.id, p.name, a.alias FROM payees AS p
+                LEFT JOIN payee_aliases AS a ON a.payee_id = p.id
+                ORDER BY p.name, a.rowid",
             )
             .unwrap();
 
         let row_iter = statement
-            .query_map(params![id], |row| {
+            .query_map(params![], |row| {
                 let id: String = row.get(0)?;
                 let name: String = row.get(1)?;
-                let value: f64 = row.get(2)?;
-                let budget_id: String = row.get(3)?;
+                let alias: Option<String> = row.get(2)?;
+
+                Ok((id, name, alias))
+            })
+            .unwrap();
+
+        // Rows arrive grouped by payee, fold the aliases into their payee
+        let mut payees: Vec<models::Payee> = vec![];
+        for row in row_iter {
+            let (id, name, alias) = row.unwrap();
+
+            if payees.last().map(|p| p.id() != id).unwrap_or(true) {
+                payees.push(models::Payee::load(id, name, vec![]));
+            }
 
-                let tx = models::Transaction::load(id, name, value, budget_id);
+            if let Some(alias) = alias {
+                payees.last_mut().unwrap().add_alias(alias);
+            }
+        }
+
+        payees
+    }
+}
+
+fn insert_account(conn: &Connection, account: &models::Account) {
+    let mut statement = conn
+        .prepare(
+            "INSERT INTO accounts (id, name, kind, opening_balance) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (id) DO UPDATE SET name=?2, kind=?3, opening_balance=?4",
+        )
+        .unwrap();
+
+    statement
+        .execute(params![account.id(), account.name(), account.kind(), account.opening_balance()])
+        .unwrap();
+}
+
+impl AccountRepository for SQLiteRepository {
+    fn add_account(&self, account: &models::Account) {
+        insert_account(&self.conn(), account);
+    }
 
-                Ok(tx)
+    fn get_account(&self, id: &str) -> Option<models::Account> {
+        self.list_accounts().into_iter().find(|a| a.id() == id)
+    }
+
+    fn list_accounts(&self) -> Vec<models::Account> {
+        let conn = self.conn();
+
+        let mut statement = conn
+            .prepare("SELECT id, name, kind, opening_balance FROM accounts ORDER BY name")
+            .unwrap();
+
+        let row_iter = statement
+            .query_map(params![], |row| {
+                Ok(models::Account::load(row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
             })
             .unwrap();
 
-        let tx: Vec<models::Transaction> = row_iter.map(|f| f.unwrap()).collect();
+        row_iter.map(|f| f.unwrap()).collect()
+    }
+
+    fn account_budget_ids(&self, account_id: &str) -> Vec<models::BudgetId> {
+        let conn = self.conn();
+
+        let mut statement = conn
+            .prepare("SELECT DISTINCT budget_id FROM transactions WHERE account_id = ?1")
+            .unwrap();
+
+        let row_iter = statement
+            .query_map(params![account_id], |row| row.get(0))
+            .unwrap();
 
-        models::BudgetManager::new(budget, RefCell::new(tx))
+        row_iter.map(|f| f.unwrap()).collect()
     }
 
-    fn delete(&self, id: &str) {
-        let conn = self.conn.borrow();
-        let mut statement = conn.prepare("DELETE FROM budgets WHERE id = ?1").unwrap();
-        statement.execute(params![id]).unwrap();
+    fn external_ids(&self) -> Vec<(Option<String>, String)> {
+        let conn = self.conn();
+
+        let mut statement = conn
+            .prepare("SELECT account_id, external_id FROM transactions WHERE external_id IS NOT NULL")
+            .unwrap();
+
+        let row_iter = statement
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+
+        row_iter.map(|f| f.unwrap()).collect()
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+fn insert_income_pool(tx: &Transaction, pool: &models::IncomePool) {
+    let mut statement = tx
+        .prepare("INSERT INTO income (id, source, amount) VALUES (?1, ?2, ?3) ON CONFLICT (id) DO UPDATE SET source=?2, amount=?3")
+        .unwrap();
 
-    fn create_tables(repo: &SQLiteRepository) {
-        let conn = repo.conn.borrow();
+    for income in pool.income() {
+        statement
+            .execute(params![income.id(), income.source(), income.amount()])
+            .unwrap();
+    }
 
-        conn.execute("PRAGMA foreign_keys = ON", ()).unwrap();
-        conn.execute("CREATE TABLE IF NOT EXISTS budgets (
-            id TEXT PRIMARY KEY,
-            name TEXT NOT NULL,
-            total REAL
-        )", ()).unwrap();
+    let mut statement = tx
+        .prepare("INSERT INTO assignments (id, budget_id, amount) VALUES (?1, ?2, ?3) ON CONFLICT (id) DO UPDATE SET budget_id=?2, amount=?3")
+        .unwrap();
 
-        conn.execute("CREATE TABLE IF NOT EXISTS transactions (
-            id TEXT PRIMARY KEY,
-            name TEXT NOT NULL,
-            value REAL,
-            budget_id TEXT NOT NULL,
-            FOREIGN KEY (budget_id)
-               REFERENCES budgets (id)
-               ON DELETE CASCADE
-        )", ()).unwrap();
+    for assignment in pool.assignments() {
+        statement
+            .execute(params![assignment.id(), assignment.budget_id(), assignment.amount()])
+            .unwrap();
     }
+}
 
-    fn drop_tables(repo: &SQLiteRepository) {
-        let conn = repo.conn.borrow();
+/// Writes the pool with the next version, unless the stored version differs from the one it was loaded with
+fn save_income_pool(tx: &Transaction, pool: &models::IncomePool) -> Result<u64, ConcurrencyConflict> {
+    // Nothing is stored before the pool's first save, which counts as version 0
+    let found: u64 = tx
+        .query_row("SELECT version FROM income_pool WHERE id = 0", params![], |row| row.get(0))
+        .optional()
+        .unwrap()
+        .unwrap_or(0);
 
-        conn.execute("DROP TABLE budgets", ()).unwrap();
-        conn.execute("DROP TABLE transactions", ()).unwrap();
+    if found != pool.version() {
+        return Err(ConcurrencyConflict {
+            budget_id: None,
+            expected: pool.version(),
+            found,
+        });
     }
 
-    fn build_budget_manager_with_tx() -> models::BudgetManager {
-        let budget = models::Budget::new(String::from("my-budget"), 200.00_f64);
-        let mut bm = models::BudgetManager::new(budget, RefCell::new(vec![]));
-        bm.add_tx(String::from("cheeseborger"), 3.99_f64);
+    tx.execute(
+        "INSERT INTO income_pool (id, version) VALUES (0, ?1) ON CONFLICT (id) DO UPDATE SET version=?1",
+        params![found + 1],
+    )
+    .unwrap();
+    insert_income_pool(tx, pool);
+
+    Ok(found + 1)
+}
 
-        bm
+impl IncomeRepository for SQLiteRepository {
+    fn add_income_pool(&self, pool: &models::IncomePool) -> Result<(), ConcurrencyConflict> {
+        let mut conn = self.conn();
+
+        // Take the write lock before reading the version so nobody can save in between
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
+
+        let version = save_income_pool(&tx, pool)?;
+
+        let result = tx.commit();
+        match result {
+            Ok(_) => (),
+            Err(e) => panic!("{}", e),
+        }
+
+        pool.set_version(version);
+
+        Ok(())
     }
 
+    fn add_funded(&self, items: &[models::BudgetManager], pool: &models::IncomePool) -> Result<(), ConcurrencyConflict> {
+        let mut conn = self.conn();
+
+        // Dropping the transaction on a conflict rolls back whatever was saved before it
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
+
+        let versions = items
+            .iter()
+            .map(|item| save_budget(&tx, item))
+            .collect::<Result<Vec<u64>, ConcurrencyConflict>>()?;
+        let pool_version = save_income_pool(&tx, pool)?;
+
+        let result = tx.commit();
+        match result {
+            Ok(_) => (),
+            Err(e) => panic!("{}", e),
+        }
+
+        for (item, version) in items.iter().zip(versions) {
+            item.set_version(version);
+        }
+        pool.set_version(pool_version);
+
+        Ok(())
+    }
+
+    fn get_income_pool(&self) -> models::IncomePool {
+        let conn = self.conn();
+
+        let mut statement = conn
+            .prepare("SELECT id, source, amount FROM income ORDER BY rowid")
+            .unwrap();
+        let income = statement
+            .query_map(params![], |row| {
+                Ok(models::Income::load(row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap()
+            .map(|f| f.unwrap())
+            .collect();
+
+        let mut statement = conn
+            .prepare("SELECT id, budget_id, amount FROM assignments ORDER BY rowid")
+            .unwrap();
+        let assignments = statement
+            .query_map(params![], |row| {
+                Ok(models::Assignment::load(row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap()
+            .map(|f| f.unwrap())
+            .collect();
+
+        let version: u64 = conn
+            .query_row("SELECT version FROM income_pool WHERE id = 0", params![], |row| row.get(0))
+            .optional()
+            .unwrap()
+            .unwrap_or(0);
+
+        models::IncomePool::load(income, assignments).with_version(version)
+    }
+}
+
+impl SnapshotRepository for SQLiteRepository {
+    fn add_snapshot(&self, snapshot: &models::Snapshot) -> Result<(), ConcurrencyConflict> {
+        let mut conn = self.conn();
+
+        // Dropping the transaction on a conflict rolls back whatever was saved before it
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
+
+        for payee in &snapshot.payees {
+            insert_payee(&tx, payee);
+        }
+        for account in &snapshot.accounts {
+            insert_account(&tx, account);
+        }
+
+        let versions = snapshot
+            .budgets
+            .iter()
+            .map(|item| save_budget(&tx, item))
+            .collect::<Result<Vec<u64>, ConcurrencyConflict>>()?;
+        let pool_version = save_income_pool(&tx, &snapshot.income_pool)?;
+
+        let result = tx.commit();
+        match result {
+            Ok(_) => (),
+            Err(e) => panic!("{}", e),
+        }
+
+        for (item, version) in snapshot.budgets.iter().zip(versions) {
+            item.set_version(version);
+        }
+        snapshot.income_pool.set_version(pool_version);
+
+        Ok(())
+    }
+}
+
+impl ViewRepository for SQLiteRepository {
+    fn list_budgets(&self) -> Vec<models::Budget> {
+        let conn = self.conn();
+
+        let mut statement = conn
+            .prepare("SELECT id, name, total FROM budgets ORDER BY rowid")
+            .unwrap();
+
+        let budgets: Vec<models::Budget> = statement
+            .query_map(params![], |row| Ok(models::Budget::load(row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(|f| f.unwrap())
+            .collect();
+
+        let ids: Vec<models::BudgetId> = budgets.iter().map(|budget| *budget.id()).collect();
+        let mut members = load_members(&conn, &ids);
+
+        budgets
+            .into_iter()
+            .map(|budget| {
+                let budget_members = members.remove(budget.id()).unwrap_or_default();
+                budget.with_members(budget_members)
+            })
+            .collect()
+    }
+
+    fn list_transactions(&self, budget_id: &models::BudgetId) -> Vec<models::Transaction> {
+        let conn = self.conn();
+
+        let mut shares = load_shares(&conn, &[*budget_id]);
+
+        let mut statement = conn
+            .prepare(
+                "SELECT id, name, value, budget_id, date, payee_id, account_id, status, paid_by, external_id
+                FROM transactions
+                WHERE budget_id = ?1
+                ORDER BY date, rowid",
+            )
+            .unwrap();
+
+        let row_iter = statement
+            .query_map(params![budget_id], |row| {
+                let id: models::TransactionId = row.get(0)?;
+                let tx_shares = shares.remove(&id).unwrap_or_default();
+
+                Ok(models::Transaction::load(id, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)
+                    .with_payee(row.get(5)?)
+                    .with_account(row.get(6)?)
+                    .with_status(row.get(7)?)
+                    .with_paid_by(row.get(8)?)
+                    .with_external_id(row.get(9)?)
+                    .with_shares(tx_shares))
+            })
+            .unwrap();
+
+        row_iter.map(|f| f.unwrap()).collect()
+    }
+
+    fn account_spend(&self, account_id: &str) -> (f64, f64) {
+        let conn = self.conn();
+
+        conn.query_row(
+            "SELECT
+                COALESCE(SUM(CASE WHEN status != 'pending' THEN value END), 0),
+                COALESCE(SUM(CASE WHEN status = 'pending' THEN value END), 0)
+            FROM transactions WHERE account_id = ?1",
+            params![account_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap()
+    }
+
+    fn payee_spend(&self) -> HashMap<String, (f64, i64)> {
+        let conn = self.conn();
+
+        let mut statement = conn
+            .prepare(
+                "SELECT payee_id, SUM(value), COUNT(id) FROM transactions
+                WHERE payee_id IS NOT NULL
+                GROUP BY payee_id",
+            )
+            .unwrap();
+
+        let row_iter = statement
+            .query_map(params![], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))
+            .unwrap();
+
+        row_iter.map(|f| f.unwrap()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::clock::{Clock, SystemClock};
+    use crate::domain::ids::RandomIds;
+    use crate::adapters::conformance::conformance_tests;
+    use std::sync::Arc;
+    use std::thread;
+    use tempfile::TempDir;
+
+    /// A repository on a fresh database in its own temporary directory, removed along with the directory
+    fn temp_repo() -> (SQLiteRepository, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = SQLiteRepository::new(dir.path().join("budgets.db").to_string_lossy().to_string());
+        repo.create_tables();
+
+        (repo, dir)
+    }
+
+    conformance_tests!(temp_repo());
+
     #[test]
-    fn can_add_retrieve_budget_manager_aggregate() {
+    fn can_share_repository_between_threads() {
         // Given
-        let repo = SQLiteRepository::new(String::from("budgets-int.db"));
-        create_tables(&repo);
+        let (repo, _dir) = temp_repo();
+        let repo = Arc::new(repo);
 
-        let bm = build_budget_manager_with_tx();
+        let budget = models::Budget::new(&RandomIds, String::from("my-budget"), 200.00_f64);
+        let mut bm = models::BudgetManager::new(budget, RefCell::new(vec![]));
+        bm.add_tx(&RandomIds, String::from("cheeseborger"), 3.99_f64, SystemClock.today());
+        repo.add(&bm).unwrap();
 
         // When
-        repo.add(&bm);
+        // Every thread adds a transaction to the same budget, starting over when another thread saved first
+        let handles: Vec<thread::JoinHandle<()>> = (0..4)
+            .map(|i| {
+                let repo = Arc::clone(&repo);
+                let id = *bm.id();
+
+                thread::spawn(move || loop {
+                    let mut budget_manager = repo.get(&id);
+                    budget_manager.add_tx(&RandomIds, format!("thread-{}", i), 1.00_f64, SystemClock.today());
+
+                    if repo.add(&budget_manager).is_ok() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
 
         // Then
         let retrieved_bm = repo.get(bm.id());
+        assert_eq!(retrieved_bm.transactions().borrow().len(), 5);
+        assert_eq!(retrieved_bm.available_funds(), 192.01_f64);
+        assert_eq!(retrieved_bm.version(), 5);
+    }
 
-        assert_eq!(bm.available_funds(), retrieved_bm.available_funds());
-        assert_eq!(bm.transactions(), retrieved_bm.transactions());
+    #[test]
+    fn opening_an_older_database_migrates_it() {
+        // Given
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("budgets.db").to_string_lossy().to_string();
+        let conn = Connection::open(&filename).unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        conn.execute_batch("ALTER TABLE transactions DROP COLUMN external_id; PRAGMA user_version = 0;")
+            .unwrap();
+        drop(conn);
 
-        // Drop tables
-        drop_tables(&repo);
+        let budget = models::Budget::new(&RandomIds, String::from("my-budget"), 200.00_f64);
+        let tx = models::Transaction::new(&RandomIds, String::from("grocer"), 3.99_f64, *budget.id(), SystemClock.today())
+            .with_external_id(Some(String::from("fitid-1")));
+        let bm = models::BudgetManager::new(budget, RefCell::new(vec![tx]));
+
+        // When
+        let repo = SQLiteRepository::new(filename);
+        repo.add(&bm).unwrap();
+
+        // Then
+        let version: i64 = repo
+            .conn()
+            .query_row("PRAGMA user_version", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        assert_eq!(repo.get(bm.id()).transactions(), bm.transactions());
     }
 
     #[test]
-    #[should_panic]
-    fn can_delete_budget_manager_aggregate() {
+    fn opening_a_database_from_before_the_schema_version_migrates_it() {
         // Given
-        let repo = SQLiteRepository::new(String::from("budgets-int.db"));
-        create_tables(&repo);
-
-        let bm = build_budget_manager_with_tx();
-        repo.add(&bm);
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("budgets.db").to_string_lossy().to_string();
+        // The schema the first release created, without a version
+        Connection::open(&filename)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE budgets (
+                    id TEXT PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    total REAL
+                );
+                CREATE TABLE transactions (
+                    id TEXT PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    value REAL,
+                    budget_id TEXT NOT NULL,
+                    FOREIGN KEY (budget_id) REFERENCES budgets (id) ON DELETE CASCADE
+                );
+                INSERT INTO budgets (id, name, total)
+                VALUES ('576bc364-7574-40ce-92ca-f488c613b7ea', 'my-budget', 196.01);
+                INSERT INTO transactions (id, name, value, budget_id)
+                VALUES ('621dba84-399b-4846-8a9e-76a2d1692683', 'cheeseborger', 3.99, '576bc364-7574-40ce-92ca-f488c613b7ea');",
+            )
+            .unwrap();
+        let id: models::BudgetId = "576bc364-7574-40ce-92ca-f488c613b7ea".parse().unwrap();
 
         // When
-        repo.delete(&bm.id());
+        let repo = SQLiteRepository::new(filename);
+        let mut bm = repo.get(&id);
+        bm.add_tx(&RandomIds, String::from("fries"), 2.49_f64, SystemClock.today());
+        repo.add(&bm).unwrap();
 
         // Then
-        repo.get(&bm.id());
+        let version: i64 = repo
+            .conn()
+            .query_row("PRAGMA user_version", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+
+        let retrieved_bm = repo.get(&id);
+        assert_eq!(retrieved_bm.budget().name(), "my-budget");
+        assert_eq!(retrieved_bm.transactions(), bm.transactions());
+        assert_eq!(retrieved_bm.transactions().borrow()[0].status(), models::TransactionStatus::Pending);
+        assert_eq!(repo.get_income_pool(), models::IncomePool::new());
+    }
 
-        // Cleanup
-        drop_tables(&repo);
+    #[test]
+    fn creating_the_tables_sets_the_schema_version() {
+        let (repo, _dir) = temp_repo();
+
+        let version: i64 = repo
+            .conn()
+            .query_row("PRAGMA user_version", params![], |row| row.get(0))
+            .unwrap();
+
+        assert_eq!(version, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn database_made_from_the_schema_file_is_stamped_when_opened() {
+        // Given
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("budgets.db").to_string_lossy().to_string();
+        Connection::open(&filename).unwrap().execute_batch(SCHEMA).unwrap();
+
+        // When
+        let repo = SQLiteRepository::new(filename);
+
+        // Then
+        let version: i64 = repo
+            .conn()
+            .query_row("PRAGMA user_version", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        assert_eq!(repo.list_budgets(), vec![]);
     }
 }
@@ -0,0 +1,110 @@
+//! Encrypted databases through SQLCipher, only built with the `sqlcipher` feature
+//! An encrypted database looks like random bytes, so it's told apart from a plain one by its header
+
+use std::fs::{self, File};
+use std::io::Read;
+
+use rusqlite::{params, Connection, DatabaseName};
+
+/// First bytes of every unencrypted SQLite database
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Whether the file holds something other than a plain SQLite database, a missing or empty file isn't encrypted
+pub fn is_encrypted(filename: &str) -> bool {
+    let mut header = [0_u8; 16];
+
+    match File::open(filename).and_then(|mut file| file.read_exact(&mut header)) {
+        Ok(_) => &header != SQLITE_HEADER,
+        Err(_) => false,
+    }
+}
+
+/// Opens a connection and unlocks it, `None` leaves the database plain
+pub(crate) fn open(filename: &str, passphrase: Option<&str>) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(filename)?;
+
+    if let Some(passphrase) = passphrase {
+        conn.pragma_update(None, "key", passphrase)?;
+    }
+
+    // Nothing is decrypted until the first read, a wrong passphrase only shows up here
+    conn.query_row("SELECT count(*) FROM sqlite_master", params![], |row| row.get::<_, i64>(0))?;
+
+    Ok(conn)
+}
+
+/// Changes the passphrase of an encrypted database, or encrypts a plain one when `passphrase` is `None`
+pub fn rekey(filename: &str, passphrase: Option<&str>, new_passphrase: &str) {
+    let conn = match open(filename, passphrase) {
+        Ok(conn) => conn,
+        Err(_) => panic!("Could not open {}, is the passphrase right?", filename),
+    };
+
+    match passphrase {
+        Some(_) => conn.pragma_update(None, "rekey", new_passphrase).unwrap(),
+        None => encrypt(conn, filename, new_passphrase),
+    }
+}
+
+/// SQLCipher can't encrypt a database in place, so everything is exported into an encrypted copy
+/// that then replaces the plain file
+fn encrypt(conn: Connection, filename: &str, passphrase: &str) {
+    let encrypted = format!("{}.encrypted", filename);
+    let _ = fs::remove_file(&encrypted);
+
+    let user_version: i64 = conn.query_row("PRAGMA user_version", params![], |row| row.get(0)).unwrap();
+
+    // Fold the write-ahead log into the file so the export sees everything
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", params![], |_| Ok(())).unwrap();
+    conn.execute("ATTACH DATABASE ?1 AS encrypted KEY ?2", params![encrypted, passphrase])
+        .unwrap();
+    conn.query_row("SELECT sqlcipher_export('encrypted')", params![], |_| Ok(()))
+        .unwrap();
+    conn.pragma_update(Some(DatabaseName::Attached("encrypted")), "user_version", user_version).unwrap();
+    conn.execute("DETACH DATABASE encrypted", params![]).unwrap();
+
+    if let Err((_, e)) = conn.close() {
+        panic!("Error closing {}: {}", filename, e);
+    }
+
+    fs::rename(&encrypted, filename).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::adapters::repository::{Repository, SQLiteRepository};
+    use crate::domain::ids::RandomIds;
+    use crate::domain::models;
+
+    #[test]
+    fn can_encrypt_and_rekey_a_plain_database() {
+        // Given
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("budgets.db").to_string_lossy().to_string();
+
+        let bm = models::BudgetManager::new(
+            models::Budget::new(&RandomIds, String::from("my-budget"), 200.00_f64),
+            RefCell::new(vec![]),
+        );
+        {
+            let repo = SQLiteRepository::new(filename.clone());
+            repo.create_tables();
+            repo.add(&bm).unwrap();
+        }
+
+        // When
+        rekey(&filename, None, "first");
+        let encrypted = is_encrypted(&filename);
+        rekey(&filename, Some("first"), "second");
+
+        // Then
+        assert!(encrypted);
+        assert!(open(&filename, Some("first")).is_err());
+
+        let repo = SQLiteRepository::encrypted(filename, String::from("second"));
+        assert_eq!(repo.get(bm.id()).budget(), bm.budget());
+    }
+}
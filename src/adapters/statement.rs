@@ -0,0 +1,102 @@
+//! What the readers of bank statements share: the rows they give back, how they report a file they can't read
+//! and how they tidy up what's in it
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::{error, fmt};
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+/// A row of the file that spent money
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BankRow {
+    /// Line in the file, counting the header
+    pub line: u64,
+    pub date: NaiveDate,
+    pub description: String,
+    /// Always positive
+    pub amount: f64,
+    /// Id the bank gave the transaction, CSV files don't have one so it's made from the row, see `row_id`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Statement {
+    pub spending: Vec<BankRow>,
+    /// Lines where money came in or nothing moved, e.g., salary and refunds
+    pub skipped: Vec<u64>,
+}
+
+/// The file isn't what its reader expects, or one of its rows can't be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementError {
+    /// `None` when the problem isn't with a single row, e.g., a missing column
+    pub line: Option<u64>,
+    pub message: String,
+}
+
+impl StatementError {
+    pub fn new(line: Option<u64>, message: impl Into<String>) -> Self {
+        StatementError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for StatementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl error::Error for StatementError {}
+
+/// The text of a statement file
+pub fn text(contents: &[u8]) -> Cow<'_, str> {
+    // Older statements and QIF files are often in a Windows code page, the odd character in a name
+    // is not worth failing for
+    String::from_utf8_lossy(contents)
+}
+
+/// A description with the runs of spaces and line breaks banks pad them with collapsed into single spaces
+pub fn normalize_description(description: &str) -> String {
+    description.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// An id for a row made from what it says rather than where it is, so the same row in an overlapping export
+/// gets the same id and is only imported once, `occurrence` counts the rows alike before it in the file
+pub fn row_id(date: NaiveDate, description: &str, amount: f64, occurrence: u32) -> String {
+    let row = format!("{}|{}|{:.2}|{}", date, description, amount, occurrence);
+
+    // FNV-1a, std's hasher may change between Rust versions and the ids are kept in the database
+    let hash = row
+        .bytes()
+        .fold(0xcbf29ce484222325_u64, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3));
+
+    format!("row-{:016x}", hash)
+}
+
+/// Hands out a `row_id` for each row of a file, counting the rows alike so far
+#[derive(Debug, Default)]
+pub struct RowIds {
+    seen: HashMap<(NaiveDate, String, String), u32>,
+}
+
+impl RowIds {
+    pub fn next(&mut self, date: NaiveDate, description: &str, amount: f64) -> String {
+        // A second identical coffee on the same day gets an id of its own
+        let occurrence = self
+            .seen
+            .entry((date, description.to_string(), format!("{:.2}", amount)))
+            .and_modify(|count| *count += 1)
+            .or_insert(0);
+
+        row_id(date, description, amount, *occurrence)
+    }
+}
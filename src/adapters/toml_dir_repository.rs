@@ -0,0 +1,251 @@
+//! Keeps everything as TOML files in a directory, meant to be committed to git so changes can be diffed and reviewed
+//! Each budget has a file of its own under `budgets/`, named after its id, with its transactions sorted by date.
+//! `budgets.toml` lists the budgets in the order they were added, payees, accounts and income have a file each.
+//! Fields are always written in the same order and untouched files aren't rewritten, so a change only shows up
+//! in the files it's about
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::adapters::file_repository::{self, FileRepository, SnapshotStore};
+use crate::domain::models::{self, Snapshot};
+
+pub type TomlDirRepository = FileRepository<TomlDir>;
+
+impl TomlDirRepository {
+    /// The directory is created with the first change, until then the repository is empty
+    pub fn new(dirname: String) -> Self {
+        FileRepository::with_store(TomlDir {
+            dir: PathBuf::from(dirname),
+        })
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BudgetIndex {
+    #[serde(default)]
+    budgets: Vec<models::BudgetId>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PayeeFile {
+    #[serde(default)]
+    payees: Vec<models::Payee>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AccountFile {
+    #[serde(default)]
+    accounts: Vec<models::Account>,
+}
+
+/// One TOML file per budget plus one for each of the other kinds of data
+pub struct TomlDir {
+    dir: PathBuf,
+}
+
+impl TomlDir {
+    fn budgets_dir(&self) -> PathBuf {
+        self.dir.join("budgets")
+    }
+
+    fn budget_path(&self, id: &models::BudgetId) -> PathBuf {
+        self.budgets_dir().join(format!("{}.toml", id))
+    }
+
+    /// Ids of the budget files on disk, in file name order
+    fn budget_files(&self) -> Vec<models::BudgetId> {
+        let entries = match fs::read_dir(self.budgets_dir()) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+
+        let mut ids: Vec<models::BudgetId> = entries
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .filter_map(|path| path.file_stem()?.to_str()?.parse().ok())
+            .collect();
+        ids.sort();
+
+        ids
+    }
+
+    fn read<T: DeserializeOwned + Default>(&self, path: &Path) -> T {
+        match file_repository::read_file(path) {
+            Some(contents) => match toml::from_str(&contents) {
+                Ok(value) => value,
+                Err(e) => panic!("Error reading {}: {}", path.display(), e),
+            },
+            None => T::default(),
+        }
+    }
+
+    fn write<T: Serialize>(&self, path: &Path, value: &T) {
+        file_repository::write_file(path, &toml::to_string(value).unwrap());
+    }
+}
+
+impl SnapshotStore for TomlDir {
+    fn lock_path(&self) -> PathBuf {
+        let mut path = self.dir.clone().into_os_string();
+        path.push(".lock");
+
+        PathBuf::from(path)
+    }
+
+    fn load(&self) -> Snapshot {
+        let index: BudgetIndex = self.read(&self.dir.join("budgets.toml"));
+
+        // Budget files that aren't in the index yet, e.g., added in a merge, come after the indexed ones
+        let indexed: HashSet<models::BudgetId> = index.budgets.iter().copied().collect();
+        let unindexed = self.budget_files().into_iter().filter(|id| !indexed.contains(id));
+
+        let budgets = index
+            .budgets
+            .into_iter()
+            .chain(unindexed)
+            .filter_map(|id| {
+                let path = self.budget_path(&id);
+                let contents = file_repository::read_file(&path)?;
+
+                match toml::from_str(&contents) {
+                    Ok(budget_manager) => Some(budget_manager),
+                    Err(e) => panic!("Error reading {}: {}", path.display(), e),
+                }
+            })
+            .collect();
+
+        let payees: PayeeFile = self.read(&self.dir.join("payees.toml"));
+        let accounts: AccountFile = self.read(&self.dir.join("accounts.toml"));
+
+        Snapshot {
+            budgets,
+            payees: payees.payees,
+            accounts: accounts.accounts,
+            income_pool: self.read(&self.dir.join("income.toml")),
+        }
+    }
+
+    fn save(&self, snapshot: &Snapshot) {
+        if let Err(e) = fs::create_dir_all(self.budgets_dir()) {
+            panic!("Error creating {}: {}", self.budgets_dir().display(), e);
+        }
+
+        for budget_manager in &snapshot.budgets {
+            // Stable, so transactions on the same day keep the order they were added in
+            let sorted = budget_manager.clone();
+            sorted.transactions().borrow_mut().sort_by_key(|tx| tx.date());
+
+            self.write(&self.budget_path(budget_manager.id()), &sorted);
+        }
+
+        let ids: HashSet<models::BudgetId> = snapshot.budgets.iter().map(|bm| *bm.id()).collect();
+        for id in self.budget_files() {
+            if !ids.contains(&id) {
+                fs::remove_file(self.budget_path(&id)).unwrap();
+            }
+        }
+
+        let index = BudgetIndex {
+            budgets: snapshot.budgets.iter().map(|bm| *bm.id()).collect(),
+        };
+        self.write(&self.dir.join("budgets.toml"), &index);
+
+        let payees = PayeeFile {
+            payees: snapshot.payees.clone(),
+        };
+        self.write(&self.dir.join("payees.toml"), &payees);
+
+        let accounts = AccountFile {
+            accounts: snapshot.accounts.clone(),
+        };
+        self.write(&self.dir.join("accounts.toml"), &accounts);
+
+        self.write(&self.dir.join("income.toml"), &snapshot.income_pool);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::fs;
+
+    use chrono::NaiveDate;
+    use tempfile::TempDir;
+
+    use super::TomlDirRepository;
+    use crate::adapters::conformance::conformance_tests;
+    use crate::adapters::repository::Repository;
+    use crate::domain::models;
+    use crate::domain::clock::{Clock, SystemClock};
+    use crate::domain::ids::RandomIds;
+
+    fn temp_repo() -> (TomlDirRepository, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = TomlDirRepository::new(dir.path().join("budgets").to_string_lossy().to_string());
+
+        (repo, dir)
+    }
+
+    conformance_tests!(temp_repo());
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 9, day).unwrap()
+    }
+
+    #[test]
+    fn writes_one_file_per_budget_with_sorted_transactions() {
+        // Given
+        let (repo, dir) = temp_repo();
+        let budget = models::Budget::new(&RandomIds, String::from("Groceries"), 200.00_f64);
+        let id = *budget.id();
+        let txs = vec![
+            models::Transaction::load(models::TransactionId::new(), String::from("late"), 1.00_f64, id, date(20)),
+            models::Transaction::load(models::TransactionId::new(), String::from("early"), 1.00_f64, id, date(3)),
+        ];
+        let bm = models::BudgetManager::new(budget, RefCell::new(txs));
+
+        // When
+        repo.add(&bm).unwrap();
+
+        // Then
+        let path = dir.path().join("budgets").join("budgets").join(format!("{}.toml", id));
+        let contents = fs::read_to_string(path).unwrap();
+
+        assert!(contents.find("early").unwrap() < contents.find("late").unwrap());
+        assert!(contents.contains("version = 1"));
+        assert!(contents.contains("name = \"Groceries\""));
+    }
+
+    #[test]
+    fn leaves_untouched_budgets_alone() {
+        // Given
+        let (repo, dir) = temp_repo();
+        let first = models::BudgetManager::new(models::Budget::new(&RandomIds, String::from("first"), 10.00_f64), RefCell::new(vec![]));
+        let mut second =
+            models::BudgetManager::new(models::Budget::new(&RandomIds, String::from("second"), 10.00_f64), RefCell::new(vec![]));
+        repo.add(&first).unwrap();
+        repo.add(&second).unwrap();
+
+        let first_path = dir.path().join("budgets").join("budgets").join(format!("{}.toml", first.id()));
+        let modified = fs::metadata(&first_path).unwrap().modified().unwrap();
+
+        // When
+        second.add_tx(&RandomIds, String::from("apples"), 3.50_f64, SystemClock.today());
+        repo.add(&second).unwrap();
+        repo.delete(second.id());
+
+        // Then
+        assert_eq!(fs::metadata(&first_path).unwrap().modified().unwrap(), modified);
+        assert!(!dir
+            .path()
+            .join("budgets")
+            .join("budgets")
+            .join(format!("{}.toml", second.id()))
+            .exists());
+    }
+}
@@ -1,18 +1,124 @@
-use clap::{Args, Parser, Subcommand};
+use chrono::NaiveDate;
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+
+use crate::adapters::{bank_csv, csv_export, ledger};
+use crate::domain::models;
+use crate::services::{handlers, validation};
 
 
 #[derive(Debug, Parser)]
 #[command(name = "budget")]
 #[command(about = "Simple CLI app for creating a budget", long_about = None)]
 pub struct Cli {
+    /// Keep everything in memory instead of budgets.db, without a command reads one command per line from stdin
+    #[arg(long)]
+    pub ephemeral: bool,
+
+    /// Keep everything in this JSON file instead of budgets.db, it's created with the first change
+    #[arg(long, value_name = "FILE", conflicts_with = "ephemeral")]
+    pub json: Option<String>,
+
+    /// Keep everything as TOML files in this directory instead of budgets.db, e.g., to commit them to git
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["ephemeral", "json"])]
+    pub toml_dir: Option<String>,
+
+    /// Read the passphrase of an encrypted budgets.db from this file instead of BUDGET_PASSPHRASE or a prompt
+    #[cfg(feature = "sqlcipher")]
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["ephemeral", "json", "toml_dir"])]
+    pub keyfile: Option<String>,
+
     #[command(subcommand)]
-    pub commands: Commands
+    pub commands: Option<Commands>
 }
 
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     Budgets(BudgetArgs),
-    Transactions(TransactionArgs)
+    Transactions(TransactionArgs),
+    Payees(PayeeArgs),
+    Accounts(AccountArgs),
+    Income(IncomeArgs),
+    /// Write everything into one versioned document, or the transactions as QIF or a Ledger journal
+    Export(ExportArgs),
+    /// Load everything from an export, or the spending in a QIF file, next to what's already stored
+    Import(ImportArgs),
+    /// Copy budgets.db into a directory as a timestamped backup, the oldest backups past --keep are removed
+    Backup(BackupArgs),
+    /// Replace everything in budgets.db with a backup
+    Restore(RestoreArgs),
+    /// Change the passphrase of budgets.db, encrypts it when it's still plain
+    #[cfg(feature = "sqlcipher")]
+    Rekey(RekeyArgs)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    /// Transactions only, as spending in one bank account with a category per budget
+    Qif,
+    /// A Ledger or hledger journal with an expense account per budget
+    Ledger,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[arg(short, long, value_enum, default_value_t = ExportFormat::Json)]
+    pub format: ExportFormat,
+
+    /// File to write the export to instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Ledger only: account paying for transactions without one, e.g., assets:bank
+    #[arg(long, default_value = "assets:checking")]
+    pub funding_account: String,
+
+    /// Ledger only: also write what each budget was given as a periodic transaction: daily, weekly, monthly, quarterly or yearly
+    #[arg(long, value_name = "PERIOD")]
+    pub budget_period: Option<ledger::BudgetPeriod>
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// Export to load
+    pub path: String,
+
+    /// Format of the file, guessed from its extension when not given: .qif is QIF, anything else JSON
+    #[arg(short, long, value_enum)]
+    pub format: Option<ExportFormat>,
+
+    /// QIF dates are written day first, e.g., 31/12/2023
+    #[arg(long)]
+    pub day_first: bool,
+
+    /// What to do with a budget, income or assignment whose id is taken: skip, replace or new-ids
+    #[arg(long, default_value = "skip")]
+    pub on_conflict: handlers::OnConflict
+}
+
+#[derive(Debug, Args)]
+pub struct BackupArgs {
+    /// Directory the backups are kept in, created when it doesn't exist
+    pub path: String,
+
+    /// How many backups to keep
+    #[arg(short, long, default_value_t = 7, value_parser = clap::value_parser!(u32).range(1..))]
+    pub keep: u32
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreArgs {
+    /// Backup file to restore
+    pub path: String
+}
+
+#[cfg(feature = "sqlcipher")]
+#[derive(Debug, Args)]
+pub struct RekeyArgs {
+    /// Read the new passphrase from this file instead of BUDGET_NEW_PASSPHRASE or a prompt
+    #[arg(long, value_name = "FILE")]
+    pub new_keyfile: Option<String>
 }
 
 #[derive(Debug, Args)]
@@ -25,16 +131,76 @@ pub struct BudgetArgs {
 #[derive(Debug, Subcommand)]
 pub enum BudgetCommands {
     Add {
-        #[arg(short, long)]
+        #[arg(short, long, value_parser = parse_name)]
         name: String,
-        #[arg(short, long)]
+        #[arg(short, long, value_parser = parse_total)]
         total: f64
     },
-    List,
+    List {
+        /// Show each budget's spending pace for the month next to it
+        #[arg(long)]
+        forecast: bool
+    },
     Delete {
         #[arg(short, long)]
-        id: String
+        id: models::BudgetId
     },
+    /// Project spending for the rest of the month at the current pace
+    Forecast {
+        #[arg(short, long)]
+        id: models::BudgetId
+    },
+    /// Set the people sharing the budget
+    Members {
+        #[arg(short, long)]
+        id: models::BudgetId,
+
+        #[arg(short, long, value_parser = parse_name)]
+        member: Vec<String>
+    },
+    /// Show who owes whom in a shared budget
+    Settle {
+        #[arg(short, long)]
+        id: models::BudgetId
+    },
+    /// Warn when spending passes a percentage of the budget's allocation
+    Alerts {
+        #[arg(short, long)]
+        id: models::BudgetId,
+
+        /// Percentage of the allocation, e.g., 50, 80 or 100
+        #[arg(short, long, value_parser = parse_percent)]
+        threshold: Vec<u32>
+    },
+    /// Write a summary of every budget as a table, e.g., to open in a spreadsheet
+    Export(BudgetExportArgs),
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum TableFormat {
+    Csv,
+}
+
+#[derive(Debug, Args)]
+pub struct BudgetExportArgs {
+    #[arg(short, long, value_enum, default_value_t = TableFormat::Csv)]
+    pub format: TableFormat,
+
+    /// Columns to write, separated by commas, out of id, name, total, spent and transactions. All of them by default
+    #[arg(short, long, value_delimiter = ',')]
+    pub columns: Vec<csv_export::BudgetColumn>,
+
+    /// Count spending from this date on, as YYYY-MM-DD
+    #[arg(long)]
+    pub from: Option<NaiveDate>,
+
+    /// Count spending up to and including this date, as YYYY-MM-DD
+    #[arg(long)]
+    pub to: Option<NaiveDate>,
+
+    /// File to write the table to instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<String>
 }
 
 #[derive(Debug, Args)]
@@ -48,37 +214,357 @@ pub struct TransactionArgs {
 pub enum TransactionCommands {
     Add {
         #[arg(short, long)]
-        budget_id: String,
+        budget_id: models::BudgetId,
 
-        #[arg(short, long)]
+        #[arg(short, long, value_parser = parse_name)]
         name: String,
 
+        #[arg(short, long, value_parser = parse_amount)]
+        amount: f64,
+
+        /// Date of the transaction as YYYY-MM-DD, defaults to today
         #[arg(short, long)]
-        amount: f64
+        date: Option<NaiveDate>,
+
+        /// Id of the account the transaction was paid from
+        #[arg(long)]
+        account: Option<String>,
+
+        /// Member of a shared budget who paid
+        #[arg(short, long, value_parser = parse_name)]
+        paid_by: Option<String>,
+
+        /// How to split the cost: equal, percent:ana=60,ben=40 or exact:ana=10,ben=5
+        #[arg(short, long, value_parser = parse_split)]
+        split: Option<models::Split>
     },
     List {
         #[arg(short, long)]
-        budget_id: String,
+        budget_id: models::BudgetId,
     },
-    Update {
+    /// Add the spending in a bank's CSV, OFX or QFX export, all rows or none
+    Import(StatementImportArgs),
+    /// Write transactions as a table, e.g., to open in a spreadsheet
+    Export(TransactionExportArgs),
+    Status {
         #[arg(short, long)]
-        budget_id: String,
+        budget_id: models::BudgetId,
 
         #[arg(short, long)]
-        id: String,
+        id: models::TransactionId,
 
+        /// pending, cleared or reconciled
+        #[arg(short, long)]
+        status: models::TransactionStatus
+    },
+    Update {
         #[arg(short, long)]
+        budget_id: models::BudgetId,
+
+        #[arg(short, long)]
+        id: models::TransactionId,
+
+        #[arg(short, long, value_parser = parse_amount)]
         amount: f64
     },
     Remove {
         #[arg(short, long)]
-        budget_id: String,
+        budget_id: models::BudgetId,
 
+        #[arg(short, long)]
+        id: models::TransactionId,
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct TransactionExportArgs {
+    /// Only the transactions of this budget, every budget's by default
+    #[arg(short, long)]
+    pub budget_id: Option<models::BudgetId>,
+
+    #[arg(short, long, value_enum, default_value_t = TableFormat::Csv)]
+    pub format: TableFormat,
+
+    /// Columns to write, separated by commas, out of id, date, budget, name, amount, status, payee and account.
+    /// All of them by default
+    #[arg(short, long, value_delimiter = ',')]
+    pub columns: Vec<csv_export::TransactionColumn>,
+
+    /// Only transactions from this date on, as YYYY-MM-DD
+    #[arg(long)]
+    pub from: Option<NaiveDate>,
+
+    /// Only transactions up to and including this date, as YYYY-MM-DD
+    #[arg(long)]
+    pub to: Option<NaiveDate>,
+
+    /// File to write the table to instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<String>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StatementFormat {
+    Csv,
+    /// OFX 1.x and 2.x, QFX is OFX too
+    Ofx,
+}
+
+#[derive(Debug, Args)]
+pub struct StatementImportArgs {
+    #[arg(short, long)]
+    pub budget_id: models::BudgetId,
+
+    #[arg(short, long, value_name = "FILE")]
+    pub file: String,
+
+    /// Format of the file, guessed from its extension when not given: .ofx and .qfx are OFX, anything else CSV
+    #[arg(long, value_enum)]
+    pub format: Option<StatementFormat>,
+
+    /// Read the file with a saved mapping, the options below override parts of it
+    #[arg(long)]
+    pub preset: Option<String>,
+
+    /// Header or position of the date column, defaults to Date
+    #[arg(long)]
+    pub date_column: Option<String>,
+
+    /// Header or position of the description column, defaults to Description
+    #[arg(long)]
+    pub description_column: Option<String>,
+
+    /// Header or position of the amount column, defaults to Amount
+    #[arg(long)]
+    pub amount_column: Option<String>,
+
+    /// Sign of money leaving the account: negative (the default) or positive
+    #[arg(long)]
+    pub spending_sign: Option<bank_csv::SpendingSign>,
+
+    /// Format of the dates, e.g., %d/%m/%Y, defaults to %Y-%m-%d
+    #[arg(long)]
+    pub date_format: Option<String>,
+
+    /// Character between columns, defaults to a comma
+    #[arg(long)]
+    pub delimiter: Option<char>,
+
+    /// Amounts are written like 1.234,56, --decimal-comma=false reads 1,234.56 even when the preset says otherwise
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub decimal_comma: Option<bool>,
+
+    /// Save the mapping under this name for next time
+    #[arg(long, value_parser = parse_name)]
+    pub save_preset: Option<String>,
+
+    /// Id of the account the transactions were paid from
+    #[arg(long)]
+    pub account: Option<String>,
+
+    /// Show what would be added without adding anything
+    #[arg(long)]
+    pub dry_run: bool
+}
+
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct PayeeArgs {
+    #[command(subcommand)]
+    pub commands: PayeeCommands
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PayeeCommands {
+    Add {
+        #[arg(short, long, value_parser = parse_name)]
+        name: String,
+
+        #[arg(short, long, value_parser = parse_name)]
+        alias: Vec<String>
+    },
+    Alias {
         #[arg(short, long)]
         id: String,
+
+        #[arg(short, long, value_parser = parse_name)]
+        alias: String
+    },
+    List,
+    Totals,
+}
+
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct AccountArgs {
+    #[command(subcommand)]
+    pub commands: AccountCommands
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AccountCommands {
+    Add {
+        #[arg(short, long, value_parser = parse_name)]
+        name: String,
+
+        /// checking, savings, credit or cash
+        #[arg(short, long)]
+        kind: models::AccountKind,
+
+        #[arg(short, long, default_value_t = 0_f64, value_parser = parse_balance)]
+        opening_balance: f64
+    },
+    List,
+    Reconcile {
+        #[arg(short, long)]
+        account: String,
+
+        #[arg(short, long, value_parser = parse_balance)]
+        statement_balance: f64
+    },
+}
+
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct IncomeArgs {
+    #[command(subcommand)]
+    pub commands: IncomeCommands
+}
+
+#[derive(Debug, Subcommand)]
+pub enum IncomeCommands {
+    Add {
+        #[arg(short, long, value_parser = parse_name)]
+        source: String,
+
+        #[arg(short, long, value_parser = parse_amount)]
+        amount: f64
+    },
+    List,
+    /// Move unassigned income into a budget
+    Assign {
+        #[arg(short, long)]
+        budget_id: models::BudgetId,
+
+        #[arg(short, long, value_parser = parse_amount)]
+        amount: f64
+    },
+    /// Show how much income has not been assigned to a budget yet
+    Ready,
+}
+
+// Value parsers reuse the handlers' validation, clap adds the argument name to the message
+
+fn parse_number(s: &str) -> Result<f64, String> {
+    s.parse().map_err(|_| format!("{} is not a number", s))
+}
+
+fn parse_name(s: &str) -> Result<String, String> {
+    validation::name("name", s).map_err(|e| e.message)?;
+
+    Ok(s.to_string())
+}
+
+fn parse_total(s: &str) -> Result<f64, String> {
+    let total = parse_number(s)?;
+    validation::non_negative("total", total).map_err(|e| e.message)?;
+
+    Ok(total)
+}
+
+fn parse_amount(s: &str) -> Result<f64, String> {
+    let amount = parse_number(s)?;
+    validation::positive("amount", amount).map_err(|e| e.message)?;
+
+    Ok(amount)
+}
+
+fn parse_balance(s: &str) -> Result<f64, String> {
+    let balance = parse_number(s)?;
+    validation::finite("balance", balance).map_err(|e| e.message)?;
+
+    Ok(balance)
+}
+
+fn parse_percent(s: &str) -> Result<u32, String> {
+    let percent: u32 = s.parse().map_err(|_| format!("{} is not a whole number", s))?;
+    validation::percent("threshold", percent).map_err(|e| e.message)?;
+
+    Ok(percent)
+}
+
+fn parse_split(s: &str) -> Result<models::Split, String> {
+    let (method, parts) = s.split_once(':').unwrap_or((s, ""));
+
+    let parse_parts = || -> Result<Vec<(String, f64)>, String> {
+        parts
+            .split(',')
+            .map(|part| {
+                let (member, amount) = part
+                    .split_once('=')
+                    .ok_or(format!("expected member=amount, got {}", part))?;
+                let amount = parse_number(amount)?;
+                validation::finite("split", amount).map_err(|e| e.message)?;
+
+                Ok((member.trim().to_string(), amount))
+            })
+            .collect()
+    };
+
+    match method {
+        "equal" => Ok(models::Split::Equal),
+        "percent" => Ok(models::Split::Percentage(parse_parts()?)),
+        "exact" => Ok(models::Split::Exact(parse_parts()?)),
+        _ => Err(format!("unknown split {}, expected equal, percent or exact", method)),
     }
 }
 
 pub fn parse_args() -> Cli {
     Cli::parse()
 }
+
+/// Parses one line of an ephemeral session, arguments are split like a shell would, quotes included
+pub fn parse_line(line: &str) -> Result<Cli, clap::Error> {
+    match shlex::split(line) {
+        Some(args) => Cli::try_parse_from(std::iter::once(String::from("budget")).chain(args)),
+        None => Err(Cli::command().error(ErrorKind::InvalidValue, "unterminated quote or trailing backslash")),
+    }
+}
+
+/// Exits like clap does when a command is missing
+pub fn missing_command() -> ! {
+    Cli::command()
+        .error(ErrorKind::MissingSubcommand, "a command is required unless running --ephemeral")
+        .exit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_line_keeps_quoted_arguments_together() {
+        // When
+        let cli = parse_line(r#"payees add -n "Corner Grocer" -a 'corner grocer #12'"#).unwrap();
+
+        // Then
+        match cli.commands {
+            Some(Commands::Payees(PayeeArgs {
+                commands: PayeeCommands::Add { name, alias },
+            })) => {
+                assert_eq!(name, "Corner Grocer");
+                assert_eq!(alias, vec![String::from("corner grocer #12")]);
+            }
+            commands => panic!("unexpected commands {:?}", commands),
+        }
+    }
+
+    #[test]
+    fn session_line_with_unterminated_quote_is_an_error() {
+        // When
+        let result = parse_line(r#"payees add -n "Corner Grocer"#);
+
+        // Then
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidValue);
+    }
+}
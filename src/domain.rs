@@ -1 +1,3 @@
+pub mod clock;
+pub mod ids;
 pub mod models;
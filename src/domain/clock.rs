@@ -0,0 +1,34 @@
+use chrono::{Local, NaiveDate};
+
+/// Tells handlers what day it is, so callers decide whether that's the real date or a fixed one
+pub trait Clock: Send + Sync {
+    fn today(&self) -> NaiveDate;
+}
+
+/// The local date, what the app uses outside of tests and replays
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn today(&self) -> NaiveDate {
+        Local::now().date_naive()
+    }
+}
+
+/// Always the same day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedClock {
+    today: NaiveDate,
+}
+
+impl FixedClock {
+    pub fn new(today: NaiveDate) -> Self {
+        FixedClock { today }
+    }
+}
+
+impl Clock for FixedClock {
+    fn today(&self) -> NaiveDate {
+        self.today
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    error, fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// An id string that isn't a UUID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseIdError {
+    kind: &'static str,
+    value: String,
+}
+
+impl fmt::Display for ParseIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is not a valid {} id", self.value, self.kind)
+    }
+}
+
+impl error::Error for ParseIdError {}
+
+/// Declares a UUID backed id that serializes as a plain string
+macro_rules! uuid_id {
+    ($(#[$meta:meta])* $name:ident, $kind:literal) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(Uuid);
+
+        impl $name {
+            pub fn new() -> Self {
+                $name(Uuid::new_v4())
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                $name::new()
+            }
+        }
+
+        impl From<Uuid> for $name {
+            fn from(uuid: Uuid) -> Self {
+                $name(uuid)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.0.hyphenated())
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseIdError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Uuid::parse_str(s.trim()).map($name).map_err(|_| ParseIdError {
+                    kind: $kind,
+                    value: s.to_string(),
+                })
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                other.parse::<$name>().map(|id| id == *self).unwrap_or(false)
+            }
+        }
+    };
+}
+
+uuid_id!(
+    /// Identifies a budget, and the `BudgetManager` aggregate built around it
+    BudgetId,
+    "budget"
+);
+
+uuid_id!(
+    /// Identifies a transaction within a budget
+    TransactionId,
+    "transaction"
+);
+
+/// Source of new ids, handlers take one so callers decide whether ids are random or reproducible
+pub trait IdGenerator: Send + Sync {
+    fn next_uuid(&self) -> Uuid;
+
+    fn budget_id(&self) -> BudgetId {
+        BudgetId::from(self.next_uuid())
+    }
+
+    fn transaction_id(&self) -> TransactionId {
+        TransactionId::from(self.next_uuid())
+    }
+
+    /// Id for entities that still use plain string ids, e.g., payees and accounts
+    fn next_id(&self) -> String {
+        self.next_uuid().to_string()
+    }
+}
+
+/// Random v4 UUIDs, what the app uses outside of tests and replays
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomIds;
+
+impl IdGenerator for RandomIds {
+    fn next_uuid(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// Counts up from a starting number, e.g., 00000000-0000-0000-0000-000000000001,
+/// so the same commands always produce the same ids
+#[derive(Debug)]
+pub struct SequentialIds {
+    next: AtomicU64,
+}
+
+impl SequentialIds {
+    pub fn new() -> Self {
+        SequentialIds::starting_at(1)
+    }
+
+    pub fn starting_at(start: u64) -> Self {
+        SequentialIds {
+            next: AtomicU64::new(start),
+        }
+    }
+}
+
+impl Default for SequentialIds {
+    fn default() -> Self {
+        SequentialIds::new()
+    }
+}
+
+impl IdGenerator for SequentialIds {
+    fn next_uuid(&self) -> Uuid {
+        Uuid::from_u128(u128::from(self.next.fetch_add(1, Ordering::Relaxed)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_parse_from_uuid_strings() {
+        let id: BudgetId = "576bc364-7574-40ce-92ca-f488c613b7ea".parse().unwrap();
+
+        assert_eq!(id.to_string(), "576bc364-7574-40ce-92ca-f488c613b7ea");
+        assert!(id == *"576bc364-7574-40ce-92ca-f488c613b7ea");
+    }
+
+    #[test]
+    fn ids_reject_anything_else() {
+        let err = "abc123".parse::<TransactionId>().unwrap_err();
+
+        assert_eq!(err.to_string(), "abc123 is not a valid transaction id");
+    }
+
+    #[test]
+    fn ids_serialize_as_strings() {
+        let id: TransactionId = "621dba84-399b-4846-8a9e-76a2d1692683".parse().unwrap();
+
+        let json = serde_json::to_string(&id).unwrap();
+
+        assert_eq!(json, "\"621dba84-399b-4846-8a9e-76a2d1692683\"");
+        assert_eq!(serde_json::from_str::<TransactionId>(&json).unwrap(), id);
+    }
+
+    #[test]
+    fn sequential_ids_are_reproducible() {
+        // Given
+        let first = SequentialIds::new();
+        let second = SequentialIds::new();
+
+        // When
+        let ids = [first.budget_id(), first.budget_id()];
+
+        // Then
+        assert_eq!(ids[0].to_string(), "00000000-0000-0000-0000-000000000001");
+        assert_eq!(ids[1].to_string(), "00000000-0000-0000-0000-000000000002");
+        assert_eq!(second.budget_id(), ids[0]);
+        assert_eq!(SequentialIds::starting_at(7).next_id(), "00000000-0000-0000-0000-000000000007");
+    }
+}
@@ -1,29 +1,67 @@
 // Temporary while developing to reduce noise
 #![allow(dead_code)]
 
-use uuid::Uuid;
+use chrono::{Datelike, NaiveDate};
 use serde::{Serialize, Deserialize};
 
-use std::{cell::RefCell, collections::HashMap};
+pub use super::ids::{BudgetId, TransactionId};
+use super::ids::IdGenerator;
+
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    str::FromStr,
+};
 
 #[derive(Debug)]
 struct User {
     id: String,
-    budgets: HashMap<String, BudgetManager>,
+    budgets: HashMap<BudgetId, BudgetManager>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BudgetManager {
     budget: Budget,
     transactions: RefCell<Vec<Transaction>>,
+    #[serde(default)]
+    alerts: Vec<AlertThreshold>,
+    /// How many times the budget has been saved, a save fails when someone else saved it first
+    #[serde(default)]
+    version: Cell<u64>,
 }
 
 impl BudgetManager {
     pub fn new(budget: Budget, transactions: RefCell<Vec<Transaction>>) -> Self {
         BudgetManager {
-            transactions: transactions,
+            transactions,
             budget,
+            alerts: vec![],
+            version: Cell::new(0),
+        }
+    }
+
+    pub fn with_alerts(mut self, alerts: Vec<AlertThreshold>) -> Self {
+        self.alerts = alerts;
+        self
+    }
+
+    pub fn with_version(self, version: u64) -> Self {
+        self.version.set(version);
+        self
+    }
+
+    /// The same budget and transactions under new ids, e.g., to import a copy next to the original
+    pub fn with_new_ids(mut self, ids: &dyn IdGenerator) -> Self {
+        self.budget.id = ids.budget_id();
+
+        for tx in self.transactions.get_mut() {
+            tx.id = ids.transaction_id();
+            tx.budget_id = self.budget.id;
         }
+
+        self
     }
 
     pub fn available_funds(&self) -> f64 {
@@ -34,7 +72,7 @@ impl BudgetManager {
         &self.budget.name
     }
 
-    pub fn id(&self) -> &str {
+    pub fn id(&self) -> &BudgetId {
         &self.budget.id
     }
 
@@ -46,9 +84,140 @@ impl BudgetManager {
         &self.transactions
     }
 
-    pub fn add_tx(&mut self, name: String, value: f64) -> String {
-        let tx = Transaction::new(name, value, String::from(self.id()));
-        let txc = tx.clone().id;
+    pub fn alerts(&self) -> &[AlertThreshold] {
+        &self.alerts
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.get()
+    }
+
+    /// Called by repositories once a save went through
+    pub fn set_version(&self, version: u64) {
+        self.version.set(version);
+    }
+
+    /// Replaces the alert thresholds, thresholds that are kept remember when they last fired
+    pub fn set_alert_thresholds(&mut self, mut percents: Vec<u32>) {
+        percents.sort_unstable();
+        percents.dedup();
+
+        self.alerts = percents
+            .into_iter()
+            .map(|percent| {
+                self.alerts
+                    .iter()
+                    .find(|alert| alert.percent == percent)
+                    .cloned()
+                    .unwrap_or_else(|| AlertThreshold::new(percent))
+            })
+            .collect();
+    }
+
+    pub fn spent_in(&self, period: &Period) -> f64 {
+        round_cents(
+            self.transactions
+                .borrow()
+                .iter()
+                .filter(|tx| period.contains(tx.date))
+                .map(|tx| tx.value)
+                .sum(),
+        )
+    }
+
+    /// Projects spending for the rest of the period containing `today` at the pace so far
+    pub fn forecast(&self, today: NaiveDate) -> Forecast {
+        let period = Period::month_of(today);
+        let elapsed = Period {
+            start: period.start,
+            end: today,
+        };
+
+        let spent = self.spent_in(&elapsed);
+        let available = round_cents(self.available_funds());
+        let days_elapsed = (today - period.start).num_days() + 1;
+        let days_remaining = (period.end - today).num_days();
+
+        // Only rounded for display, a rounded rate would be off by up to half a cent for every day left
+        let daily_burn_rate = spent / days_elapsed as f64;
+        let projected_end_balance = round_cents(available - daily_burn_rate * days_remaining as f64);
+
+        let runs_out_on = if daily_burn_rate > 0_f64 {
+            let days_left = (available / daily_burn_rate).ceil().max(0_f64) as u64;
+
+            today
+                .checked_add_days(chrono::Days::new(days_left))
+                .filter(|date| *date <= period.end)
+        } else {
+            None
+        };
+
+        Forecast {
+            budget_id: self.budget.id,
+            period_start: period.start,
+            period_end: period.end,
+            spent,
+            available,
+            daily_burn_rate: round_cents(daily_burn_rate),
+            projected_end_balance,
+            runs_out_on,
+        }
+    }
+
+    /// Fires every threshold crossed by spending in the period containing `today`
+    ///
+    /// The allocation for the period is what was available at its start,
+    /// i.e., the available funds plus whatever has been spent during the period.
+    /// Each threshold fires at most once per period.
+    pub fn check_alerts(&mut self, today: NaiveDate) -> Vec<Alert> {
+        let period = Period::month_of(today);
+        let spent = self.spent_in(&period);
+        let allocated = round_cents(self.available_funds() + spent);
+
+        if allocated <= 0_f64 {
+            return vec![];
+        }
+
+        let percent_used = round_cents(spent / allocated * 100_f64);
+        let mut fired = vec![];
+
+        for alert in self.alerts.iter_mut() {
+            if percent_used >= alert.percent as f64 && alert.last_fired != Some(period.start) {
+                alert.last_fired = Some(period.start);
+
+                fired.push(Alert {
+                    budget_id: self.budget.id,
+                    budget_name: self.budget.name.clone(),
+                    threshold: alert.percent,
+                    spent,
+                    allocated,
+                    percent_used,
+                });
+            }
+        }
+
+        fired
+    }
+
+    /// Moves money into the budget, e.g., from the income pool
+    pub fn allocate(&mut self, amount: f64) {
+        self.budget.deposit(&amount);
+    }
+
+    pub fn add_tx(&mut self, ids: &dyn IdGenerator, name: String, value: f64, date: NaiveDate) -> TransactionId {
+        let tx = Transaction::new(ids, name, value, *self.id(), date);
+
+        self.add_transaction(tx)
+    }
+
+    pub fn add_transaction(&mut self, tx: Transaction) -> TransactionId {
+        if let Some(paid_by) = &tx.paid_by {
+            if !self.budget.members.contains(paid_by) {
+                panic!("{} is not a member of the budget", paid_by)
+            }
+        }
+
+        let txc = tx.id;
 
         self.budget.withdraw(&tx.value);
         self.transactions.borrow_mut().push(tx);
@@ -56,9 +225,9 @@ impl BudgetManager {
         txc
     }
 
-    pub fn find_tx_index(&self, id: &str) -> Result<usize, String> {
+    pub fn find_tx_index(&self, id: &TransactionId) -> Result<usize, String> {
         for (i, tx) in self.transactions.borrow().iter().enumerate() {
-            if &tx.id == id {
+            if tx.id == *id {
                 return Ok(i);
             } else {
                 continue;
@@ -68,7 +237,7 @@ impl BudgetManager {
         Err(String::from("Error, not found"))
     }
 
-    pub fn update_tx(&mut self, id: &str, val: f64) {
+    pub fn update_tx(&mut self, id: &TransactionId, val: f64) {
         let idx = match self.find_tx_index(id) {
             Ok(i) => i,
             _ => panic!("Transaction not found"),
@@ -80,9 +249,118 @@ impl BudgetManager {
         self.budget.deposit(&tx.value);
         tx.update_value(val);
         self.budget.withdraw(&tx.value);
+
+        // Keep each member's portion of a shared transaction the same
+        if !tx.shares.is_empty() {
+            let weights: Vec<f64> = tx.shares.iter().map(|share| share.amount).collect();
+            let amounts = distribute(to_cents(val), &weights);
+
+            for (share, amount) in tx.shares.iter_mut().zip(amounts) {
+                share.amount = from_cents(amount);
+            }
+        }
+    }
+
+    /// Replaces the people sharing the budget
+    pub fn set_members(&mut self, members: Vec<String>) {
+        let mut unique: Vec<String> = vec![];
+
+        for member in members {
+            if !unique.contains(&member) {
+                unique.push(member);
+            }
+        }
+
+        self.budget.members = unique;
+    }
+
+    /// Works out how much of `value` each member owes
+    pub fn split(&self, value: f64, split: &Split) -> Vec<Share> {
+        split.shares(value, &self.budget.members)
+    }
+
+    /// What each person paid and owes across the shared transactions,
+    /// along with the payments that settle everyone up
+    pub fn settle_up(&self) -> SettleUp {
+        // Work in cents so the balances always net out to zero
+        let mut paid: BTreeMap<String, i64> = BTreeMap::new();
+        let mut owes: BTreeMap<String, i64> = BTreeMap::new();
+
+        for tx in self.transactions.borrow().iter() {
+            if let Some(paid_by) = &tx.paid_by {
+                *paid.entry(paid_by.clone()).or_default() += to_cents(tx.value);
+
+                for share in tx.shares.iter() {
+                    *owes.entry(share.member.clone()).or_default() += to_cents(share.amount);
+                }
+            }
+        }
+
+        let mut members = self.budget.members.clone();
+        for member in paid.keys().chain(owes.keys()) {
+            if !members.contains(member) {
+                members.push(member.clone());
+            }
+        }
+
+        let nets: Vec<(String, i64)> = members
+            .iter()
+            .map(|m| {
+                let net = paid.get(m).copied().unwrap_or(0) - owes.get(m).copied().unwrap_or(0);
+                (m.clone(), net)
+            })
+            .collect();
+
+        let balances = members
+            .iter()
+            .zip(nets.iter())
+            .map(|(member, (_, net))| MemberBalance {
+                member: member.clone(),
+                paid: from_cents(paid.get(member).copied().unwrap_or(0)),
+                owes: from_cents(owes.get(member).copied().unwrap_or(0)),
+                net: from_cents(*net),
+            })
+            .collect();
+
+        SettleUp {
+            budget_id: self.budget.id,
+            balances,
+            payments: settle(nets),
+        }
+    }
+
+    pub fn set_tx_status(&mut self, id: &TransactionId, status: TransactionStatus) {
+        let idx = match self.find_tx_index(id) {
+            Ok(i) => i,
+            _ => panic!("Transaction not found"),
+        };
+
+        let mut txs = self.transactions.borrow_mut();
+        let tx = txs.get_mut(idx).expect("Transaction not found");
+
+        if tx.status == TransactionStatus::Reconciled && status != TransactionStatus::Reconciled {
+            panic!("Reconciled transactions cannot change status")
+        }
+
+        tx.status = status;
     }
 
-    pub fn remove_tx(&mut self, id: &str) {
+    /// Marks every cleared transaction paid from the account as reconciled
+    /// returning the ids of the transactions that changed
+    pub fn reconcile_account(&mut self, account_id: &str) -> Vec<TransactionId> {
+        let mut reconciled = vec![];
+
+        for tx in self.transactions.borrow_mut().iter_mut() {
+            if tx.account_id() == Some(account_id) && tx.status == TransactionStatus::Cleared {
+                tx.status = TransactionStatus::Reconciled;
+                reconciled.push(tx.id);
+            }
+        }
+
+        reconciled
+    }
+
+    pub fn remove_tx(&mut self, id: &TransactionId) {
         let idx = match self.find_tx_index(id) {
             Ok(i) => i,
             _ => panic!("Transaction not found"),
@@ -98,25 +376,39 @@ impl BudgetManager {
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Budget {
-    id: String,
+    id: BudgetId,
     name: String,
     total: f64,
+    /// People sharing the budget, empty for a personal budget
+    #[serde(default)]
+    members: Vec<String>,
 }
 
 impl Budget {
-    pub fn new(name: String, total: f64) -> Self {
+    pub fn new(ids: &dyn IdGenerator, name: String, total: f64) -> Self {
+        Budget {
+            id: ids.budget_id(),
+            name,
+            total,
+            members: vec![],
+        }
+    }
+
+    pub fn load(id: BudgetId, name: String, total: f64) -> Self {
         Budget {
-            id: Uuid::new_v4().to_string(),
+            id,
             name,
             total,
+            members: vec![],
         }
     }
 
-    pub fn load(id: String, name: String, total: f64) -> Self {
-        Budget { id, name, total }
+    pub fn with_members(mut self, members: Vec<String>) -> Self {
+        self.members = members;
+        self
     }
 
-    pub fn id(&self) -> &str {
+    pub fn id(&self) -> &BudgetId {
         &self.id
     }
 
@@ -128,6 +420,10 @@ impl Budget {
         &self.total
     }
 
+    pub fn members(&self) -> &[String] {
+        &self.members
+    }
+
     fn available_funds(&self) -> f64 {
         self.total
     }
@@ -138,7 +434,7 @@ impl Budget {
 
     fn withdraw(&mut self, val: &f64) {
         if self.can_withdraw(val) {
-            self.total = self.total - val;
+            self.total -= val;
         } else {
             // TODO: use Result instead of panic
             panic!("Insufficient funds, cannot withdraw {}", val)
@@ -146,38 +442,105 @@ impl Budget {
     }
 
     fn deposit(&mut self, val: &f64) {
-        self.total = self.total + val;
+        self.total += val;
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Transaction {
-    id: String,
+    id: TransactionId,
     name: String,
     value: f64,
-    budget_id: String,
+    budget_id: BudgetId,
+    /// Transactions saved before dates were kept get the Unix epoch, an unknown date sorts before every known one
+    #[serde(default)]
+    date: NaiveDate,
+    #[serde(default)]
+    payee_id: Option<String>,
+    #[serde(default)]
+    account_id: Option<String>,
+    #[serde(default)]
+    status: TransactionStatus,
+    /// Member of a shared budget who paid
+    #[serde(default)]
+    paid_by: Option<String>,
+    /// How the cost is split between members of a shared budget
+    #[serde(default)]
+    shares: Vec<Share>,
+    /// Id the bank gave the transaction, e.g., an OFX FITID, so a statement isn't imported twice
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    external_id: Option<String>,
 }
 
 impl Transaction {
-    pub fn new(name: String, value: f64, budget_id: String) -> Self {
+    pub fn new(ids: &dyn IdGenerator, name: String, value: f64, budget_id: BudgetId, date: NaiveDate) -> Self {
         Transaction {
-            id: Uuid::new_v4().to_string(),
+            id: ids.transaction_id(),
             name,
             value,
             budget_id,
+            date,
+            payee_id: None,
+            account_id: None,
+            status: TransactionStatus::Pending,
+            paid_by: None,
+            shares: vec![],
+            external_id: None,
         }
     }
 
-    pub fn load(id: String, name: String, value: f64, budget_id: String) -> Self {
+    pub fn load(id: TransactionId, name: String, value: f64, budget_id: BudgetId, date: NaiveDate) -> Self {
         Transaction {
             id,
             name,
             value,
             budget_id,
+            date,
+            payee_id: None,
+            account_id: None,
+            status: TransactionStatus::Pending,
+            paid_by: None,
+            shares: vec![],
+            external_id: None,
         }
     }
 
-    pub fn id(&self) -> &str {
+    pub fn with_date(mut self, date: NaiveDate) -> Self {
+        self.date = date;
+        self
+    }
+
+    pub fn with_payee(mut self, payee_id: Option<String>) -> Self {
+        self.payee_id = payee_id;
+        self
+    }
+
+    pub fn with_account(mut self, account_id: Option<String>) -> Self {
+        self.account_id = account_id;
+        self
+    }
+
+    pub fn with_status(mut self, status: TransactionStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn with_paid_by(mut self, paid_by: Option<String>) -> Self {
+        self.paid_by = paid_by;
+        self
+    }
+
+    pub fn with_shares(mut self, shares: Vec<Share>) -> Self {
+        self.shares = shares;
+        self
+    }
+
+    pub fn with_external_id(mut self, external_id: Option<String>) -> Self {
+        self.external_id = external_id;
+        self
+    }
+
+    pub fn id(&self) -> &TransactionId {
         &self.id
     }
 
@@ -189,10 +552,38 @@ impl Transaction {
         &self.value
     }
 
-    pub fn budget_id(&self) -> &str {
+    pub fn budget_id(&self) -> &BudgetId {
         &self.budget_id
     }
 
+    pub fn date(&self) -> NaiveDate {
+        self.date
+    }
+
+    pub fn payee_id(&self) -> Option<&str> {
+        self.payee_id.as_deref()
+    }
+
+    pub fn account_id(&self) -> Option<&str> {
+        self.account_id.as_deref()
+    }
+
+    pub fn status(&self) -> TransactionStatus {
+        self.status
+    }
+
+    pub fn paid_by(&self) -> Option<&str> {
+        self.paid_by.as_deref()
+    }
+
+    pub fn shares(&self) -> &[Share] {
+        &self.shares
+    }
+
+    pub fn external_id(&self) -> Option<&str> {
+        self.external_id.as_deref()
+    }
+
     fn rename(&mut self, name: String) {
         self.name = name;
     }
@@ -202,82 +593,1202 @@ impl Transaction {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Payee {
+    id: String,
+    name: String,
+    aliases: Vec<String>,
+}
 
-    #[test]
-    fn budget_manager_can_add_transaction() {
-        let budg = Budget::new(String::from("my-budget"), 200.00_f64);
-        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]));
+impl Payee {
+    pub fn new(ids: &dyn IdGenerator, name: String, aliases: Vec<String>) -> Self {
+        let mut payee = Payee {
+            id: ids.next_id(),
+            name,
+            aliases: vec![],
+        };
 
-        budgman.add_tx(String::from("cheeseborger"), 3.99_f64);
+        for alias in aliases {
+            payee.add_alias(alias);
+        }
 
-        assert_eq!(budgman.transactions.borrow().len(), 1);
-        assert_eq!(budgman.budget.available_funds(), 196.01_f64)
+        payee
     }
 
-    #[test]
-    fn budget_manager_can_remove_transaction() {
-        let budg = Budget::new(String::from("my-budget"), 200.00_f64);
-        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]));
+    pub fn load(id: String, name: String, aliases: Vec<String>) -> Self {
+        Payee { id, name, aliases }
+    }
 
-        let tx_id = budgman.add_tx(String::from("cheeseborger"), 3.99_f64);
-        budgman.remove_tx(&tx_id);
+    pub fn id(&self) -> &str {
+        &self.id
+    }
 
-        assert_eq!(budgman.transactions.borrow().len(), 0);
-        assert_eq!(budgman.available_funds(), 200.00_f64);
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    #[test]
-    fn budget_can_withdraw_money() {
-        let mut budget = Budget::new(String::from("my-budget"), 200.00_f64);
+    pub fn aliases(&self) -> &[String] {
+        &self.aliases
+    }
 
-        budget.withdraw(&10.00_f64);
+    pub fn add_alias(&mut self, alias: String) {
+        if !self.matches(&alias) {
+            self.aliases.push(alias);
+        }
+    }
 
-        assert_eq!(budget.available_funds(), 190.00_f64)
+    /// A payee matches a name when it equals the canonical name or any alias,
+    /// ignoring case and surrounding whitespace
+    pub fn matches(&self, name: &str) -> bool {
+        let name = normalize_name(name);
+
+        normalize_name(&self.name) == name
+            || self.aliases.iter().any(|alias| normalize_name(alias) == name)
     }
+}
 
-    #[test]
-    #[should_panic]
-    fn budget_cant_withdraw_money_more_than_available() {
-        let mut budget = Budget::new(String::from("my-budget"), 9.00_f64);
+fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
 
-        budget.withdraw(&10.00_f64);
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionStatus {
+    #[default]
+    Pending,
+    Cleared,
+    Reconciled,
+}
+
+impl fmt::Display for TransactionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            TransactionStatus::Pending => "pending",
+            TransactionStatus::Cleared => "cleared",
+            TransactionStatus::Reconciled => "reconciled",
+        };
+
+        write!(f, "{}", status)
     }
+}
 
-    #[test]
-    fn budget_can_deposit_money() {
-        let mut budget = Budget::new(String::from("my-budget"), 200.00_f64);
+impl FromStr for TransactionStatus {
+    type Err = String;
 
-        budget.deposit(&10.00_f64);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(TransactionStatus::Pending),
+            "cleared" => Ok(TransactionStatus::Cleared),
+            "reconciled" => Ok(TransactionStatus::Reconciled),
+            _ => Err(format!("Unknown transaction status {}", s)),
+        }
+    }
+}
 
-        assert_eq!(budget.available_funds(), 210.00_f64)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountKind {
+    Checking,
+    Savings,
+    Credit,
+    Cash,
+}
+
+impl fmt::Display for AccountKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            AccountKind::Checking => "checking",
+            AccountKind::Savings => "savings",
+            AccountKind::Credit => "credit",
+            AccountKind::Cash => "cash",
+        };
+
+        write!(f, "{}", kind)
     }
+}
 
-    #[test]
-    fn tx_can_get_renamed() {
-        let mut tx = Transaction::new(
-            String::from("cheeseborger"),
-            3.99_f64,
-            String::from("abc123"),
-        );
+impl FromStr for AccountKind {
+    type Err = String;
 
-        tx.rename(String::from("cheeseburger"));
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "checking" => Ok(AccountKind::Checking),
+            "savings" => Ok(AccountKind::Savings),
+            "credit" => Ok(AccountKind::Credit),
+            "cash" => Ok(AccountKind::Cash),
+            _ => Err(format!("Unknown account kind {}", s)),
+        }
+    }
+}
 
-        assert_eq!(tx.name, String::from("cheeseburger"))
+/// An account money is paid from, e.g., a checking account or a credit card
+///
+/// Balances follow the bank's view of a checking account: transactions reduce the balance,
+/// so a credit card with outstanding charges has a negative balance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Account {
+    id: String,
+    name: String,
+    kind: AccountKind,
+    opening_balance: f64,
+}
+
+impl Account {
+    pub fn new(ids: &dyn IdGenerator, name: String, kind: AccountKind, opening_balance: f64) -> Self {
+        Account {
+            id: ids.next_id(),
+            name,
+            kind,
+            opening_balance,
+        }
     }
 
-    #[test]
-    fn tx_can_have_value_updated() {
-        let mut tx = Transaction::new(
-            String::from("cheeseborger"),
-            3.99_f64,
-            String::from("abc123"),
-        );
+    pub fn load(id: String, name: String, kind: AccountKind, opening_balance: f64) -> Self {
+        Account {
+            id,
+            name,
+            kind,
+            opening_balance,
+        }
+    }
 
-        tx.update_value(4.99_f64);
+    pub fn id(&self) -> &str {
+        &self.id
+    }
 
-        assert_eq!(tx.value, 4.99_f64)
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> AccountKind {
+        self.kind
+    }
+
+    pub fn opening_balance(&self) -> &f64 {
+        &self.opening_balance
+    }
+
+    /// The balance the bank should agree with, only cleared and reconciled transactions count
+    pub fn cleared_balance<'a>(&self, transactions: impl Iterator<Item = &'a Transaction>) -> f64 {
+        let spent: f64 = transactions
+            .filter(|tx| tx.account_id() == Some(self.id()))
+            .filter(|tx| tx.status() != TransactionStatus::Pending)
+            .map(|tx| tx.value)
+            .sum();
+
+        round_cents(self.opening_balance - spent)
+    }
+}
+
+/// The outcome of comparing an account with a bank statement
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reconciliation {
+    pub account_id: String,
+    pub statement_balance: f64,
+    pub cleared_balance: f64,
+    pub difference: f64,
+    pub reconciled: Vec<TransactionId>,
+}
+
+impl Reconciliation {
+    pub fn new(account_id: String, statement_balance: f64, cleared_balance: f64) -> Self {
+        Reconciliation {
+            account_id,
+            statement_balance,
+            cleared_balance,
+            difference: round_cents(statement_balance - cleared_balance),
+            reconciled: vec![],
+        }
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.difference == 0_f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Income {
+    id: String,
+    source: String,
+    amount: f64,
+}
+
+impl Income {
+    pub fn new(ids: &dyn IdGenerator, source: String, amount: f64) -> Self {
+        Income {
+            id: ids.next_id(),
+            source,
+            amount,
+        }
+    }
+
+    pub fn load(id: String, source: String, amount: f64) -> Self {
+        Income { id, source, amount }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn amount(&self) -> &f64 {
+        &self.amount
+    }
+}
+
+/// Money from the income pool given to a budget
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Assignment {
+    id: String,
+    budget_id: BudgetId,
+    amount: f64,
+}
+
+impl Assignment {
+    pub fn new(ids: &dyn IdGenerator, budget_id: BudgetId, amount: f64) -> Self {
+        Assignment {
+            id: ids.next_id(),
+            budget_id,
+            amount,
+        }
+    }
+
+    pub fn load(id: String, budget_id: BudgetId, amount: f64) -> Self {
+        Assignment { id, budget_id, amount }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn budget_id(&self) -> &BudgetId {
+        &self.budget_id
+    }
+
+    pub fn amount(&self) -> &f64 {
+        &self.amount
+    }
+}
+
+/// All of a user's income and where it has been assigned
+///
+/// Every dollar of income should end up assigned to a budget,
+/// whatever hasn't been assigned yet is ready to assign
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IncomePool {
+    income: Vec<Income>,
+    assignments: Vec<Assignment>,
+    /// How many times the pool has been saved, a save fails when someone else saved it first
+    #[serde(default)]
+    version: Cell<u64>,
+}
+
+impl IncomePool {
+    pub fn new() -> Self {
+        IncomePool::default()
+    }
+
+    pub fn load(income: Vec<Income>, assignments: Vec<Assignment>) -> Self {
+        IncomePool {
+            income,
+            assignments,
+            version: Cell::new(0),
+        }
+    }
+
+    pub fn with_version(self, version: u64) -> Self {
+        self.version.set(version);
+        self
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.get()
+    }
+
+    /// Called by repositories once a save went through
+    pub fn set_version(&self, version: u64) {
+        self.version.set(version);
+    }
+
+    pub fn income(&self) -> &[Income] {
+        &self.income
+    }
+
+    pub fn assignments(&self) -> &[Assignment] {
+        &self.assignments
+    }
+
+    pub fn total_income(&self) -> f64 {
+        round_cents(self.income.iter().map(|i| i.amount).sum())
+    }
+
+    pub fn total_assigned(&self) -> f64 {
+        round_cents(self.assignments.iter().map(|a| a.amount).sum())
+    }
+
+    pub fn ready_to_assign(&self) -> f64 {
+        round_cents(self.total_income() - self.total_assigned())
+    }
+
+    pub fn add_income(&mut self, income: Income) -> String {
+        let id = income.id.clone();
+
+        self.income.push(income);
+
+        id
+    }
+
+    pub fn assign(&mut self, ids: &dyn IdGenerator, budget_id: BudgetId, amount: f64) -> Result<String, InsufficientFunds> {
+        self.try_assign(Assignment::new(ids, budget_id, amount))
+    }
+
+    /// Adds the assignment when there is enough ready to assign, otherwise the pool is left as it was
+    pub fn try_assign(&mut self, assignment: Assignment) -> Result<String, InsufficientFunds> {
+        let ready_to_assign = self.ready_to_assign();
+        if assignment.amount > ready_to_assign {
+            return Err(InsufficientFunds {
+                amount: assignment.amount,
+                ready_to_assign,
+            });
+        }
+
+        let id = assignment.id.clone();
+
+        self.assignments.push(assignment);
+
+        Ok(id)
+    }
+}
+
+/// An assignment asked for more than the income pool has ready to assign
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsufficientFunds {
+    pub amount: f64,
+    pub ready_to_assign: f64,
+}
+
+impl fmt::Display for InsufficientFunds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot assign {:.2}, only {:.2} is ready to assign", self.amount, self.ready_to_assign)
+    }
+}
+
+impl std::error::Error for InsufficientFunds {}
+
+/// Everything a repository holds as plain data, e.g., to write it to a file and read it back
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub budgets: Vec<BudgetManager>,
+    pub payees: Vec<Payee>,
+    pub accounts: Vec<Account>,
+    pub income_pool: IncomePool,
+}
+
+/// The calendar month a date falls in, spending is tracked month by month
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Period {
+    start: NaiveDate,
+    end: NaiveDate,
+}
+
+impl Period {
+    pub fn month_of(date: NaiveDate) -> Self {
+        let start = date.with_day(1).unwrap();
+        let next_start = match start.month() {
+            12 => NaiveDate::from_ymd_opt(start.year() + 1, 1, 1),
+            month => NaiveDate::from_ymd_opt(start.year(), month + 1, 1),
+        }
+        .unwrap();
+
+        Period {
+            start,
+            end: next_start.pred_opt().unwrap(),
+        }
+    }
+
+    pub fn start(&self) -> NaiveDate {
+        self.start
+    }
+
+    pub fn end(&self) -> NaiveDate {
+        self.end
+    }
+
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.start <= date && date <= self.end
+    }
+}
+
+/// How the cost of a shared transaction is divided between the budget's members
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Split {
+    /// Everyone pays the same
+    Equal,
+    /// Each listed member pays a percentage, the percentages add up to 100
+    Percentage(Vec<(String, f64)>),
+    /// Each listed member pays an amount, the amounts add up to the transaction value
+    Exact(Vec<(String, f64)>),
+}
+
+impl Split {
+    pub fn shares(&self, value: f64, members: &[String]) -> Vec<Share> {
+        let check_members = |parts: &[(String, f64)]| {
+            for (member, _) in parts {
+                if !members.contains(member) {
+                    panic!("{} is not a member of the budget", member)
+                }
+            }
+        };
+
+        let (names, amounts): (Vec<String>, Vec<i64>) = match self {
+            Split::Equal => {
+                if members.is_empty() {
+                    panic!("Budget has no members to split between")
+                }
+
+                let weights = vec![1_f64; members.len()];
+                (members.to_vec(), distribute(to_cents(value), &weights))
+            }
+            Split::Percentage(parts) => {
+                check_members(parts);
+
+                let weights: Vec<f64> = parts.iter().map(|(_, pct)| *pct).collect();
+                if (weights.iter().sum::<f64>() - 100_f64).abs() > 0.01 {
+                    panic!("Split percentages must add up to 100")
+                }
+
+                let names = parts.iter().map(|(m, _)| m.clone()).collect();
+                (names, distribute(to_cents(value), &weights))
+            }
+            Split::Exact(parts) => {
+                check_members(parts);
+
+                let amounts: Vec<i64> = parts.iter().map(|(_, amount)| to_cents(*amount)).collect();
+                if amounts.iter().sum::<i64>() != to_cents(value) {
+                    panic!("Split amounts must add up to {}", value)
+                }
+
+                let names = parts.iter().map(|(m, _)| m.clone()).collect();
+                (names, amounts)
+            }
+        };
+
+        names
+            .into_iter()
+            .zip(amounts)
+            .map(|(member, amount)| Share::new(member, from_cents(amount)))
+            .collect()
+    }
+}
+
+/// One member's portion of a shared transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Share {
+    member: String,
+    amount: f64,
+}
+
+impl Share {
+    pub fn new(member: String, amount: f64) -> Self {
+        Share { member, amount }
+    }
+
+    pub fn member(&self) -> &str {
+        &self.member
+    }
+
+    pub fn amount(&self) -> &f64 {
+        &self.amount
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemberBalance {
+    pub member: String,
+    pub paid: f64,
+    pub owes: f64,
+    /// Positive when the member is owed money
+    pub net: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Payment {
+    pub from: String,
+    pub to: String,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettleUp {
+    pub budget_id: BudgetId,
+    pub balances: Vec<MemberBalance>,
+    pub payments: Vec<Payment>,
+}
+
+/// Beyond this many people with something to settle, finding the fewest payments takes too long
+const MAX_EXACT_SETTLE: usize = 16;
+
+/// The fewest payments that leave everyone even
+///
+/// A group of people whose balances cancel out can settle among themselves with one payment fewer
+/// than there are people in it, so the fewest payments come from splitting everyone into
+/// as many such groups as possible
+fn settle(nets: Vec<(String, i64)>) -> Vec<Payment> {
+    let nets: Vec<(String, i64)> = nets.into_iter().filter(|(_, n)| *n != 0).collect();
+
+    if nets.len() > MAX_EXACT_SETTLE {
+        return settle_group(nets);
+    }
+
+    zero_sum_groups(nets).into_iter().flat_map(settle_group).collect()
+}
+
+/// Splits the balances into as many groups that add up to zero as there can be
+fn zero_sum_groups(nets: Vec<(String, i64)>) -> Vec<Vec<(String, i64)>> {
+    let n = nets.len();
+    let everyone = (1_usize << n) - 1;
+    let has = |set: usize, i: usize| set & (1 << i) != 0;
+
+    // For every set of people, what their balances add up to and the most groups it splits into
+    let mut sums = vec![0_i64; everyone + 1];
+    let mut groups = vec![0_u32; everyone + 1];
+    for set in 1..=everyone {
+        let first = set.trailing_zeros() as usize;
+        sums[set] = sums[set & (set - 1)] + nets[first].1;
+
+        let most = (0..n).filter(|i| has(set, *i)).map(|i| groups[set ^ (1 << i)]).max().unwrap_or(0);
+        groups[set] = most + u32::from(sums[set] == 0);
+    }
+
+    // Take people out one by one along the best split, a group ends whenever the ones left add up to zero
+    let mut result = vec![];
+    let mut group = vec![];
+    let mut set = everyone;
+    while set != 0 {
+        let i = (0..n)
+            .find(|i| has(set, *i) && groups[set ^ (1 << i)] + u32::from(sums[set] == 0) == groups[set])
+            .unwrap();
+
+        group.push(nets[i].clone());
+        set ^= 1 << i;
+
+        if sums[set] == 0 {
+            result.push(std::mem::take(&mut group));
+        }
+    }
+
+    result
+}
+
+/// Pairs the largest debtor with the largest creditor until everyone is even,
+/// this needs at most one payment fewer than there are people
+fn settle_group(nets: Vec<(String, i64)>) -> Vec<Payment> {
+    let mut debtors: Vec<(String, i64)> = nets.iter().filter(|(_, n)| *n < 0).map(|(m, n)| (m.clone(), -n)).collect();
+    let mut creditors: Vec<(String, i64)> = nets.into_iter().filter(|(_, n)| *n > 0).collect();
+    let mut payments = vec![];
+
+    loop {
+        debtors.sort_by_key(|d| Reverse(d.1));
+        creditors.sort_by_key(|c| Reverse(c.1));
+
+        let (debtor, creditor) = match (debtors.first_mut(), creditors.first_mut()) {
+            (Some(debtor), Some(creditor)) if debtor.1 > 0 && creditor.1 > 0 => (debtor, creditor),
+            _ => break,
+        };
+
+        let amount = debtor.1.min(creditor.1);
+        debtor.1 -= amount;
+        creditor.1 -= amount;
+
+        payments.push(Payment {
+            from: debtor.0.clone(),
+            to: creditor.0.clone(),
+            amount: from_cents(amount),
+        });
+    }
+
+    payments
+}
+
+fn to_cents(val: f64) -> i64 {
+    (val * 100_f64).round() as i64
+}
+
+fn from_cents(cents: i64) -> f64 {
+    cents as f64 / 100_f64
+}
+
+/// Divides cents in proportion to the weights, leftover cents go to the first shares
+/// so the parts always add up to the total
+fn distribute(total: i64, weights: &[f64]) -> Vec<i64> {
+    if weights.is_empty() {
+        return vec![];
+    }
+
+    let sum: f64 = weights.iter().sum();
+    let weights: Vec<f64> = if sum == 0_f64 {
+        vec![1_f64; weights.len()]
+    } else {
+        weights.to_vec()
+    };
+    let sum: f64 = weights.iter().sum();
+
+    let sign = total.signum();
+    let total = total.abs();
+
+    let mut amounts: Vec<i64> = weights
+        .iter()
+        .map(|w| (total as f64 * w / sum).floor() as i64)
+        .collect();
+
+    let leftover = total - amounts.iter().sum::<i64>();
+    for i in 0..leftover as usize {
+        amounts[i % weights.len()] += 1;
+    }
+
+    amounts.into_iter().map(|a| a * sign).collect()
+}
+
+/// Where a budget is heading if spending continues at the same pace
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Forecast {
+    pub budget_id: BudgetId,
+    pub period_start: NaiveDate,
+    pub period_end: NaiveDate,
+    /// Spent from the start of the period up to and including today
+    pub spent: f64,
+    pub available: f64,
+    pub daily_burn_rate: f64,
+    pub projected_end_balance: f64,
+    /// Empty when the money is expected to last until the end of the period
+    pub runs_out_on: Option<NaiveDate>,
+}
+
+/// Warn once spending reaches a percentage of a budget's allocation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertThreshold {
+    percent: u32,
+    /// Start of the period the threshold last fired in
+    last_fired: Option<NaiveDate>,
+}
+
+impl AlertThreshold {
+    pub fn new(percent: u32) -> Self {
+        AlertThreshold {
+            percent,
+            last_fired: None,
+        }
+    }
+
+    pub fn load(percent: u32, last_fired: Option<NaiveDate>) -> Self {
+        AlertThreshold { percent, last_fired }
+    }
+
+    pub fn percent(&self) -> u32 {
+        self.percent
+    }
+
+    pub fn last_fired(&self) -> Option<NaiveDate> {
+        self.last_fired
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    pub budget_id: BudgetId,
+    pub budget_name: String,
+    pub threshold: u32,
+    pub spent: f64,
+    pub allocated: f64,
+    pub percent_used: f64,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "budget {} has used {}% of its allocation ({} of {}), passing the {}% threshold",
+            self.budget_name, self.percent_used, self.spent, self.allocated, self.threshold
+        )
+    }
+}
+
+pub fn round_cents(val: f64) -> f64 {
+    (val * 100_f64).round() / 100_f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::clock::{Clock, SystemClock};
+    use crate::domain::ids::RandomIds;
+
+    #[test]
+    fn budget_manager_can_add_transaction() {
+        let budg = Budget::new(&RandomIds, String::from("my-budget"), 200.00_f64);
+        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]));
+
+        budgman.add_tx(&RandomIds, String::from("cheeseborger"), 3.99_f64, SystemClock.today());
+
+        assert_eq!(budgman.transactions.borrow().len(), 1);
+        assert_eq!(budgman.budget.available_funds(), 196.01_f64)
+    }
+
+    #[test]
+    fn budget_manager_can_remove_transaction() {
+        let budg = Budget::new(&RandomIds, String::from("my-budget"), 200.00_f64);
+        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]));
+
+        let tx_id = budgman.add_tx(&RandomIds, String::from("cheeseborger"), 3.99_f64, SystemClock.today());
+        budgman.remove_tx(&tx_id);
+
+        assert_eq!(budgman.transactions.borrow().len(), 0);
+        assert_eq!(budgman.available_funds(), 200.00_f64);
+    }
+
+    #[test]
+    fn budget_can_withdraw_money() {
+        let mut budget = Budget::new(&RandomIds, String::from("my-budget"), 200.00_f64);
+
+        budget.withdraw(&10.00_f64);
+
+        assert_eq!(budget.available_funds(), 190.00_f64)
+    }
+
+    #[test]
+    #[should_panic]
+    fn budget_cant_withdraw_money_more_than_available() {
+        let mut budget = Budget::new(&RandomIds, String::from("my-budget"), 9.00_f64);
+
+        budget.withdraw(&10.00_f64);
+    }
+
+    #[test]
+    fn budget_can_deposit_money() {
+        let mut budget = Budget::new(&RandomIds, String::from("my-budget"), 200.00_f64);
+
+        budget.deposit(&10.00_f64);
+
+        assert_eq!(budget.available_funds(), 210.00_f64)
+    }
+
+    #[test]
+    fn tx_can_get_renamed() {
+        let mut tx = Transaction::new(
+            &RandomIds,
+            String::from("cheeseborger"),
+            3.99_f64,
+            BudgetId::new(),
+            SystemClock.today(),
+        );
+
+        tx.rename(String::from("cheeseburger"));
+
+        assert_eq!(tx.name, String::from("cheeseburger"))
+    }
+
+    #[test]
+    fn tx_can_have_value_updated() {
+        let mut tx = Transaction::new(
+            &RandomIds,
+            String::from("cheeseborger"),
+            3.99_f64,
+            BudgetId::new(),
+            SystemClock.today(),
+        );
+
+        tx.update_value(4.99_f64);
+
+        assert_eq!(tx.value, 4.99_f64)
+    }
+
+    #[test]
+    fn payee_matches_name_and_aliases_ignoring_case() {
+        let payee = Payee::new(
+            &RandomIds,
+            String::from("Cheeseburger Palace"),
+            vec![String::from("cheeseborger")],
+        );
+
+        assert!(payee.matches("cheeseburger palace"));
+        assert!(payee.matches("  CheeseBorger "));
+        assert!(!payee.matches("pizza"));
+    }
+
+    #[test]
+    fn payee_ignores_duplicate_aliases() {
+        let mut payee = Payee::new(&RandomIds, String::from("Cheeseburger Palace"), vec![]);
+
+        payee.add_alias(String::from("cheeseborger"));
+        payee.add_alias(String::from("Cheeseborger"));
+        payee.add_alias(String::from("cheeseburger palace"));
+
+        assert_eq!(payee.aliases(), &[String::from("cheeseborger")]);
+    }
+
+    #[test]
+    fn account_cleared_balance_ignores_pending_and_other_accounts() {
+        let account = Account::new(&RandomIds, String::from("checking"), AccountKind::Checking, 100.00_f64);
+        let txs = [
+            Transaction::new(&RandomIds, String::from("a"), 10.00_f64, BudgetId::new(), SystemClock.today())
+                .with_account(Some(account.id().to_string()))
+                .with_status(TransactionStatus::Cleared),
+            Transaction::new(&RandomIds, String::from("b"), 5.50_f64, BudgetId::new(), SystemClock.today())
+                .with_account(Some(account.id().to_string()))
+                .with_status(TransactionStatus::Reconciled),
+            Transaction::new(&RandomIds, String::from("c"), 20.00_f64, BudgetId::new(), SystemClock.today())
+                .with_account(Some(account.id().to_string())),
+            Transaction::new(&RandomIds, String::from("d"), 30.00_f64, BudgetId::new(), SystemClock.today())
+                .with_status(TransactionStatus::Cleared),
+        ];
+
+        assert_eq!(account.cleared_balance(txs.iter()), 84.50_f64);
+    }
+
+    #[test]
+    fn budget_manager_reconciles_cleared_transactions_for_account() {
+        let budg = Budget::new(&RandomIds, String::from("my-budget"), 200.00_f64);
+        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]));
+
+        let cleared = budgman.add_transaction(
+            Transaction::new(&RandomIds, String::from("a"), 10.00_f64, *budgman.id(), SystemClock.today())
+                .with_account(Some(String::from("acct")))
+                .with_status(TransactionStatus::Cleared),
+        );
+        budgman.add_transaction(
+            Transaction::new(&RandomIds, String::from("b"), 10.00_f64, *budgman.id(), SystemClock.today())
+                .with_account(Some(String::from("acct"))),
+        );
+
+        let reconciled = budgman.reconcile_account("acct");
+
+        assert_eq!(reconciled, vec![cleared]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn reconciled_transaction_cannot_be_marked_pending() {
+        let budg = Budget::new(&RandomIds, String::from("my-budget"), 200.00_f64);
+        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]));
+
+        let tx_id = budgman.add_tx(&RandomIds, String::from("cheeseborger"), 3.99_f64, SystemClock.today());
+        budgman.set_tx_status(&tx_id, TransactionStatus::Reconciled);
+
+        budgman.set_tx_status(&tx_id, TransactionStatus::Pending);
+    }
+
+    #[test]
+    fn reads_budgets_saved_before_transactions_had_dates() {
+        // Given
+        let json = r#"{
+            "budget": {"id": "b7b1f1a6-3a8e-4f6e-9a55-8f4d8d0c8a01", "name": "Groceries", "total": 150.0},
+            "transactions": [
+                {"id": "0c5e3f43-2c3b-4d0e-8a4b-1c0f4e6f9b02", "name": "apples", "value": 50.0, "budget_id": "b7b1f1a6-3a8e-4f6e-9a55-8f4d8d0c8a01"}
+            ]
+        }"#;
+
+        // When
+        let budget_manager: BudgetManager = serde_json::from_str(json).unwrap();
+
+        // Then
+        let tx = budget_manager.transactions().borrow()[0].clone();
+        assert_eq!(budget_manager.name(), "Groceries");
+        assert_eq!(tx.name(), "apples");
+        assert_eq!(tx.date(), NaiveDate::from_ymd_opt(1970, 1, 1).unwrap());
+        assert_eq!(tx.payee_id(), None);
+        assert_eq!(tx.account_id(), None);
+        assert_eq!(tx.status(), TransactionStatus::Pending);
+    }
+
+    #[test]
+    fn income_pool_tracks_money_ready_to_assign() {
+        let mut pool = IncomePool::new();
+
+        pool.add_income(Income::new(&RandomIds, String::from("paycheck"), 1000.00_f64));
+        pool.add_income(Income::new(&RandomIds, String::from("side gig"), 250.50_f64));
+        pool.assign(&RandomIds, BudgetId::new(), 400.25_f64).unwrap();
+
+        assert_eq!(pool.total_income(), 1250.50_f64);
+        assert_eq!(pool.total_assigned(), 400.25_f64);
+        assert_eq!(pool.ready_to_assign(), 850.25_f64);
+    }
+
+    #[test]
+    fn income_pool_cant_assign_more_than_ready() {
+        let mut pool = IncomePool::new();
+
+        pool.add_income(Income::new(&RandomIds, String::from("paycheck"), 100.00_f64));
+
+        let err = pool.assign(&RandomIds, BudgetId::new(), 100.01_f64).unwrap_err();
+
+        assert_eq!(err.to_string(), "cannot assign 100.01, only 100.00 is ready to assign");
+        assert!(pool.assignments().is_empty());
+    }
+
+    #[test]
+    fn period_covers_calendar_month() {
+        let period = Period::month_of(NaiveDate::from_ymd_opt(2024, 2, 14).unwrap());
+
+        assert_eq!(period.start(), NaiveDate::from_ymd_opt(2024, 2, 1).unwrap());
+        assert_eq!(period.end(), NaiveDate::from_ymd_opt(2024, 2, 29).unwrap());
+
+        let period = Period::month_of(NaiveDate::from_ymd_opt(2023, 12, 31).unwrap());
+
+        assert_eq!(period.end(), NaiveDate::from_ymd_opt(2023, 12, 31).unwrap());
+    }
+
+    #[test]
+    fn budget_manager_fires_crossed_thresholds_once_per_period() {
+        let budg = Budget::new(&RandomIds, String::from("my-budget"), 100.00_f64);
+        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]));
+        budgman.set_alert_thresholds(vec![80, 50, 100]);
+
+        let today = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        let add = |bm: &mut BudgetManager, value: f64, date: NaiveDate| {
+            let tx = Transaction::new(&RandomIds, String::from("cheeseborger"), value, *bm.id(), SystemClock.today())
+                .with_date(date);
+            bm.add_transaction(tx);
+        };
+
+        add(&mut budgman, 60.00_f64, today);
+        let fired: Vec<u32> = budgman.check_alerts(today).iter().map(|a| a.threshold).collect();
+        assert_eq!(fired, vec![50]);
+
+        add(&mut budgman, 25.00_f64, today);
+        let fired: Vec<u32> = budgman.check_alerts(today).iter().map(|a| a.threshold).collect();
+        assert_eq!(fired, vec![80]);
+
+        assert!(budgman.check_alerts(today).is_empty());
+    }
+
+    #[test]
+    fn budget_manager_alerts_only_count_spending_in_current_period() {
+        let budg = Budget::new(&RandomIds, String::from("my-budget"), 100.00_f64);
+        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]));
+        budgman.set_alert_thresholds(vec![50]);
+
+        let last_month = NaiveDate::from_ymd_opt(2024, 2, 10).unwrap();
+        let tx = Transaction::new(&RandomIds, String::from("rent"), 60.00_f64, *budgman.id(), SystemClock.today())
+            .with_date(last_month);
+        budgman.add_transaction(tx);
+        assert_eq!(budgman.check_alerts(last_month).len(), 1);
+
+        let today = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        let tx = Transaction::new(&RandomIds, String::from("snacks"), 5.00_f64, *budgman.id(), SystemClock.today())
+            .with_date(today);
+        budgman.add_transaction(tx);
+
+        assert!(budgman.check_alerts(today).is_empty());
+    }
+
+    #[test]
+    fn setting_thresholds_keeps_when_existing_ones_fired() {
+        let budg = Budget::new(&RandomIds, String::from("my-budget"), 100.00_f64);
+        let fired_on = NaiveDate::from_ymd_opt(2024, 3, 1);
+        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]))
+            .with_alerts(vec![AlertThreshold::load(50, fired_on)]);
+
+        budgman.set_alert_thresholds(vec![50, 80, 50]);
+
+        assert_eq!(
+            budgman.alerts(),
+            &[AlertThreshold::load(50, fired_on), AlertThreshold::new(80)]
+        );
+    }
+
+    #[test]
+    fn forecast_projects_spending_pace_to_end_of_period() {
+        let budg = Budget::new(&RandomIds, String::from("my-budget"), 300.00_f64);
+        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]));
+
+        let date = |day: u32| NaiveDate::from_ymd_opt(2024, 4, day).unwrap();
+        for (day, value) in [(1, 40.00_f64), (5, 60.00_f64)] {
+            let tx = Transaction::new(&RandomIds, String::from("groceries"), value, *budgman.id(), SystemClock.today())
+                .with_date(date(day));
+            budgman.add_transaction(tx);
+        }
+
+        let forecast = budgman.forecast(date(10));
+
+        assert_eq!(forecast.period_start, date(1));
+        assert_eq!(forecast.period_end, date(30));
+        assert_eq!(forecast.spent, 100.00_f64);
+        assert_eq!(forecast.available, 200.00_f64);
+        assert_eq!(forecast.daily_burn_rate, 10.00_f64);
+        assert_eq!(forecast.projected_end_balance, 0_f64);
+        assert_eq!(forecast.runs_out_on, Some(date(30)));
+    }
+
+    #[test]
+    fn forecast_without_running_out_this_period() {
+        let budg = Budget::new(&RandomIds, String::from("my-budget"), 300.00_f64);
+        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]));
+
+        let date = |day: u32| NaiveDate::from_ymd_opt(2024, 4, day).unwrap();
+        let tx = Transaction::new(&RandomIds, String::from("groceries"), 10.00_f64, *budgman.id(), SystemClock.today())
+            .with_date(date(1));
+        budgman.add_transaction(tx);
+
+        let forecast = budgman.forecast(date(10));
+
+        assert_eq!(forecast.daily_burn_rate, 1.00_f64);
+        assert_eq!(forecast.projected_end_balance, 270.00_f64);
+        assert_eq!(forecast.runs_out_on, None);
+
+        let forecast = BudgetManager::new(Budget::new(&RandomIds, String::from("empty"), 50.00_f64), RefCell::new(vec![]))
+            .forecast(date(10));
+
+        assert_eq!(forecast.daily_burn_rate, 0_f64);
+        assert_eq!(forecast.runs_out_on, None);
+    }
+
+    #[test]
+    fn forecast_projects_from_unrounded_burn_rate() {
+        let budg = Budget::new(&RandomIds, String::from("my-budget"), 300.00_f64);
+        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]));
+
+        let date = |day: u32| NaiveDate::from_ymd_opt(2024, 4, day).unwrap();
+        let tx = Transaction::new(&RandomIds, String::from("groceries"), 10.00_f64, *budgman.id(), SystemClock.today())
+            .with_date(date(1));
+        budgman.add_transaction(tx);
+
+        let forecast = budgman.forecast(date(3));
+
+        // 10 over 3 days for the 27 days left, at the rounded 3.33 a day it would be 200.09
+        assert_eq!(forecast.daily_burn_rate, 3.33_f64);
+        assert_eq!(forecast.projected_end_balance, 200.00_f64);
+    }
+
+    fn shared_budget_manager() -> BudgetManager {
+        let budg = Budget::new(&RandomIds, String::from("house"), 500.00_f64);
+        let mut budgman = BudgetManager::new(budg, RefCell::new(vec![]));
+        budgman.set_members(vec![
+            String::from("ana"),
+            String::from("ben"),
+            String::from("cal"),
+        ]);
+
+        budgman
+    }
+
+    fn add_shared_tx(budgman: &mut BudgetManager, paid_by: &str, value: f64, split: Split) -> TransactionId {
+        let shares = budgman.split(value, &split);
+        let tx = Transaction::new(&RandomIds, String::from("shared"), value, *budgman.id(), SystemClock.today())
+            .with_paid_by(Some(String::from(paid_by)))
+            .with_shares(shares);
+
+        budgman.add_transaction(tx)
+    }
+
+    #[test]
+    fn split_equal_hands_out_leftover_cents() {
+        let budgman = shared_budget_manager();
+
+        let shares = budgman.split(10.00_f64, &Split::Equal);
+        let amounts: Vec<f64> = shares.iter().map(|s| *s.amount()).collect();
+
+        assert_eq!(amounts, vec![3.34_f64, 3.33_f64, 3.33_f64]);
+    }
+
+    #[test]
+    fn split_by_percentage_and_exact_amounts() {
+        let budgman = shared_budget_manager();
+
+        let shares = budgman.split(
+            50.00_f64,
+            &Split::Percentage(vec![(String::from("ana"), 60_f64), (String::from("ben"), 40_f64)]),
+        );
+        assert_eq!(
+            shares,
+            vec![Share::new(String::from("ana"), 30.00_f64), Share::new(String::from("ben"), 20.00_f64)]
+        );
+
+        let shares = budgman.split(
+            50.00_f64,
+            &Split::Exact(vec![(String::from("ben"), 45.50_f64), (String::from("cal"), 4.50_f64)]),
+        );
+        assert_eq!(
+            shares,
+            vec![Share::new(String::from("ben"), 45.50_f64), Share::new(String::from("cal"), 4.50_f64)]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn split_exact_amounts_must_add_up() {
+        let budgman = shared_budget_manager();
+
+        budgman.split(50.00_f64, &Split::Exact(vec![(String::from("ana"), 20.00_f64)]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn split_only_between_members() {
+        let budgman = shared_budget_manager();
+
+        budgman.split(50.00_f64, &Split::Exact(vec![(String::from("dan"), 50.00_f64)]));
+    }
+
+    #[test]
+    fn updating_shared_transaction_keeps_proportions() {
+        let mut budgman = shared_budget_manager();
+        let tx_id = add_shared_tx(
+            &mut budgman,
+            "ana",
+            40.00_f64,
+            Split::Percentage(vec![(String::from("ana"), 75_f64), (String::from("ben"), 25_f64)]),
+        );
+
+        budgman.update_tx(&tx_id, 80.00_f64);
+
+        let txs = budgman.transactions.borrow();
+        let amounts: Vec<f64> = txs[0].shares().iter().map(|s| *s.amount()).collect();
+        assert_eq!(amounts, vec![60.00_f64, 20.00_f64]);
+    }
+
+    #[test]
+    fn settle_up_settles_with_one_creditor() {
+        let mut budgman = shared_budget_manager();
+        add_shared_tx(&mut budgman, "ana", 90.00_f64, Split::Equal);
+        add_shared_tx(&mut budgman, "ben", 30.00_f64, Split::Equal);
+
+        let settle_up = budgman.settle_up();
+
+        let nets: Vec<f64> = settle_up.balances.iter().map(|b| b.net).collect();
+        assert_eq!(nets, vec![50.00_f64, -10.00_f64, -40.00_f64]);
+        assert_eq!(
+            settle_up.payments,
+            vec![
+                Payment { from: String::from("cal"), to: String::from("ana"), amount: 40.00_f64 },
+                Payment { from: String::from("ben"), to: String::from("ana"), amount: 10.00_f64 },
+            ]
+        );
+    }
+
+    #[test]
+    fn settle_finds_the_fewest_payments() {
+        let nets = vec![
+            (String::from("ana"), 400),
+            (String::from("ben"), 300),
+            (String::from("cal"), 300),
+            (String::from("dee"), -600),
+            (String::from("eve"), -400),
+        ];
+
+        let payments = settle(nets);
+
+        // Pairing the largest debtor with the largest creditor would take 4
+        assert_eq!(
+            payments,
+            vec![
+                Payment { from: String::from("eve"), to: String::from("ana"), amount: 4.00_f64 },
+                Payment { from: String::from("dee"), to: String::from("ben"), amount: 3.00_f64 },
+                Payment { from: String::from("dee"), to: String::from("cal"), amount: 3.00_f64 },
+            ]
+        );
     }
 }
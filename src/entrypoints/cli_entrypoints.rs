@@ -1,45 +1,737 @@
+use std::collections::BTreeMap;
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::{env, io, process};
+
 use crate::cli::cli_args;
-use crate::adapters::repository;
+use crate::adapters::{backups, bank_csv, csv_export, export, file_repository, ledger, ofx, qif, statement};
+use crate::adapters::repository::{self, ConcurrencyConflict};
+#[cfg(feature = "sqlcipher")]
+use crate::adapters::sqlcipher;
+use crate::domain::clock::{Clock, FixedClock, SystemClock};
+use crate::domain::ids::{IdGenerator, RandomIds, SequentialIds};
 use crate::services::handlers;
+use crate::services::validation::ValidationError;
+use crate::domain::models;
 use crate::views;
 
-pub fn handle_budget(args: cli_args::BudgetArgs, repo: &repository::SQLiteRepository) {
+/// Ids count up from `BUDGET_ID_SEED` when it is set, so scripted runs print the same ids every time
+pub fn id_generator() -> Box<dyn IdGenerator> {
+    match env::var("BUDGET_ID_SEED") {
+        Ok(seed) => Box::new(SequentialIds::starting_at(
+            seed.parse().expect("BUDGET_ID_SEED must be a whole number"),
+        )),
+        Err(_) => Box::new(RandomIds),
+    }
+}
+
+/// Today is `BUDGET_TODAY` (YYYY-MM-DD) when it is set
+pub fn clock() -> Box<dyn Clock> {
+    match env::var("BUDGET_TODAY") {
+        Ok(today) => Box::new(FixedClock::new(
+            today.parse().expect("BUDGET_TODAY must be a date as YYYY-MM-DD"),
+        )),
+        Err(_) => Box::new(SystemClock),
+    }
+}
+
+thread_local! {
+    /// Set while an ephemeral session runs, a failing command then only gives up on its own line
+    static IN_SESSION: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Unwinds out of a command that failed in a session, its error was already printed
+struct SessionExit;
+
+/// Exits with `code`, or in an ephemeral session gives up on the current line
+fn exit(code: i32) -> ! {
+    if IN_SESSION.with(Cell::get) {
+        panic::resume_unwind(Box::new(SessionExit));
+    }
+
+    process::exit(code)
+}
+
+/// Unwraps a command, exiting with the same status clap uses when its input is invalid
+fn validated<T>(cmd: Result<T, ValidationError>) -> T {
+    match cmd {
+        Ok(cmd) => cmd,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(2);
+        }
+    }
+}
+
+/// How many times a command starts over when another run saved the same budget first
+const CONFLICT_RETRIES: u32 = 3;
+
+/// Unwraps the result of a command that saves a budget, exiting when it kept losing to other runs
+fn saved<T>(result: Result<T, ConcurrencyConflict>) -> T {
+    match result {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Same as `saved` for commands that assign from the income pool, a shortfall is invalid input
+fn funded<T>(result: Result<T, handlers::CommandError>) -> T {
+    match result {
+        Ok(result) => result,
+        Err(handlers::CommandError::Invalid(e)) => validated(Err(e)),
+        Err(handlers::CommandError::Conflict(e)) => saved(Err(e)),
+    }
+}
+
+fn print_alerts(alerts: &[models::Alert]) {
+    for alert in alerts {
+        eprintln!("warning: {}", alert);
+    }
+}
+
+/// Opens budgets.db, plain as it always was without the `sqlcipher` feature
+#[cfg(not(feature = "sqlcipher"))]
+pub fn open_sqlite(filename: String, _args: &cli_args::Cli) -> repository::SQLiteRepository {
+    repository::SQLiteRepository::new(filename)
+}
+
+/// Opens budgets.db, unlocking it when it's encrypted or a passphrase was given, e.g., to start a new encrypted one
+#[cfg(feature = "sqlcipher")]
+pub fn open_sqlite(filename: String, args: &cli_args::Cli) -> repository::SQLiteRepository {
+    let given = args.keyfile.is_some() || env::var_os(PASSPHRASE_VAR).is_some();
+
+    if !given && !sqlcipher::is_encrypted(&filename) {
+        return repository::SQLiteRepository::new(filename);
+    }
+
+    let passphrase = passphrase(args.keyfile.as_deref(), PASSPHRASE_VAR, "Passphrase: ");
+    unlocked(&filename, &passphrase);
+
+    repository::SQLiteRepository::encrypted(filename, passphrase)
+}
+
+#[cfg(feature = "sqlcipher")]
+const PASSPHRASE_VAR: &str = "BUDGET_PASSPHRASE";
+
+#[cfg(feature = "sqlcipher")]
+const NEW_PASSPHRASE_VAR: &str = "BUDGET_NEW_PASSPHRASE";
+
+/// Reads a passphrase from the keyfile, the environment variable or a prompt, in that order
+#[cfg(feature = "sqlcipher")]
+fn passphrase(keyfile: Option<&str>, var: &str, prompt: &str) -> String {
+    let passphrase = match (keyfile, env::var(var)) {
+        (Some(keyfile), _) => match std::fs::read_to_string(keyfile) {
+            // Editors add a final newline that isn't part of the passphrase
+            Ok(contents) => contents.trim_end_matches(['\n', '\r']).to_string(),
+            Err(e) => {
+                eprintln!("error: could not read {}: {}", keyfile, e);
+                exit(2);
+            }
+        },
+        (None, Ok(passphrase)) => passphrase,
+        (None, Err(_)) => match rpassword::prompt_password(prompt) {
+            Ok(passphrase) => passphrase,
+            Err(_) => {
+                eprintln!("error: no terminal to ask for the passphrase, set {} or use a keyfile", var);
+                exit(2);
+            }
+        },
+    };
+
+    // SQLCipher leaves the database plain with an empty key
+    if passphrase.is_empty() {
+        eprintln!("error: the passphrase can't be empty");
+        exit(2);
+    }
+
+    passphrase
+}
+
+/// Exits when the passphrase doesn't unlock the database
+#[cfg(feature = "sqlcipher")]
+fn unlocked(filename: &str, passphrase: &str) {
+    if sqlcipher::open(filename, Some(passphrase)).is_err() {
+        eprintln!("error: could not unlock {}, is the passphrase right?", filename);
+        exit(1);
+    }
+}
+
+/// Changes the passphrase of budgets.db, a plain database is migrated to an encrypted one
+#[cfg(feature = "sqlcipher")]
+pub fn rekey(filename: String, args: &cli_args::Cli, rargs: &cli_args::RekeyArgs) {
+    if !std::path::Path::new(&filename).exists() {
+        eprintln!("error: {} doesn't exist yet", filename);
+        exit(1);
+    }
+
+    let current = if sqlcipher::is_encrypted(&filename) {
+        let current = passphrase(args.keyfile.as_deref(), PASSPHRASE_VAR, "Current passphrase: ");
+        unlocked(&filename, &current);
+
+        Some(current)
+    } else {
+        None
+    };
+
+    let new = passphrase(rargs.new_keyfile.as_deref(), NEW_PASSPHRASE_VAR, "New passphrase: ");
+    if rargs.new_keyfile.is_none()
+        && env::var_os(NEW_PASSPHRASE_VAR).is_none()
+        && passphrase(None, NEW_PASSPHRASE_VAR, "Repeat new passphrase: ") != new
+    {
+        eprintln!("error: the passphrases don't match");
+        exit(2);
+    }
+
+    sqlcipher::rekey(&filename, current.as_deref(), &new);
+
+    match current {
+        Some(_) => println!("Changed the passphrase of {}", filename),
+        None => println!("Encrypted {}", filename),
+    }
+}
+
+pub fn run<T: repository::Storage>(commands: cli_args::Commands, repo: &T, ids: &dyn IdGenerator, clock: &dyn Clock) {
+    match commands {
+        cli_args::Commands::Budgets(bargs) => handle_budget(bargs, repo, ids, clock),
+        cli_args::Commands::Transactions(txargs) => handle_transaction(txargs, repo, ids, clock),
+        cli_args::Commands::Payees(pargs) => handle_payee(pargs, repo, ids),
+        cli_args::Commands::Accounts(aargs) => handle_account(aargs, repo, ids),
+        cli_args::Commands::Income(iargs) => handle_income(iargs, repo, ids),
+        cli_args::Commands::Export(eargs) => handle_export(eargs, repo),
+        cli_args::Commands::Import(iargs) => handle_import(iargs, repo, ids, clock),
+        // These work on budgets.db itself and are run before it gets here, there's nothing to do anywhere else
+        cli_args::Commands::Backup(_) => sqlite_only("backup"),
+        cli_args::Commands::Restore(_) => sqlite_only("restore"),
+        #[cfg(feature = "sqlcipher")]
+        cli_args::Commands::Rekey(_) => sqlite_only("rekey"),
+    }
+}
+
+fn sqlite_only(command: &str) -> ! {
+    eprintln!("error: {} only works on budgets.db", command);
+    exit(2);
+}
+
+pub fn handle_export<T: repository::Storage>(args: cli_args::ExportArgs, repo: &T) {
+    let snapshot = views::snapshot(repo);
+
+    let document = match args.format {
+        cli_args::ExportFormat::Json => export::to_json(&snapshot),
+        cli_args::ExportFormat::Qif => qif::write(&snapshot.budgets),
+        cli_args::ExportFormat::Ledger => {
+            let options = ledger::LedgerOptions {
+                funding_account: args.funding_account,
+                budget_period: args.budget_period,
+            };
+            ledger::write(&snapshot, &options)
+        }
+    };
+
+    write_output(args.output, &document);
+}
+
+/// Writes a document to the file given with --output, or to stdout
+fn write_output(output: Option<String>, document: &str) {
+    match output {
+        Some(path) => file_repository::write_file(Path::new(&path), document),
+        None if document.ends_with('\n') => print!("{}", document),
+        None => println!("{}", document),
+    }
+}
+
+/// Loads an export or a QIF file, exits when the file isn't one this version can read
+pub fn handle_import<T: repository::Storage>(
+    args: cli_args::ImportArgs,
+    repo: &T,
+    ids: &dyn IdGenerator,
+    clock: &dyn Clock,
+) {
+    let contents = match std::fs::read(&args.path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("error: could not read {}: {}", args.path, e);
+            exit(1);
+        }
+    };
+
+    let is_qif = Path::new(&args.path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("qif"));
+    let format = args.format.unwrap_or(match is_qif {
+        true => cli_args::ExportFormat::Qif,
+        false => cli_args::ExportFormat::Json,
+    });
+
+    match format {
+        cli_args::ExportFormat::Json => {}
+        cli_args::ExportFormat::Qif => return import_qif(&args, &statement::text(&contents), repo, ids, clock),
+        cli_args::ExportFormat::Ledger => {
+            eprintln!("error: Ledger journals can be exported but not imported");
+            exit(2);
+        }
+    }
+
+    let snapshot = match export::from_json(&String::from_utf8_lossy(&contents)) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    };
+
+    let cmd = handlers::ImportSnapshot::new(snapshot).with_on_conflict(args.on_conflict);
+    let result = saved(cmd.run(repo, ids));
+    println!("{}", serde_json::to_string_pretty(&result).unwrap());
+}
+
+/// Budget for the QIF records without a category
+const UNCATEGORIZED: &str = "Uncategorized";
+
+/// Adds the spending in a QIF file to the budget named like its category, budgets that don't exist yet
+/// are created with a total that covers it. Prints the ids of the added transactions by budget
+fn import_qif<T: repository::Storage>(
+    args: &cli_args::ImportArgs,
+    contents: &str,
+    repo: &T,
+    ids: &dyn IdGenerator,
+    clock: &dyn Clock,
+) {
+    let statement = match qif::read(contents, args.day_first) {
+        Ok(statement) => statement,
+        Err(e) => {
+            eprintln!("error: {}, {}", args.path, e);
+            exit(2);
+        }
+    };
+
+    let mut by_category: BTreeMap<String, Vec<&qif::QifRow>> = BTreeMap::new();
+    for row in &statement.spending {
+        let category = row.category.clone().unwrap_or(String::from(UNCATEGORIZED));
+        by_category.entry(category).or_default().push(row);
+    }
+
+    let categories = by_category
+        .into_iter()
+        .map(|(category, rows)| (category, qif_transactions(args, &rows)))
+        .collect();
+
+    let cmd = handlers::ImportByCategory::new(categories).with_retries(CONFLICT_RETRIES);
+    let imported = funded(cmd.run(repo, ids, clock));
+
+    let mut added: BTreeMap<String, Vec<models::TransactionId>> = BTreeMap::new();
+    for category in imported {
+        if let Some(total) = category.created_with {
+            eprintln!("created budget {} with a total of {}", category.category, total);
+        }
+        for tx in &category.added {
+            print_alerts(&tx.alerts);
+        }
+        added.insert(category.category, category.added.iter().map(|tx| tx.transaction_id).collect());
+    }
+
+    println!("{}", serde_json::to_string_pretty(&added).unwrap());
+}
+
+/// The commands adding QIF rows, exits on the first row that can't be added
+fn qif_transactions(args: &cli_args::ImportArgs, rows: &[&qif::QifRow]) -> Vec<handlers::AddTransaction> {
+    // The handler adds each row to the budget of its category, which may not exist yet, so any id does
+    let budget_id = models::BudgetId::default();
+
+    rows.iter()
+        .map(|row| match handlers::AddTransaction::new(budget_id, row.description.clone(), row.amount) {
+            Ok(cmd) => cmd
+                .with_date(Some(row.date))
+                .with_external_id(Some(row.external_id.clone())),
+            Err(e) => {
+                eprintln!("error: {}, line {}: {}", args.path, row.line, e);
+                exit(2);
+            }
+        })
+        .collect()
+}
+
+/// Writes a timestamped backup of budgets.db, the timestamp is the local time
+pub fn handle_backup(args: cli_args::BackupArgs, repo: &repository::SQLiteRepository) {
+    let now = chrono::Local::now().naive_local();
+    let path = backups::backup(repo, Path::new(&args.path), args.keep as usize, now);
+
+    println!("Backed up to {}", path.display());
+}
+
+/// Replaces budgets.db with the backup, exits when the backup doesn't fit
+pub fn handle_restore(args: cli_args::RestoreArgs, repo: &repository::SQLiteRepository) {
+    match repo.restore_from(Path::new(&args.path)) {
+        Ok(()) => println!("Restored {}", args.path),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Runs the command given on the command line, exits like clap when there is none
+pub fn run_once<T: repository::Storage>(
+    commands: Option<cli_args::Commands>,
+    repo: &T,
+    ids: &dyn IdGenerator,
+    clock: &dyn Clock,
+) {
+    match commands {
+        Some(commands) => run(commands, repo, ids, clock),
+        None => cli_args::missing_command(),
+    }
+}
+
+/// Runs one command per line from stdin against the same repository until stdin ends,
+/// a line that doesn't parse or whose command fails is reported and skipped
+pub fn run_session<T: repository::Storage>(repo: &T, ids: &dyn IdGenerator, clock: &dyn Clock) {
+    IN_SESSION.with(|in_session| in_session.set(true));
+    // Commands panic on what they can't recover from, e.g., an id that doesn't exist, report it like any other error
+    panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let message = match payload.downcast_ref::<String>() {
+            Some(message) => message.as_str(),
+            None => payload.downcast_ref::<&str>().copied().unwrap_or("the command failed"),
+        };
+
+        eprintln!("error: {}", message);
+    }));
+
+    for line in io::stdin().lines() {
+        let line = line.unwrap();
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match cli_args::parse_line(&line) {
+            Ok(cli_args::Cli { commands: Some(commands), .. }) => {
+                // Whatever the command saved before it failed is kept, as it would be in budgets.db
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| run(commands, repo, ids, clock)));
+            }
+            Ok(_) => (),
+            Err(e) => e.print().unwrap(),
+        }
+    }
+}
+
+pub fn handle_budget<T: repository::Storage>(
+    args: cli_args::BudgetArgs,
+    repo: &T,
+    ids: &dyn IdGenerator,
+    clock: &dyn Clock,
+) {
     match args.commands {
         cli_args::BudgetCommands::Add { name, total } => {
-            let cmd = handlers::CreateBudget::new(name, total);
-            let result = cmd.run(repo);
+            let cmd = validated(handlers::CreateBudget::new(name, total)).with_retries(CONFLICT_RETRIES);
+            let result = funded(cmd.run(repo, ids));
             println!("{}", serde_json::to_string_pretty(&result).unwrap());
         }
-        cli_args::BudgetCommands::List => {
+        cli_args::BudgetCommands::List { forecast: false } => {
             let result = views::list_budgets(repo);
             println!("{}", serde_json::to_string_pretty(&result).unwrap());
-
+        }
+        cli_args::BudgetCommands::List { forecast: true } => {
+            let result = views::list_budgets_with_forecast(clock.today(), repo);
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
         }
         cli_args::BudgetCommands::Delete { id } => {
-            let cmd = handlers::DeleteBudget::new(id);
+            let cmd = validated(handlers::DeleteBudget::new(id));
             cmd.run(repo);
         }
+        cli_args::BudgetCommands::Forecast { id } => {
+            let result = views::forecast(&id, clock.today(), repo);
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        cli_args::BudgetCommands::Members { id, member } => {
+            let cmd = validated(handlers::SetBudgetMembers::new(id, member)).with_retries(CONFLICT_RETRIES);
+            let result = saved(cmd.run(repo));
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        cli_args::BudgetCommands::Settle { id } => {
+            let result = views::settle_up(&id, repo);
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        cli_args::BudgetCommands::Alerts { id, threshold } => {
+            let cmd = validated(handlers::SetAlertThresholds::new(id, threshold)).with_retries(CONFLICT_RETRIES);
+            let result = saved(cmd.run(repo));
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        cli_args::BudgetCommands::Export(eargs) => export_budgets(eargs, repo),
+    }
+}
+
+/// Every column when none were chosen
+fn columns<C: Copy>(chosen: Vec<C>, all: &[C]) -> Vec<C> {
+    match chosen.is_empty() {
+        true => all.to_vec(),
+        false => chosen,
     }
 }
 
-pub fn handle_transaction(args: cli_args::TransactionArgs, repo: &repository::SQLiteRepository) {
+fn export_budgets<T: repository::Storage>(args: cli_args::BudgetExportArgs, repo: &T) {
+    let columns = columns(args.columns, &csv_export::BudgetColumn::ALL);
+    let range = csv_export::DateRange {
+        from: args.from,
+        to: args.to,
+    };
+
+    let document = match args.format {
+        cli_args::TableFormat::Csv => csv_export::budgets(&views::snapshot(repo), &columns, &range),
+    };
+
+    write_output(args.output, &document);
+}
+
+fn export_transactions<T: repository::Storage>(args: cli_args::TransactionExportArgs, repo: &T) {
+    let columns = columns(args.columns, &csv_export::TransactionColumn::ALL);
+    let range = csv_export::DateRange {
+        from: args.from,
+        to: args.to,
+    };
+
+    let document = match args.format {
+        cli_args::TableFormat::Csv => {
+            csv_export::transactions(&views::snapshot(repo), args.budget_id.as_ref(), &columns, &range)
+        }
+    };
+
+    write_output(args.output, &document);
+}
+
+pub fn handle_transaction<T: repository::Storage>(
+    args: cli_args::TransactionArgs,
+    repo: &T,
+    ids: &dyn IdGenerator,
+    clock: &dyn Clock,
+) {
     match args.commands {
-        cli_args::TransactionCommands::Add { budget_id, name, amount } => {
-            let cmd = handlers::AddTransaction::new(budget_id, name, amount);
-            let result = cmd.run(repo);
-            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        cli_args::TransactionCommands::Add { budget_id, name, amount, date, account, paid_by, split } => {
+            let cmd = validated(handlers::AddTransaction::new(budget_id, name, amount))
+                .with_date(date)
+                .with_account(account)
+                .with_split(paid_by, split)
+                .with_retries(CONFLICT_RETRIES);
+            let result = saved(cmd.run(repo, ids, clock));
+            print_alerts(&result.alerts);
+            println!("{}", serde_json::to_string_pretty(&result.transaction_id).unwrap());
         }
         cli_args::TransactionCommands::List { budget_id } => {
             let result = views::list_transactions(&budget_id, repo);
             println!("{}", serde_json::to_string_pretty(&result).unwrap());
         }
         cli_args::TransactionCommands::Remove { budget_id, id } => {
-            let cmd = handlers::RemoveTransaction::new(budget_id, id);
-            cmd.run(repo);
+            let cmd = validated(handlers::RemoveTransaction::new(budget_id, id)).with_retries(CONFLICT_RETRIES);
+            saved(cmd.run(repo));
         }
         cli_args::TransactionCommands::Update { budget_id, id, amount } => {
-            let cmd = handlers::UpdateTransaction::new(budget_id, id, amount);
-            cmd.run(repo);
+            let cmd = validated(handlers::UpdateTransaction::new(budget_id, id, amount)).with_retries(CONFLICT_RETRIES);
+            let alerts = saved(cmd.run(repo, clock));
+            print_alerts(&alerts);
+        }
+        cli_args::TransactionCommands::Import(cargs) => import_statement(cargs, repo, ids, clock),
+        cli_args::TransactionCommands::Export(eargs) => export_transactions(eargs, repo),
+        cli_args::TransactionCommands::Status { budget_id, id, status } => {
+            let cmd = validated(handlers::SetTransactionStatus::new(budget_id, id, status))
+                .with_retries(CONFLICT_RETRIES);
+            saved(cmd.run(repo));
+        }
+    }
+}
+
+/// Mappings saved with --save-preset, in the working directory like budgets.db
+const CSV_PRESETS: &str = "csv-presets.toml";
+
+/// The preset, if there is one, with the options given on the command line on top
+fn csv_mapping(args: &cli_args::StatementImportArgs, presets: &bank_csv::Presets) -> bank_csv::CsvMapping {
+    let mut mapping = match &args.preset {
+        Some(name) => match presets.get(name) {
+            Some(mapping) => mapping.clone(),
+            None => {
+                eprintln!("error: there is no preset named {} in {}", name, CSV_PRESETS);
+                exit(2);
+            }
+        },
+        None => bank_csv::CsvMapping::default(),
+    };
+
+    if let Some(column) = &args.date_column {
+        mapping.date = column.clone();
+    }
+    if let Some(column) = &args.description_column {
+        mapping.description = column.clone();
+    }
+    if let Some(column) = &args.amount_column {
+        mapping.amount = column.clone();
+    }
+    if let Some(sign) = args.spending_sign {
+        mapping.spending_sign = sign;
+    }
+    if let Some(format) = &args.date_format {
+        mapping.date_format = format.clone();
+    }
+    if let Some(delimiter) = args.delimiter {
+        mapping.delimiter = delimiter;
+    }
+    if let Some(decimal_comma) = args.decimal_comma {
+        mapping.decimal_comma = decimal_comma;
+    }
+
+    mapping
+}
+
+/// The format given with --format, or the one the file's extension stands for
+fn statement_format(args: &cli_args::StatementImportArgs) -> cli_args::StatementFormat {
+    let extension = Path::new(&args.file)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+
+    match (args.format, extension.as_deref()) {
+        (Some(format), _) => format,
+        (None, Some("ofx" | "qfx")) => cli_args::StatementFormat::Ofx,
+        (None, _) => cli_args::StatementFormat::Csv,
+    }
+}
+
+/// Adds the spending in a bank's statement, a dry run prints the rows that would be added instead
+fn import_statement<T: repository::Storage>(
+    args: cli_args::StatementImportArgs,
+    repo: &T,
+    ids: &dyn IdGenerator,
+    clock: &dyn Clock,
+) {
+    let contents = match std::fs::read(&args.file) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("error: could not read {}: {}", args.file, e);
+            exit(1);
+        }
+    };
+
+    let statement = match statement_format(&args) {
+        cli_args::StatementFormat::Csv => {
+            let mut presets = bank_csv::Presets::load(Path::new(CSV_PRESETS));
+            let mapping = csv_mapping(&args, &presets);
+            let statement = bank_csv::read(contents.as_slice(), &mapping).map_err(|e| e.to_string());
+
+            // Only a mapping that read the whole file is worth keeping
+            if let (Ok(_), Some(name)) = (&statement, &args.save_preset) {
+                presets.insert(name.clone(), mapping);
+                presets.save(Path::new(CSV_PRESETS));
+            }
+
+            statement
+        }
+        cli_args::StatementFormat::Ofx => ofx::read(&statement::text(&contents)).map_err(|e| e.to_string()),
+    };
+    let statement = match statement {
+        Ok(statement) => statement,
+        Err(e) => {
+            eprintln!("error: {}, {}", args.file, e);
+            exit(2);
+        }
+    };
+
+    let transactions = statement
+        .spending
+        .iter()
+        .map(|row| match handlers::AddTransaction::new(args.budget_id, row.description.clone(), row.amount) {
+            Ok(cmd) => cmd
+                .with_date(Some(row.date))
+                .with_account(args.account.clone())
+                .with_external_id(row.external_id.clone()),
+            Err(e) => {
+                eprintln!("error: {}, line {}: {}", args.file, row.line, e);
+                exit(2);
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if args.dry_run {
+        println!("{}", serde_json::to_string_pretty(&statement).unwrap());
+        return;
+    }
+
+    let rows = transactions.len();
+    let cmd = handlers::ImportTransactions::new(args.budget_id, transactions).with_retries(CONFLICT_RETRIES);
+    let added = saved(cmd.run(repo, ids, clock));
+
+    if added.len() < rows {
+        eprintln!("transactions imported before and left out: {}", rows - added.len());
+    }
+
+    for tx in &added {
+        print_alerts(&tx.alerts);
+    }
+    let transaction_ids: Vec<models::TransactionId> = added.iter().map(|tx| tx.transaction_id).collect();
+    println!("{}", serde_json::to_string_pretty(&transaction_ids).unwrap());
+}
+
+pub fn handle_payee<T: repository::Storage>(args: cli_args::PayeeArgs, repo: &T, ids: &dyn IdGenerator) {
+    match args.commands {
+        cli_args::PayeeCommands::Add { name, alias } => {
+            let cmd = validated(handlers::CreatePayee::new(name, alias));
+            let result = validated(cmd.run(repo, ids));
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        cli_args::PayeeCommands::Alias { id, alias } => {
+            let cmd = validated(handlers::AddPayeeAlias::new(id, alias));
+            let result = validated(cmd.run(repo));
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        cli_args::PayeeCommands::List => {
+            let result = views::list_payees(repo);
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        cli_args::PayeeCommands::Totals => {
+            let result = views::payee_totals(repo);
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+    }
+}
+
+pub fn handle_account<T: repository::Storage>(args: cli_args::AccountArgs, repo: &T, ids: &dyn IdGenerator) {
+    match args.commands {
+        cli_args::AccountCommands::Add { name, kind, opening_balance } => {
+            let cmd = validated(handlers::CreateAccount::new(name, kind, opening_balance));
+            let result = cmd.run(repo, ids);
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        cli_args::AccountCommands::List => {
+            let result = views::list_accounts(repo);
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        cli_args::AccountCommands::Reconcile { account, statement_balance } => {
+            let cmd = validated(handlers::ReconcileAccount::new(account, statement_balance)).with_retries(CONFLICT_RETRIES);
+            let result = saved(cmd.run(repo));
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+    }
+}
+
+pub fn handle_income<T: repository::Storage>(args: cli_args::IncomeArgs, repo: &T, ids: &dyn IdGenerator) {
+    match args.commands {
+        cli_args::IncomeCommands::Add { source, amount } => {
+            let cmd = validated(handlers::AddIncome::new(source, amount)).with_retries(CONFLICT_RETRIES);
+            let result = saved(cmd.run(repo, ids));
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        cli_args::IncomeCommands::List => {
+            let result = views::list_income(repo);
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        cli_args::IncomeCommands::Assign { budget_id, amount } => {
+            let cmd = validated(handlers::AssignFunds::new(budget_id, amount)).with_retries(CONFLICT_RETRIES);
+            let result = funded(cmd.run(repo, ids));
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        cli_args::IncomeCommands::Ready => {
+            let result = views::income_summary(repo);
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
         }
     }
 }
@@ -2,19 +2,42 @@ use std::env::current_dir;
 
 use budget::cli::cli_args;
 use budget::entrypoints::cli_entrypoints;
-use budget::adapters::repository;
+use budget::adapters::{in_memory_repository, json_file_repository, toml_dir_repository};
 
 fn main() {
     let args = cli_args::parse_args();
-    let pwd = current_dir().unwrap();
-    let repo = repository::SQLiteRepository::new(format!("{}/budgets.db", pwd.to_string_lossy()));
+    let ids = cli_entrypoints::id_generator();
+    let clock = cli_entrypoints::clock();
 
-    match args.commands {
-        cli_args::Commands::Budgets(bargs) => {
-            cli_entrypoints::handle_budget(bargs, &repo);
+    if args.ephemeral {
+        let repo = in_memory_repository::InMemoryRepository::new();
+
+        match args.commands {
+            Some(commands) => cli_entrypoints::run(commands, &repo, ids.as_ref(), clock.as_ref()),
+            None => cli_entrypoints::run_session(&repo, ids.as_ref(), clock.as_ref()),
+        }
+    } else if let Some(filename) = args.json {
+        let repo = json_file_repository::JsonFileRepository::new(filename);
+        cli_entrypoints::run_once(args.commands, &repo, ids.as_ref(), clock.as_ref());
+    } else if let Some(dirname) = args.toml_dir {
+        let repo = toml_dir_repository::TomlDirRepository::new(dirname);
+        cli_entrypoints::run_once(args.commands, &repo, ids.as_ref(), clock.as_ref());
+    } else {
+        let pwd = current_dir().unwrap();
+        let filename = format!("{}/budgets.db", pwd.to_string_lossy());
+
+        #[cfg(feature = "sqlcipher")]
+        if let Some(cli_args::Commands::Rekey(rargs)) = &args.commands {
+            cli_entrypoints::rekey(filename, &args, rargs);
+            return;
         }
-        cli_args::Commands::Transactions(txargs) => {
-            cli_entrypoints::handle_transaction(txargs, &repo);
+
+        let repo = cli_entrypoints::open_sqlite(filename, &args);
+
+        match args.commands {
+            Some(cli_args::Commands::Backup(bargs)) => cli_entrypoints::handle_backup(bargs, &repo),
+            Some(cli_args::Commands::Restore(rargs)) => cli_entrypoints::handle_restore(rargs, &repo),
+            commands => cli_entrypoints::run_once(commands, &repo, ids.as_ref(), clock.as_ref()),
         }
     }
 }
@@ -1,269 +1,2874 @@
 #![allow(dead_code)]
 
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::str::FromStr;
+use std::{error, fmt};
 
-use crate::adapters::repository;
-use crate::domain::models;
+use chrono::NaiveDate;
+use serde::Serialize;
 
-/// This module contains handler functions that call business logic and persistence layers
-/// This module is the API for consumers, e.g., an web API or CLI that is implemented later
+use crate::adapters::async_repository;
+use crate::adapters::repository::{self, ConcurrencyConflict};
+use crate::domain::clock::Clock;
+use crate::domain::ids::IdGenerator;
+use crate::domain::models::{self, Snapshot};
+use crate::services::validation::{self, FieldError, ValidationError, Validator};
+
+/// Runs a read-modify-write of a budget, starting over from a fresh read
+/// up to `retries` times when someone else saved the budget in the meantime
+fn retrying<R>(
+    retries: u32,
+    mut attempt: impl FnMut() -> Result<R, ConcurrencyConflict>,
+) -> Result<R, ConcurrencyConflict> {
+    let mut remaining = retries;
+
+    loop {
+        match attempt() {
+            Err(_) if remaining > 0 => remaining -= 1,
+            result => return result,
+        }
+    }
+}
+
+/// Same as `retrying` for async attempts
+async fn retrying_async<R, F, Fut>(retries: u32, mut attempt: F) -> Result<R, ConcurrencyConflict>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<R, ConcurrencyConflict>>,
+{
+    let mut remaining = retries;
+
+    loop {
+        match attempt().await {
+            Err(_) if remaining > 0 => remaining -= 1,
+            result => return result,
+        }
+    }
+}
+
+/// Why a command that assigns money from the income pool didn't go through, nothing was saved
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    /// The pool hasn't enough ready to assign
+    Invalid(ValidationError),
+    Conflict(ConcurrencyConflict),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Invalid(e) => write!(f, "{}", e),
+            CommandError::Conflict(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for CommandError {}
+
+impl From<ValidationError> for CommandError {
+    fn from(e: ValidationError) -> Self {
+        CommandError::Invalid(e)
+    }
+}
+
+impl From<ConcurrencyConflict> for CommandError {
+    fn from(e: ConcurrencyConflict) -> Self {
+        CommandError::Conflict(e)
+    }
+}
+
+/// Assigns from the pool, a shortfall is reported against `field` like any other invalid input
+fn try_assign(pool: &mut models::IncomePool, assignment: models::Assignment, field: &'static str) -> Result<(), ValidationError> {
+    pool.try_assign(assignment).map(|_| ()).map_err(|e| ValidationError {
+        errors: vec![FieldError::new(field, e.to_string())],
+    })
+}
+
+// This module contains handler functions that call business logic and persistence layers
+// This module is the API for consumers, e.g., an web API or CLI that is implemented later
+// Every handler has a blocking `run` and a `run_async` for async services, both share the same steps
 
 pub struct CreateBudget {
     budget_name: String,
     total: f64,
+    retries: u32,
 }
 
 impl CreateBudget {
-    pub fn new(budget_name: String, total: f64) -> Self {
-        CreateBudget {
+    pub fn new(budget_name: String, total: f64) -> Result<Self, ValidationError> {
+        Validator::new()
+            .check(validation::name("name", &budget_name))
+            .check(validation::non_negative("total", total))
+            .finish()?;
+
+        Ok(CreateBudget {
             budget_name,
             total,
-        }
+            retries: 0,
+        })
     }
 
-    pub fn run<T: repository::Repository>(&self, repo: &T) -> models::BudgetManager {
-        let budget = models::Budget::new(self.budget_name.clone(), self.total);
-        let budget_manager = models::BudgetManager::new(budget, RefCell::new(vec![]));
+    /// Tries again this many times when the income pool was saved by someone else first
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn run<T>(&self, repo: &T, ids: &dyn IdGenerator) -> Result<models::BudgetManager, CommandError>
+    where
+        T: repository::Repository + repository::IncomeRepository,
+    {
+        let budget_manager = retrying(self.retries, || {
+            let mut pool = repo.get_income_pool();
+            let budget_manager = match self.build(&mut pool, ids) {
+                Ok(budget_manager) => budget_manager,
+                Err(e) => return Ok(Err(e)),
+            };
 
-        repo.add(&budget_manager);
+            repo.add_funded(std::slice::from_ref(&budget_manager), &pool)?;
 
-        budget_manager
+            Ok(Ok(budget_manager))
+        })??;
+
+        Ok(budget_manager)
+    }
+
+    pub async fn run_async<T>(&self, repo: &T, ids: &dyn IdGenerator) -> Result<models::BudgetManager, CommandError>
+    where
+        T: async_repository::AsyncRepository + async_repository::AsyncIncomeRepository,
+    {
+        let budget_manager = retrying_async(self.retries, || async {
+            let mut pool = repo.get_income_pool().await;
+            let budget_manager = match self.build(&mut pool, ids) {
+                Ok(budget_manager) => budget_manager,
+                Err(e) => return Ok(Err(e)),
+            };
+
+            let (mut saved, _) = repo.add_funded(vec![budget_manager], pool).await?;
+
+            Ok(Ok(saved.pop().unwrap()))
+        })
+        .await??;
+
+        Ok(budget_manager)
+    }
+
+    /// The budget starts out empty, its total is assigned to it from the income pool. A total of 0 creates
+    /// an unfunded budget that money can be assigned to later
+    fn build(&self, pool: &mut models::IncomePool, ids: &dyn IdGenerator) -> Result<models::BudgetManager, ValidationError> {
+        let budget = models::Budget::new(ids, self.budget_name.clone(), 0_f64);
+        let mut budget_manager = models::BudgetManager::new(budget, RefCell::new(vec![]));
+
+        if self.total > 0_f64 {
+            try_assign(pool, models::Assignment::new(ids, *budget_manager.id(), self.total), "total")?;
+            budget_manager.allocate(self.total);
+        }
+
+        Ok(budget_manager)
     }
 }
 
 pub struct DeleteBudget {
-    id: String,
+    id: models::BudgetId,
 }
 
 impl DeleteBudget {
-    pub fn new(id: String) -> Self {
-        DeleteBudget { id }
+    pub fn new(id: models::BudgetId) -> Result<Self, ValidationError> {
+        Ok(DeleteBudget { id })
     }
 
     pub fn run<T: repository::Repository>(&self, repo: &T) {
         repo.delete(&self.id);
     }
+
+    pub async fn run_async<T: async_repository::AsyncRepository>(&self, repo: &T) {
+        repo.delete(&self.id).await;
+    }
+}
+
+pub struct SetAlertThresholds {
+    budget_id: models::BudgetId,
+    thresholds: Vec<u32>,
+    retries: u32,
+}
+
+impl SetAlertThresholds {
+    pub fn new(budget_id: models::BudgetId, thresholds: Vec<u32>) -> Result<Self, ValidationError> {
+        thresholds
+            .iter()
+            .fold(Validator::new(), |v, t| v.check(validation::percent("threshold", *t)))
+            .finish()?;
+
+        Ok(SetAlertThresholds {
+            budget_id,
+            thresholds,
+            retries: 0,
+        })
+    }
+
+    /// Tries again this many times when the budget was saved by someone else first
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn run<T: repository::Repository>(&self, repo: &T) -> Result<models::BudgetManager, ConcurrencyConflict> {
+        retrying(self.retries, || {
+            let mut budget_manager = repo.get(&self.budget_id);
+
+            self.apply(&mut budget_manager);
+
+            repo.add(&budget_manager)?;
+
+            Ok(budget_manager)
+        })
+    }
+
+    pub async fn run_async<T: async_repository::AsyncRepository>(
+        &self,
+        repo: &T,
+    ) -> Result<models::BudgetManager, ConcurrencyConflict> {
+        retrying_async(self.retries, || async {
+            let mut budget_manager = repo.get(&self.budget_id).await;
+
+            self.apply(&mut budget_manager);
+
+            repo.add(budget_manager).await
+        })
+        .await
+    }
+
+    fn apply(&self, budget_manager: &mut models::BudgetManager) {
+        budget_manager.set_alert_thresholds(self.thresholds.clone());
+    }
+}
+
+/// Sets the people sharing a budget
+pub struct SetBudgetMembers {
+    budget_id: models::BudgetId,
+    members: Vec<String>,
+    retries: u32,
+}
+
+impl SetBudgetMembers {
+    pub fn new(budget_id: models::BudgetId, members: Vec<String>) -> Result<Self, ValidationError> {
+        members
+            .iter()
+            .fold(Validator::new(), |v, m| v.check(validation::name("member", m)))
+            .finish()?;
+
+        Ok(SetBudgetMembers {
+            budget_id,
+            members,
+            retries: 0,
+        })
+    }
+
+    /// Tries again this many times when the budget was saved by someone else first
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn run<T: repository::Repository>(&self, repo: &T) -> Result<models::BudgetManager, ConcurrencyConflict> {
+        retrying(self.retries, || {
+            let mut budget_manager = repo.get(&self.budget_id);
+
+            self.apply(&mut budget_manager);
+
+            repo.add(&budget_manager)?;
+
+            Ok(budget_manager)
+        })
+    }
+
+    pub async fn run_async<T: async_repository::AsyncRepository>(
+        &self,
+        repo: &T,
+    ) -> Result<models::BudgetManager, ConcurrencyConflict> {
+        retrying_async(self.retries, || async {
+            let mut budget_manager = repo.get(&self.budget_id).await;
+
+            self.apply(&mut budget_manager);
+
+            repo.add(budget_manager).await
+        })
+        .await
+    }
+
+    fn apply(&self, budget_manager: &mut models::BudgetManager) {
+        budget_manager.set_members(self.members.clone());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransactionAdded {
+    pub transaction_id: models::TransactionId,
+    /// Spending thresholds the new transaction pushed the budget past
+    pub alerts: Vec<models::Alert>,
 }
 
 pub struct AddTransaction {
-    budget_id: String,
+    budget_id: models::BudgetId,
     name: String,
     value: f64,
+    date: Option<NaiveDate>,
+    account_id: Option<String>,
+    paid_by: Option<String>,
+    split: Option<models::Split>,
+    external_id: Option<String>,
+    retries: u32,
 }
 
 impl AddTransaction {
-    pub fn new(budget_id: String, name: String, value: f64) -> Self {
-        AddTransaction {
+    pub fn new(budget_id: models::BudgetId, name: String, value: f64) -> Result<Self, ValidationError> {
+        Validator::new()
+            .check(validation::name("name", &name))
+            .check(validation::positive("amount", value))
+            .finish()?;
+
+        Ok(AddTransaction {
             budget_id,
             name,
             value,
-        }
+            date: None,
+            account_id: None,
+            paid_by: None,
+            split: None,
+            external_id: None,
+            retries: 0,
+        })
     }
 
-    pub fn run<T: repository::Repository>(&self, repo: &T) -> String {
-        let mut budget_manager = repo.get(&self.budget_id);
+    /// Records who paid for a transaction on a shared budget and how the cost is split,
+    /// the cost is split equally when no split is given
+    pub fn with_split(mut self, paid_by: Option<String>, split: Option<models::Split>) -> Self {
+        self.paid_by = paid_by;
+        self.split = split;
+        self
+    }
 
-        let tx_id = budget_manager.add_tx(self.name.clone(), self.value);
+    /// Dates the transaction, defaults to today
+    pub fn with_date(mut self, date: Option<NaiveDate>) -> Self {
+        self.date = date;
+        self
+    }
 
-        repo.add(&budget_manager);
+    /// Records the account the transaction was paid from
+    pub fn with_account(mut self, account_id: Option<String>) -> Self {
+        self.account_id = account_id;
+        self
+    }
 
-        tx_id
+    /// Records the id the bank gave the transaction, e.g., an OFX FITID
+    pub fn with_external_id(mut self, external_id: Option<String>) -> Self {
+        self.external_id = external_id;
+        self
     }
-}
 
-pub struct RemoveTransaction {
-    budget_id: String,
-    transaction_id: String,
-}
+    /// Tries again this many times when the budget was saved by someone else first
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
 
-impl RemoveTransaction {
-    pub fn new(budget_id: String, transaction_id: String) -> Self {
-        RemoveTransaction {
-            budget_id,
-            transaction_id,
+    pub fn run<T>(
+        &self,
+        repo: &T,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Result<TransactionAdded, ConcurrencyConflict>
+    where
+        T: repository::Repository + repository::PayeeRepository + repository::AccountRepository,
+    {
+        let today = clock.today();
+
+        if let Some(account_id) = &self.account_id {
+            self.check_account(repo.get_account(account_id));
         }
+
+        let payee_id = self.payee_id(&repo.list_payees());
+
+        retrying(self.retries, || {
+            let mut budget_manager = repo.get(&self.budget_id);
+
+            let added = self.apply(&mut budget_manager, payee_id.clone(), ids, today);
+
+            repo.add(&budget_manager)?;
+
+            Ok(added)
+        })
     }
 
-    pub fn run<T: repository::Repository>(&self, repo: &T) {
-        let mut budget_manager = repo.get(&self.budget_id);
+    pub async fn run_async<T>(
+        &self,
+        repo: &T,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Result<TransactionAdded, ConcurrencyConflict>
+    where
+        T: async_repository::AsyncRepository
+            + async_repository::AsyncPayeeRepository
+            + async_repository::AsyncAccountRepository,
+    {
+        let today = clock.today();
+
+        if let Some(account_id) = &self.account_id {
+            self.check_account(repo.get_account(account_id).await);
+        }
+
+        let payee_id = self.payee_id(&repo.list_payees().await);
+
+        retrying_async(self.retries, || async {
+            let mut budget_manager = repo.get(&self.budget_id).await;
+
+            let added = self.apply(&mut budget_manager, payee_id.clone(), ids, today);
 
-        budget_manager.remove_tx(&self.transaction_id);
+            repo.add(budget_manager).await?;
 
-        repo.add(&budget_manager)
+            Ok(added)
+        })
+        .await
+    }
+
+    fn check_account(&self, account: Option<models::Account>) {
+        if account.is_none() {
+            panic!("Account not found")
+        }
+    }
+
+    /// Attach the transaction to a known payee when its name is one of the payee's aliases
+    fn payee_id(&self, payees: &[models::Payee]) -> Option<String> {
+        payees
+            .iter()
+            .find(|payee| payee.matches(&self.name))
+            .map(|payee| payee.id().to_string())
+    }
+
+    fn apply(
+        &self,
+        budget_manager: &mut models::BudgetManager,
+        payee_id: Option<String>,
+        ids: &dyn IdGenerator,
+        today: NaiveDate,
+    ) -> TransactionAdded {
+        let shares = match (&self.paid_by, &self.split) {
+            (Some(_), split) => budget_manager.split(self.value, split.as_ref().unwrap_or(&models::Split::Equal)),
+            (None, Some(_)) => panic!("A split transaction needs someone who paid"),
+            (None, None) => vec![],
+        };
+
+        let date = self.date.unwrap_or(today);
+        let tx = models::Transaction::new(ids, self.name.clone(), self.value, *budget_manager.id(), date)
+            .with_payee(payee_id)
+            .with_account(self.account_id.clone())
+            .with_paid_by(self.paid_by.clone())
+            .with_shares(shares)
+            .with_external_id(self.external_id.clone());
+        let transaction_id = budget_manager.add_transaction(tx);
+        let alerts = budget_manager.check_alerts(today);
+
+        TransactionAdded {
+            transaction_id,
+            alerts,
+        }
     }
 }
 
-pub struct UpdateTransaction {
-    budget_id: String,
-    transaction_id: String,
-    new_val: f64,
+/// Adds many transactions to a budget in a single save, so either all of them are added or none.
+/// A transaction whose external id is already stored for the same account, in any budget,
+/// was imported before and is left out
+pub struct ImportTransactions {
+    budget_id: models::BudgetId,
+    transactions: Vec<AddTransaction>,
+    retries: u32,
 }
 
-impl UpdateTransaction {
-    pub fn new(budget_id: String, transaction_id: String, new_val: f64) -> Self {
-        UpdateTransaction {
+impl ImportTransactions {
+    /// Panics when one of the transactions is for another budget
+    pub fn new(budget_id: models::BudgetId, transactions: Vec<AddTransaction>) -> Self {
+        if transactions.iter().any(|tx| tx.budget_id != budget_id) {
+            panic!("Every imported transaction must be for budget {}", budget_id)
+        }
+
+        ImportTransactions {
             budget_id,
-            transaction_id,
-            new_val,
+            transactions,
+            retries: 0,
         }
     }
 
-    pub fn run<T: repository::Repository>(&self, repo: &T) {
-        let mut budget_manager = repo.get(&self.budget_id);
+    /// Tries again this many times when the budget was saved by someone else first
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
 
-        budget_manager.update_tx(&self.transaction_id, self.new_val);
+    pub fn run<T>(
+        &self,
+        repo: &T,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Result<Vec<TransactionAdded>, ConcurrencyConflict>
+    where
+        T: repository::Repository + repository::PayeeRepository + repository::AccountRepository,
+    {
+        let today = clock.today();
+
+        for tx in &self.transactions {
+            if let Some(account_id) = &tx.account_id {
+                tx.check_account(repo.get_account(account_id));
+            }
+        }
 
-        repo.add(&budget_manager);
+        let payees = repo.list_payees();
+        let payee_ids: Vec<Option<String>> = self.transactions.iter().map(|tx| tx.payee_id(&payees)).collect();
+
+        retrying(self.retries, || {
+            let mut budget_manager = repo.get(&self.budget_id);
+            let imported = repo.external_ids();
+
+            let added = self.apply(&mut budget_manager, imported, &payee_ids, ids, today);
+
+            repo.add(&budget_manager)?;
+
+            Ok(added)
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::adapters::repository::{self, Repository};
-    use std::cell::RefCell;
-    use std::collections::HashMap;
+    pub async fn run_async<T>(
+        &self,
+        repo: &T,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Result<Vec<TransactionAdded>, ConcurrencyConflict>
+    where
+        T: async_repository::AsyncRepository
+            + async_repository::AsyncPayeeRepository
+            + async_repository::AsyncAccountRepository,
+    {
+        let today = clock.today();
 
-    fn budget_name() -> String {
-        String::from("my-budget")
+        for tx in &self.transactions {
+            if let Some(account_id) = &tx.account_id {
+                tx.check_account(repo.get_account(account_id).await);
+            }
+        }
+
+        let payees = repo.list_payees().await;
+        let payee_ids: Vec<Option<String>> = self.transactions.iter().map(|tx| tx.payee_id(&payees)).collect();
+
+        retrying_async(self.retries, || async {
+            let mut budget_manager = repo.get(&self.budget_id).await;
+            let imported = repo.external_ids().await;
+
+            let added = self.apply(&mut budget_manager, imported, &payee_ids, ids, today);
+
+            repo.add(budget_manager).await?;
+
+            Ok(added)
+        })
+        .await
     }
 
-    fn budget_max() -> f64 {
-        200.00_f64
+    /// Adds the transactions that weren't `imported` before, as account id and external id
+    fn apply(
+        &self,
+        budget_manager: &mut models::BudgetManager,
+        imported: Vec<(Option<String>, String)>,
+        payee_ids: &[Option<String>],
+        ids: &dyn IdGenerator,
+        today: NaiveDate,
+    ) -> Vec<TransactionAdded> {
+        let mut imported: HashSet<(Option<String>, String)> = imported.into_iter().collect();
+
+        add_new_transactions(budget_manager, &self.transactions, &mut imported, payee_ids, ids, today)
     }
+}
 
-    fn make_empty_budget_manager() -> models::BudgetManager {
-        models::BudgetManager::new(
-            models::Budget::new(budget_name(), budget_max()),
-            RefCell::new(vec![]),
-        )
+/// Adds the transactions that aren't in `imported` yet, as account id and external id, and puts them in it
+fn add_new_transactions(
+    budget_manager: &mut models::BudgetManager,
+    transactions: &[AddTransaction],
+    imported: &mut HashSet<(Option<String>, String)>,
+    payee_ids: &[Option<String>],
+    ids: &dyn IdGenerator,
+    today: NaiveDate,
+) -> Vec<TransactionAdded> {
+    transactions
+        .iter()
+        .zip(payee_ids)
+        .filter(|(tx, _)| match &tx.external_id {
+            Some(external_id) => imported.insert((tx.account_id.clone(), external_id.clone())),
+            None => true,
+        })
+        .map(|(tx, payee_id)| tx.apply(budget_manager, payee_id.clone(), ids, today))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CategoryImported {
+    pub category: String,
+    pub budget_id: models::BudgetId,
+    /// Total the budget was created with, `None` when it was stored already
+    pub created_with: Option<f64>,
+    pub added: Vec<TransactionAdded>,
+}
+
+/// Adds transactions to the budgets named like their categories, ignoring case. A category without a budget
+/// gets a new one with a total that covers its transactions, assigned from the income pool. The budgets are saved
+/// together with the pool, so either everything is added or nothing. Transactions imported before are left out
+/// like `ImportTransactions` leaves them out
+pub struct ImportByCategory {
+    categories: Vec<(String, Vec<AddTransaction>)>,
+    retries: u32,
+}
+
+impl ImportByCategory {
+    /// Each category with its transactions, a transaction goes to its category's budget whichever budget it was made for
+    pub fn new(categories: Vec<(String, Vec<AddTransaction>)>) -> Self {
+        ImportByCategory { categories, retries: 0 }
     }
 
-    // Fake repository
-    struct InMemoryRepository {
-        budgets: RefCell<HashMap<String, models::BudgetManager>>,
+    /// Tries again this many times when a budget or the income pool was saved by someone else first
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
     }
 
-    impl InMemoryRepository {
-        fn new() -> Self {
-            InMemoryRepository {
-                budgets: RefCell::new(HashMap::new()),
+    pub fn run<T>(&self, repo: &T, ids: &dyn IdGenerator, clock: &dyn Clock) -> Result<Vec<CategoryImported>, CommandError>
+    where
+        T: repository::Repository
+            + repository::PayeeRepository
+            + repository::AccountRepository
+            + repository::IncomeRepository
+            + repository::ViewRepository,
+    {
+        let today = clock.today();
+
+        for (_, transactions) in &self.categories {
+            for tx in transactions {
+                if let Some(account_id) = &tx.account_id {
+                    tx.check_account(repo.get_account(account_id));
+                }
             }
         }
+
+        let payees = repo.list_payees();
+
+        let imported = retrying(self.retries, || {
+            let mut pool = repo.get_income_pool();
+            let stored = repo.get_many(&self.stored_ids(&repo.list_budgets()));
+
+            let (budget_managers, imported) =
+                match self.apply(stored, &mut pool, repo.external_ids(), &payees, ids, today) {
+                    Ok(applied) => applied,
+                    Err(e) => return Ok(Err(e)),
+                };
+
+            repo.add_funded(&budget_managers, &pool)?;
+
+            Ok(Ok(imported))
+        })??;
+
+        Ok(imported)
     }
 
-    impl repository::Repository for InMemoryRepository {
-        fn add(&self, item: &models::BudgetManager) {
-            self.budgets
-                .borrow_mut()
-                .insert(item.id().to_string(), item.clone());
+    pub async fn run_async<T>(
+        &self,
+        repo: &T,
+        ids: &dyn IdGenerator,
+        clock: &dyn Clock,
+    ) -> Result<Vec<CategoryImported>, CommandError>
+    where
+        T: async_repository::AsyncRepository
+            + async_repository::AsyncPayeeRepository
+            + async_repository::AsyncAccountRepository
+            + async_repository::AsyncIncomeRepository
+            + async_repository::AsyncViewRepository,
+    {
+        let today = clock.today();
+
+        for (_, transactions) in &self.categories {
+            for tx in transactions {
+                if let Some(account_id) = &tx.account_id {
+                    tx.check_account(repo.get_account(account_id).await);
+                }
+            }
         }
 
-        fn get(&self, id: &str) -> models::BudgetManager {
-            let budgets = self.budgets.borrow();
-            let budget = budgets.get(id);
+        let payees = repo.list_payees().await;
 
-            let budg = match budget {
-                Some(result) => result.clone(),
-                _ => panic!("Budget not found"),
+        let imported = retrying_async(self.retries, || async {
+            let mut pool = repo.get_income_pool().await;
+            let stored = repo.get_many(&self.stored_ids(&repo.list_budgets().await)).await;
+            let external_ids = repo.external_ids().await;
+
+            let (budget_managers, imported) = match self.apply(stored, &mut pool, external_ids, &payees, ids, today) {
+                Ok(applied) => applied,
+                Err(e) => return Ok(Err(e)),
             };
 
-            budg
-        }
+            repo.add_funded(budget_managers, pool).await?;
 
-        fn delete(&self, id: &str) {
-            let mut budgets = self.budgets.borrow_mut();
+            Ok(Ok(imported))
+        })
+        .await??;
 
-            budgets.remove(id).unwrap();
-        }
+        Ok(imported)
     }
 
-    #[test]
-    fn user_can_create_budget() {
-        // Given
-        let cmd = CreateBudget::new(budget_name(), budget_max());
-        let repo = InMemoryRepository::new();
+    /// Ids of the stored budgets named like one of the categories, oldest first
+    fn stored_ids(&self, budgets: &[models::Budget]) -> Vec<models::BudgetId> {
+        budgets
+            .iter()
+            .filter(|budget| self.categories.iter().any(|(category, _)| budget.name().eq_ignore_ascii_case(category)))
+            .map(|budget| *budget.id())
+            .collect()
+    }
 
-        // When
-        let bdg = cmd.run(&repo);
+    /// The budgets to save with the transactions added, new ones funded from `pool`,
+    /// fails without a change to anything stored when the pool can't fund them
+    fn apply(
+        &self,
+        stored: Vec<Option<models::BudgetManager>>,
+        pool: &mut models::IncomePool,
+        imported: Vec<(Option<String>, String)>,
+        payees: &[models::Payee],
+        ids: &dyn IdGenerator,
+        today: NaiveDate,
+    ) -> Result<(Vec<models::BudgetManager>, Vec<CategoryImported>), ValidationError> {
+        let mut stored: Vec<models::BudgetManager> = stored.into_iter().flatten().collect();
+        let mut imported: HashSet<(Option<String>, String)> = imported.into_iter().collect();
+        let mut budget_managers: Vec<models::BudgetManager> = vec![];
+        let mut results = vec![];
 
-        // Then
-        // The budget was created with the expected name and total
-        assert_eq!(bdg.name(), budget_name());
-        assert_eq!(bdg.available_funds(), budget_max());
+        for (category, transactions) in &self.categories {
+            let named = |bm: &models::BudgetManager| bm.name().eq_ignore_ascii_case(category);
 
-        // We can retrieve the budget from the repository
-        // and the value is equal to the value return from `run()`
-        assert_eq!(repo.get(bdg.id()), bdg);
-    }
+            // Categories that differ only in case share a budget, which is saved once
+            let (idx, created_with) = match budget_managers.iter().position(named) {
+                Some(idx) => (idx, None),
+                None => match stored.iter().position(named) {
+                    Some(idx) => {
+                        budget_managers.push(stored.remove(idx));
+                        (budget_managers.len() - 1, None)
+                    }
+                    None => {
+                        // Rounded up to a whole amount, a total of exactly the spending could fall short by a rounding error
+                        let spent: f64 = self
+                            .categories
+                            .iter()
+                            .filter(|(other, _)| other.eq_ignore_ascii_case(category))
+                            .flat_map(|(_, transactions)| transactions.iter().map(|tx| tx.value))
+                            .sum();
+                        let total = (spent + 0.01_f64).ceil();
+                        budget_managers.push(CreateBudget::new(category.clone(), total)?.build(pool, ids)?);
+                        (budget_managers.len() - 1, Some(total))
+                    }
+                },
+            };
 
-    #[test]
-    fn user_can_add_transaction() {
-        // Given
-        // Set up data required to run the test
-        let budget_manager = make_empty_budget_manager();
-        let repo = InMemoryRepository::new();
-        repo.add(&budget_manager);
+            let payee_ids: Vec<Option<String>> = transactions.iter().map(|tx| tx.payee_id(payees)).collect();
+            let budget_manager = &mut budget_managers[idx];
+            let added = add_new_transactions(budget_manager, transactions, &mut imported, &payee_ids, ids, today);
 
-        // Set up the command
-        let cmd = AddTransaction::new(
-            budget_manager.id().to_string(),
-            String::from("cheeseborger"),
-            9.99_f64,
-        );
+            results.push(CategoryImported {
+                category: category.clone(),
+                budget_id: *budget_manager.id(),
+                created_with,
+                added,
+            });
+        }
 
-        // When
-        cmd.run(&repo);
+        Ok((budget_managers, results))
+    }
+}
 
-        // Then
-        let bm = repo.get(budget_manager.id());
-        assert_eq!(bm.available_funds(), 190.01_f64);
+pub struct RemoveTransaction {
+    budget_id: models::BudgetId,
+    transaction_id: models::TransactionId,
+    retries: u32,
+}
+
+impl RemoveTransaction {
+    pub fn new(budget_id: models::BudgetId, transaction_id: models::TransactionId) -> Result<Self, ValidationError> {
+        Ok(RemoveTransaction {
+            budget_id,
+            transaction_id,
+            retries: 0,
+        })
     }
 
-    #[test]
-    fn user_can_remove_transaction() {
-        // Given
-        // Set up data required to run the test
-        let mut budget_manager = make_empty_budget_manager();
-        let transaction_id = budget_manager.add_tx(String::from("cheeseborger"), 3.99_f64);
+    /// Tries again this many times when the budget was saved by someone else first
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
 
-        let repo = InMemoryRepository::new();
-        repo.add(&budget_manager);
+    pub fn run<T: repository::Repository>(&self, repo: &T) -> Result<(), ConcurrencyConflict> {
+        retrying(self.retries, || {
+            let mut budget_manager = repo.get(&self.budget_id);
 
-        // Set up the command we're going to test!
-        let cmd =
-            RemoveTransaction::new(budget_manager.id().to_string(), transaction_id);
+            budget_manager.remove_tx(&self.transaction_id);
 
-        // When
-        cmd.run(&repo);
+            repo.add(&budget_manager)
+        })
+    }
 
-        // Then
-        let bm = repo.get(budget_manager.id());
-        assert_eq!(bm.available_funds(), budget_max());
+    pub async fn run_async<T: async_repository::AsyncRepository>(&self, repo: &T) -> Result<(), ConcurrencyConflict> {
+        retrying_async(self.retries, || async {
+            let mut budget_manager = repo.get(&self.budget_id).await;
+
+            budget_manager.remove_tx(&self.transaction_id);
+
+            repo.add(budget_manager).await.map(|_| ())
+        })
+        .await
     }
+}
 
-    #[test]
-    fn user_can_update_transaction() {
-        // Given
-        // Set up data required to run the test
-        let mut budget_manager = make_empty_budget_manager();
-        let transaction_id = budget_manager.add_tx(String::from("cheeseborger"), 3.99_f64);
+pub struct UpdateTransaction {
+    budget_id: models::BudgetId,
+    transaction_id: models::TransactionId,
+    new_val: f64,
+    retries: u32,
+}
 
-        let repo = InMemoryRepository::new();
-        repo.add(&budget_manager);
+impl UpdateTransaction {
+    pub fn new(budget_id: models::BudgetId, transaction_id: models::TransactionId, new_val: f64) -> Result<Self, ValidationError> {
+        Validator::new()
+            .check(validation::positive("amount", new_val))
+            .finish()?;
 
-        // Set up the command we're going to test
-        let cmd = UpdateTransaction::new(
-            budget_manager.id().to_string(),
+        Ok(UpdateTransaction {
+            budget_id,
             transaction_id,
-            4.99_f64,
-        );
+            new_val,
+            retries: 0,
+        })
+    }
 
-        // When
-        cmd.run(&repo);
+    /// Tries again this many times when the budget was saved by someone else first
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn run<T: repository::Repository>(
+        &self,
+        repo: &T,
+        clock: &dyn Clock,
+    ) -> Result<Vec<models::Alert>, ConcurrencyConflict> {
+        retrying(self.retries, || {
+            let mut budget_manager = repo.get(&self.budget_id);
+
+            let alerts = self.apply(&mut budget_manager, clock.today());
+
+            repo.add(&budget_manager)?;
+
+            Ok(alerts)
+        })
+    }
+
+    pub async fn run_async<T: async_repository::AsyncRepository>(
+        &self,
+        repo: &T,
+        clock: &dyn Clock,
+    ) -> Result<Vec<models::Alert>, ConcurrencyConflict> {
+        retrying_async(self.retries, || async {
+            let mut budget_manager = repo.get(&self.budget_id).await;
+
+            let alerts = self.apply(&mut budget_manager, clock.today());
+
+            repo.add(budget_manager).await?;
+
+            Ok(alerts)
+        })
+        .await
+    }
+
+    fn apply(&self, budget_manager: &mut models::BudgetManager, today: NaiveDate) -> Vec<models::Alert> {
+        budget_manager.update_tx(&self.transaction_id, self.new_val);
+
+        budget_manager.check_alerts(today)
+    }
+}
+
+pub struct SetTransactionStatus {
+    budget_id: models::BudgetId,
+    transaction_id: models::TransactionId,
+    status: models::TransactionStatus,
+    retries: u32,
+}
+
+impl SetTransactionStatus {
+    pub fn new(
+        budget_id: models::BudgetId,
+        transaction_id: models::TransactionId,
+        status: models::TransactionStatus,
+    ) -> Result<Self, ValidationError> {
+        Ok(SetTransactionStatus {
+            budget_id,
+            transaction_id,
+            status,
+            retries: 0,
+        })
+    }
+
+    /// Tries again this many times when the budget was saved by someone else first
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn run<T: repository::Repository>(&self, repo: &T) -> Result<(), ConcurrencyConflict> {
+        retrying(self.retries, || {
+            let mut budget_manager = repo.get(&self.budget_id);
+
+            budget_manager.set_tx_status(&self.transaction_id, self.status);
+
+            repo.add(&budget_manager)
+        })
+    }
+
+    pub async fn run_async<T: async_repository::AsyncRepository>(&self, repo: &T) -> Result<(), ConcurrencyConflict> {
+        retrying_async(self.retries, || async {
+            let mut budget_manager = repo.get(&self.budget_id).await;
+
+            budget_manager.set_tx_status(&self.transaction_id, self.status);
+
+            repo.add(budget_manager).await.map(|_| ())
+        })
+        .await
+    }
+}
+
+pub struct CreatePayee {
+    name: String,
+    aliases: Vec<String>,
+}
+
+impl CreatePayee {
+    pub fn new(name: String, aliases: Vec<String>) -> Result<Self, ValidationError> {
+        aliases
+            .iter()
+            .fold(Validator::new(), |v, a| v.check(validation::name("alias", a)))
+            .check(validation::name("name", &name))
+            .finish()?;
+
+        Ok(CreatePayee { name, aliases })
+    }
+
+    pub fn run<T: repository::PayeeRepository>(
+        &self,
+        repo: &T,
+        ids: &dyn IdGenerator,
+    ) -> Result<models::Payee, ValidationError> {
+        let payee = self.build(ids);
+        check_payee_is_unique(&payee, &repo.list_payees())?;
+
+        repo.add_payee(&payee);
+
+        Ok(payee)
+    }
+
+    pub async fn run_async<T: async_repository::AsyncPayeeRepository>(
+        &self,
+        repo: &T,
+        ids: &dyn IdGenerator,
+    ) -> Result<models::Payee, ValidationError> {
+        let payee = self.build(ids);
+        check_payee_is_unique(&payee, &repo.list_payees().await)?;
+
+        repo.add_payee(&payee).await;
+
+        Ok(payee)
+    }
+
+    fn build(&self, ids: &dyn IdGenerator) -> models::Payee {
+        models::Payee::new(ids, self.name.clone(), self.aliases.clone())
+    }
+}
+
+/// No two payees may share a name or alias, otherwise a transaction's name could match either of them
+fn check_payee_is_unique(payee: &models::Payee, payees: &[models::Payee]) -> Result<(), ValidationError> {
+    let taken = |field: &'static str, name: &str| match payees
+        .iter()
+        .find(|other| other.id() != payee.id() && other.matches(name))
+    {
+        Some(other) => Err(FieldError::new(field, format!("{} is already used by payee {}", name, other.name()))),
+        None => Ok(()),
+    };
+
+    payee
+        .aliases()
+        .iter()
+        .fold(Validator::new().check(taken("name", payee.name())), |v, a| v.check(taken("alias", a)))
+        .finish()
+}
+
+pub struct AddPayeeAlias {
+    payee_id: String,
+    alias: String,
+}
+
+impl AddPayeeAlias {
+    pub fn new(payee_id: String, alias: String) -> Result<Self, ValidationError> {
+        Validator::new()
+            .check(validation::id("id", &payee_id))
+            .check(validation::name("alias", &alias))
+            .finish()?;
+
+        Ok(AddPayeeAlias { payee_id, alias })
+    }
+
+    pub fn run<T: repository::PayeeRepository>(&self, repo: &T) -> Result<models::Payee, ValidationError> {
+        let payee = self.apply(repo.get_payee(&self.payee_id));
+        check_payee_is_unique(&payee, &repo.list_payees())?;
+
+        repo.add_payee(&payee);
+
+        Ok(payee)
+    }
+
+    pub async fn run_async<T: async_repository::AsyncPayeeRepository>(
+        &self,
+        repo: &T,
+    ) -> Result<models::Payee, ValidationError> {
+        let payee = self.apply(repo.get_payee(&self.payee_id).await);
+        check_payee_is_unique(&payee, &repo.list_payees().await)?;
+
+        repo.add_payee(&payee).await;
+
+        Ok(payee)
+    }
+
+    fn apply(&self, payee: Option<models::Payee>) -> models::Payee {
+        let mut payee = match payee {
+            Some(payee) => payee,
+            _ => panic!("Payee not found"),
+        };
+
+        payee.add_alias(self.alias.clone());
+
+        payee
+    }
+}
+
+pub struct CreateAccount {
+    name: String,
+    kind: models::AccountKind,
+    opening_balance: f64,
+}
+
+impl CreateAccount {
+    pub fn new(name: String, kind: models::AccountKind, opening_balance: f64) -> Result<Self, ValidationError> {
+        Validator::new()
+            .check(validation::name("name", &name))
+            .check(validation::finite("opening_balance", opening_balance))
+            .finish()?;
+
+        Ok(CreateAccount {
+            name,
+            kind,
+            opening_balance,
+        })
+    }
+
+    pub fn run<T: repository::AccountRepository>(&self, repo: &T, ids: &dyn IdGenerator) -> models::Account {
+        let account = self.build(ids);
+
+        repo.add_account(&account);
+
+        account
+    }
+
+    pub async fn run_async<T: async_repository::AsyncAccountRepository>(
+        &self,
+        repo: &T,
+        ids: &dyn IdGenerator,
+    ) -> models::Account {
+        let account = self.build(ids);
+
+        repo.add_account(&account).await;
+
+        account
+    }
+
+    fn build(&self, ids: &dyn IdGenerator) -> models::Account {
+        models::Account::new(ids, self.name.clone(), self.kind, self.opening_balance)
+    }
+}
+
+/// Compares an account's cleared balance with a bank statement,
+/// when they agree every cleared transaction on the account is marked as reconciled
+pub struct ReconcileAccount {
+    account_id: String,
+    statement_balance: f64,
+    retries: u32,
+}
+
+impl ReconcileAccount {
+    pub fn new(account_id: String, statement_balance: f64) -> Result<Self, ValidationError> {
+        Validator::new()
+            .check(validation::id("account", &account_id))
+            .check(validation::finite("statement_balance", statement_balance))
+            .finish()?;
+
+        Ok(ReconcileAccount {
+            account_id,
+            statement_balance,
+            retries: 0,
+        })
+    }
+
+    /// Tries again this many times when a budget was saved by someone else first
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn run<T>(&self, repo: &T) -> Result<models::Reconciliation, ConcurrencyConflict>
+    where
+        T: repository::Repository + repository::AccountRepository,
+    {
+        let account = self.found(repo.get_account(&self.account_id));
+
+        retrying(self.retries, || {
+            // A budget deleted since its id was read has nothing left to reconcile
+            let budget_managers = repo.get_many(&repo.account_budget_ids(&self.account_id)).into_iter().flatten().collect();
+
+            let (reconciliation, changed) = self.reconcile(&account, budget_managers);
+
+            repo.add_all(&changed)?;
+
+            Ok(reconciliation)
+        })
+    }
+
+    pub async fn run_async<T>(&self, repo: &T) -> Result<models::Reconciliation, ConcurrencyConflict>
+    where
+        T: async_repository::AsyncRepository + async_repository::AsyncAccountRepository,
+    {
+        let account = self.found(repo.get_account(&self.account_id).await);
+
+        retrying_async(self.retries, || async {
+            let budget_managers = repo
+                .get_many(&repo.account_budget_ids(&self.account_id).await)
+                .await
+                .into_iter()
+                .flatten()
+                .collect();
+
+            let (reconciliation, changed) = self.reconcile(&account, budget_managers);
+
+            repo.add_all(changed).await?;
+
+            Ok(reconciliation)
+        })
+        .await
+    }
+
+    fn found(&self, account: Option<models::Account>) -> models::Account {
+        match account {
+            Some(account) => account,
+            _ => panic!("Account not found"),
+        }
+    }
+
+    /// Reconciles the budgets when the balances agree, gives back the budgets that need saving
+    fn reconcile(
+        &self,
+        account: &models::Account,
+        budget_managers: Vec<models::BudgetManager>,
+    ) -> (models::Reconciliation, Vec<models::BudgetManager>) {
+        let cleared_balance = {
+            let txs: Vec<models::Transaction> = budget_managers
+                .iter()
+                .flat_map(|bm| bm.transactions().borrow().clone())
+                .collect();
+
+            account.cleared_balance(txs.iter())
+        };
+
+        let mut reconciliation =
+            models::Reconciliation::new(self.account_id.clone(), self.statement_balance, cleared_balance);
+
+        if !reconciliation.is_balanced() {
+            return (reconciliation, vec![]);
+        }
+
+        let mut changed = vec![];
+        for mut budget_manager in budget_managers {
+            let reconciled = budget_manager.reconcile_account(&self.account_id);
+
+            if !reconciled.is_empty() {
+                reconciliation.reconciled.extend(reconciled);
+                changed.push(budget_manager);
+            }
+        }
+
+        (reconciliation, changed)
+    }
+}
+
+pub struct AddIncome {
+    source: String,
+    amount: f64,
+    retries: u32,
+}
+
+impl AddIncome {
+    pub fn new(source: String, amount: f64) -> Result<Self, ValidationError> {
+        Validator::new()
+            .check(validation::name("source", &source))
+            .check(validation::positive("amount", amount))
+            .finish()?;
+
+        Ok(AddIncome {
+            source,
+            amount,
+            retries: 0,
+        })
+    }
+
+    /// Tries again this many times when the income pool was saved by someone else first
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn run<T: repository::IncomeRepository>(
+        &self,
+        repo: &T,
+        ids: &dyn IdGenerator,
+    ) -> Result<models::Income, ConcurrencyConflict> {
+        retrying(self.retries, || {
+            let mut pool = repo.get_income_pool();
+
+            let income = self.apply(&mut pool, ids);
+
+            repo.add_income_pool(&pool)?;
+
+            Ok(income)
+        })
+    }
+
+    pub async fn run_async<T: async_repository::AsyncIncomeRepository>(
+        &self,
+        repo: &T,
+        ids: &dyn IdGenerator,
+    ) -> Result<models::Income, ConcurrencyConflict> {
+        retrying_async(self.retries, || async {
+            let mut pool = repo.get_income_pool().await;
+
+            let income = self.apply(&mut pool, ids);
+
+            repo.add_income_pool(pool).await?;
+
+            Ok(income)
+        })
+        .await
+    }
+
+    fn apply(&self, pool: &mut models::IncomePool, ids: &dyn IdGenerator) -> models::Income {
+        let income = models::Income::new(ids, self.source.clone(), self.amount);
+        pool.add_income(income.clone());
+
+        income
+    }
+}
+
+/// Gives unassigned income a job by moving it into a budget
+pub struct AssignFunds {
+    budget_id: models::BudgetId,
+    amount: f64,
+    retries: u32,
+}
+
+impl AssignFunds {
+    pub fn new(budget_id: models::BudgetId, amount: f64) -> Result<Self, ValidationError> {
+        Validator::new()
+            .check(validation::positive("amount", amount))
+            .finish()?;
+
+        Ok(AssignFunds {
+            budget_id,
+            amount,
+            retries: 0,
+        })
+    }
+
+    /// Tries again this many times when the budget was saved by someone else first
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn run<T>(&self, repo: &T, ids: &dyn IdGenerator) -> Result<models::BudgetManager, CommandError>
+    where
+        T: repository::Repository + repository::IncomeRepository,
+    {
+        let budget_manager = retrying(self.retries, || {
+            let mut pool = repo.get_income_pool();
+            let mut budget_manager = repo.get(&self.budget_id);
+
+            if let Err(e) = self.apply(&mut pool, &mut budget_manager, ids) {
+                return Ok(Err(e));
+            }
+
+            repo.add_funded(std::slice::from_ref(&budget_manager), &pool)?;
+
+            Ok(Ok(budget_manager))
+        })??;
+
+        Ok(budget_manager)
+    }
+
+    pub async fn run_async<T>(
+        &self,
+        repo: &T,
+        ids: &dyn IdGenerator,
+    ) -> Result<models::BudgetManager, CommandError>
+    where
+        T: async_repository::AsyncRepository + async_repository::AsyncIncomeRepository,
+    {
+        let budget_manager = retrying_async(self.retries, || async {
+            let mut pool = repo.get_income_pool().await;
+            let mut budget_manager = repo.get(&self.budget_id).await;
+
+            if let Err(e) = self.apply(&mut pool, &mut budget_manager, ids) {
+                return Ok(Err(e));
+            }
+
+            let (mut saved, _) = repo.add_funded(vec![budget_manager], pool).await?;
+
+            Ok(Ok(saved.pop().unwrap()))
+        })
+        .await??;
+
+        Ok(budget_manager)
+    }
+
+    fn apply(
+        &self,
+        pool: &mut models::IncomePool,
+        budget_manager: &mut models::BudgetManager,
+        ids: &dyn IdGenerator,
+    ) -> Result<(), ValidationError> {
+        try_assign(pool, models::Assignment::new(ids, self.budget_id, self.amount), "amount")?;
+        budget_manager.allocate(self.amount);
+
+        Ok(())
+    }
+}
+
+/// What an import does with a budget, income or assignment whose id is already taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Keep what's stored
+    Skip,
+    /// Overwrite what's stored with the imported one
+    Replace,
+    /// Import a copy under new ids next to what's stored
+    NewIds,
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OnConflict::Skip),
+            "replace" => Ok(OnConflict::Replace),
+            "new-ids" => Ok(OnConflict::NewIds),
+            _ => Err(format!("Unknown conflict handling {}, expected skip, replace or new-ids", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ImportSummary {
+    pub budgets_added: usize,
+    pub budgets_replaced: usize,
+    pub budgets_skipped: usize,
+    pub payees_added: usize,
+    pub accounts_added: usize,
+}
+
+/// Loads a snapshot, e.g., an export, into a repository that may already hold data
+///
+/// Payees and accounts are matched by id and then by name, since names are unique,
+/// the imported transactions are pointed at the ones that are kept.
+/// Everything is saved at once, nothing is written when someone else saved a budget or the pool in the meantime
+pub struct ImportSnapshot {
+    snapshot: Snapshot,
+    on_conflict: OnConflict,
+}
+
+impl ImportSnapshot {
+    pub fn new(snapshot: Snapshot) -> Self {
+        ImportSnapshot {
+            snapshot,
+            on_conflict: OnConflict::Skip,
+        }
+    }
+
+    /// Skips budgets, income and assignments whose id is taken unless told otherwise
+    pub fn with_on_conflict(mut self, on_conflict: OnConflict) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    pub fn run<T>(&self, repo: &T, ids: &dyn IdGenerator) -> Result<ImportSummary, ConcurrencyConflict>
+    where
+        T: repository::Repository
+            + repository::PayeeRepository
+            + repository::AccountRepository
+            + repository::IncomeRepository
+            + repository::SnapshotRepository,
+    {
+        let stored_budgets = repo.get_many(&self.budget_ids());
+        let (snapshot, summary) = self.prepare_snapshot(
+            repo.list_payees(),
+            repo.list_accounts(),
+            stored_budgets,
+            repo.get_income_pool(),
+            ids,
+        );
+
+        repo.add_snapshot(&snapshot)?;
+
+        Ok(summary)
+    }
+
+    pub async fn run_async<T>(&self, repo: &T, ids: &dyn IdGenerator) -> Result<ImportSummary, ConcurrencyConflict>
+    where
+        T: async_repository::AsyncRepository
+            + async_repository::AsyncPayeeRepository
+            + async_repository::AsyncAccountRepository
+            + async_repository::AsyncIncomeRepository
+            + async_repository::AsyncSnapshotRepository,
+    {
+        let stored_budgets = repo.get_many(&self.budget_ids()).await;
+        let (snapshot, summary) = self.prepare_snapshot(
+            repo.list_payees().await,
+            repo.list_accounts().await,
+            stored_budgets,
+            repo.get_income_pool().await,
+            ids,
+        );
+
+        repo.add_snapshot(snapshot).await?;
+
+        Ok(summary)
+    }
+
+    fn budget_ids(&self) -> Vec<models::BudgetId> {
+        self.snapshot.budgets.iter().map(|bm| *bm.id()).collect()
+    }
+
+    /// Everything to save in one go, merged with what's stored, `stored_budgets` in the order of the snapshot's budgets
+    fn prepare_snapshot(
+        &self,
+        stored_payees: Vec<models::Payee>,
+        stored_accounts: Vec<models::Account>,
+        stored_budgets: Vec<Option<models::BudgetManager>>,
+        stored_pool: models::IncomePool,
+        ids: &dyn IdGenerator,
+    ) -> (Snapshot, ImportSummary) {
+        let mut summary = ImportSummary::default();
+
+        let (payees, payee_ids) = self.merge(&self.snapshot.payees, stored_payees, |p| p.id(), |p| p.name());
+        let (accounts, account_ids) = self.merge(&self.snapshot.accounts, stored_accounts, |a| a.id(), |a| a.name());
+        summary.payees_added = payees.len();
+        summary.accounts_added = accounts.len();
+
+        let mut budgets = vec![];
+        let mut budget_ids = HashMap::new();
+        for (imported, stored) in self.snapshot.budgets.iter().zip(stored_budgets) {
+            let budget_manager = self.prepare(imported, &payee_ids, &account_ids);
+
+            match (stored, self.on_conflict) {
+                (None, _) => {
+                    budgets.push(budget_manager);
+                    summary.budgets_added += 1;
+                }
+                (Some(_), OnConflict::Skip) => summary.budgets_skipped += 1,
+                (Some(stored), OnConflict::Replace) => {
+                    budgets.push(budget_manager.with_version(stored.version()));
+                    summary.budgets_replaced += 1;
+                }
+                (Some(_), OnConflict::NewIds) => {
+                    let copy = budget_manager.with_new_ids(ids);
+                    budget_ids.insert(*imported.id(), *copy.id());
+                    budgets.push(copy);
+                    summary.budgets_added += 1;
+                }
+            }
+        }
+
+        let income_pool = self.merge_pool(stored_pool, &budget_ids, ids);
+
+        (
+            Snapshot {
+                budgets,
+                payees,
+                accounts,
+                income_pool,
+            },
+            summary,
+        )
+    }
+
+    /// The imported entities that need saving, and the stored id each imported id ends up as
+    fn merge<E: Clone>(
+        &self,
+        imported: &[E],
+        stored: Vec<E>,
+        id: fn(&E) -> &str,
+        name: fn(&E) -> &str,
+    ) -> (Vec<E>, HashMap<String, String>) {
+        let mut to_save = vec![];
+        let mut ids = HashMap::new();
+
+        for entity in imported {
+            let same_id = stored.iter().find(|s| id(s) == id(entity));
+            let same_name = stored.iter().find(|s| name(s) == name(entity));
+
+            match (same_id, same_name) {
+                (Some(_), same_name) => {
+                    // Names are unique, the stored one is kept when replacing it would take another one's name
+                    let name_taken = same_name.is_some_and(|s| id(s) != id(entity));
+                    if self.on_conflict == OnConflict::Replace && !name_taken {
+                        to_save.push(entity.clone());
+                    }
+                    ids.insert(id(entity).to_string(), id(entity).to_string());
+                }
+                (None, Some(stored)) => {
+                    ids.insert(id(entity).to_string(), id(stored).to_string());
+                }
+                (None, None) => {
+                    to_save.push(entity.clone());
+                    ids.insert(id(entity).to_string(), id(entity).to_string());
+                }
+            }
+        }
+
+        (to_save, ids)
+    }
+
+    /// A budget to save as new, its transactions pointed at the payees and accounts that are kept
+    fn prepare(
+        &self,
+        imported: &models::BudgetManager,
+        payee_ids: &HashMap<String, String>,
+        account_ids: &HashMap<String, String>,
+    ) -> models::BudgetManager {
+        let budget_manager = imported.clone().with_version(0);
+
+        for tx in budget_manager.transactions().borrow_mut().iter_mut() {
+            let payee_id = tx.payee_id().and_then(|id| payee_ids.get(id)).cloned();
+            let account_id = tx.account_id().and_then(|id| account_ids.get(id)).cloned();
+
+            *tx = tx.clone().with_payee(payee_id).with_account(account_id);
+        }
+
+        budget_manager
+    }
+
+    /// The stored pool with the imported income and assignments, assignments follow budgets that got new ids
+    fn merge_pool(
+        &self,
+        stored: models::IncomePool,
+        budget_ids: &HashMap<models::BudgetId, models::BudgetId>,
+        ids: &dyn IdGenerator,
+    ) -> models::IncomePool {
+        let imported = &self.snapshot.income_pool;
+        let mut income = stored.income().to_vec();
+        let mut assignments = stored.assignments().to_vec();
+
+        for entry in imported.income() {
+            match (income.iter().position(|i| i.id() == entry.id()), self.on_conflict) {
+                (None, _) => income.push(entry.clone()),
+                (Some(_), OnConflict::Skip) => (),
+                (Some(idx), OnConflict::Replace) => income[idx] = entry.clone(),
+                (Some(_), OnConflict::NewIds) => {
+                    income.push(models::Income::load(ids.next_id(), entry.source().to_string(), *entry.amount()))
+                }
+            }
+        }
+
+        for entry in imported.assignments() {
+            let budget_id = budget_ids.get(entry.budget_id()).unwrap_or(entry.budget_id());
+
+            match (assignments.iter().position(|a| a.id() == entry.id()), self.on_conflict) {
+                (None, _) => assignments.push(models::Assignment::load(entry.id().to_string(), *budget_id, *entry.amount())),
+                (Some(_), OnConflict::Skip) => (),
+                (Some(idx), OnConflict::Replace) => assignments[idx] = entry.clone(),
+                (Some(_), OnConflict::NewIds) => {
+                    assignments.push(models::Assignment::load(ids.next_id(), *budget_id, *entry.amount()))
+                }
+            }
+        }
+
+        models::IncomePool::load(income, assignments).with_version(stored.version())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::repository::{
+        self, AccountRepository, IncomeRepository, PayeeRepository, Repository, SnapshotRepository,
+    };
+    use crate::domain::clock::{FixedClock, SystemClock};
+    use crate::domain::ids::{RandomIds, SequentialIds};
+    use crate::adapters::in_memory_repository::InMemoryRepository;
+    use std::cell::RefCell;
+    use std::panic;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn budget_name() -> String {
+        String::from("my-budget")
+    }
+
+    fn budget_max() -> f64 {
+        200.00_f64
+    }
+
+    fn make_empty_budget_manager() -> models::BudgetManager {
+        models::BudgetManager::new(
+            models::Budget::new(&RandomIds, budget_name(), budget_max()),
+            RefCell::new(vec![]),
+        )
+    }
+
+    /// Someone else saves the budget right before each of the next `races` saves
+    struct RacingRepository {
+        inner: InMemoryRepository,
+        races: AtomicU32,
+    }
+
+    impl RacingRepository {
+        fn new() -> Self {
+            RacingRepository {
+                inner: InMemoryRepository::new(),
+                races: AtomicU32::new(0),
+            }
+        }
+
+        fn race(&self, id: &models::BudgetId) {
+            let raced = self
+                .races
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |races| races.checked_sub(1))
+                .is_ok();
+
+            if raced {
+                Repository::add(&self.inner, &Repository::get(&self.inner, id)).unwrap();
+            }
+        }
+
+        fn race_pool(&self) {
+            let raced = self
+                .races
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |races| races.checked_sub(1))
+                .is_ok();
+
+            if raced {
+                IncomeRepository::add_income_pool(&self.inner, &IncomeRepository::get_income_pool(&self.inner)).unwrap();
+            }
+        }
+    }
+
+    impl repository::Repository for RacingRepository {
+        fn add(&self, item: &models::BudgetManager) -> Result<(), ConcurrencyConflict> {
+            self.race(item.id());
+
+            Repository::add(&self.inner, item)
+        }
+
+        fn get(&self, id: &models::BudgetId) -> models::BudgetManager {
+            Repository::get(&self.inner, id)
+        }
+
+        fn get_many(&self, ids: &[models::BudgetId]) -> Vec<Option<models::BudgetManager>> {
+            Repository::get_many(&self.inner, ids)
+        }
+    }
+
+    impl repository::PayeeRepository for RacingRepository {
+        fn add_payee(&self, payee: &models::Payee) {
+            PayeeRepository::add_payee(&self.inner, payee)
+        }
+
+        fn get_payee(&self, id: &str) -> Option<models::Payee> {
+            PayeeRepository::get_payee(&self.inner, id)
+        }
+
+        fn list_payees(&self) -> Vec<models::Payee> {
+            PayeeRepository::list_payees(&self.inner)
+        }
+    }
+
+    impl repository::AccountRepository for RacingRepository {
+        fn add_account(&self, account: &models::Account) {
+            AccountRepository::add_account(&self.inner, account)
+        }
+
+        fn get_account(&self, id: &str) -> Option<models::Account> {
+            AccountRepository::get_account(&self.inner, id)
+        }
+
+        fn list_accounts(&self) -> Vec<models::Account> {
+            AccountRepository::list_accounts(&self.inner)
+        }
+
+        fn account_budget_ids(&self, account_id: &str) -> Vec<models::BudgetId> {
+            AccountRepository::account_budget_ids(&self.inner, account_id)
+        }
+
+        fn external_ids(&self) -> Vec<(Option<String>, String)> {
+            AccountRepository::external_ids(&self.inner)
+        }
+    }
+
+    impl repository::IncomeRepository for RacingRepository {
+        fn add_income_pool(&self, pool: &models::IncomePool) -> Result<(), ConcurrencyConflict> {
+            IncomeRepository::add_income_pool(&self.inner, pool)
+        }
+
+        fn get_income_pool(&self) -> models::IncomePool {
+            IncomeRepository::get_income_pool(&self.inner)
+        }
+
+        /// Someone else saves the pool first instead
+        fn add_funded(&self, items: &[models::BudgetManager], pool: &models::IncomePool) -> Result<(), ConcurrencyConflict> {
+            self.race_pool();
+
+            IncomeRepository::add_funded(&self.inner, items, pool)
+        }
+    }
+
+    impl repository::SnapshotRepository for RacingRepository {
+        /// Someone else saves the pool first instead
+        fn add_snapshot(&self, snapshot: &Snapshot) -> Result<(), ConcurrencyConflict> {
+            self.race_pool();
+
+            SnapshotRepository::add_snapshot(&self.inner, snapshot)
+        }
+    }
+
+    impl async_repository::AsyncRepository for RacingRepository {
+        async fn add(&self, item: models::BudgetManager) -> Result<models::BudgetManager, ConcurrencyConflict> {
+            self.race(item.id());
+
+            async_repository::AsyncRepository::add(&self.inner, item).await
+        }
+
+        async fn get(&self, id: &models::BudgetId) -> models::BudgetManager {
+            Repository::get(&self.inner, id)
+        }
+
+        async fn get_many(&self, ids: &[models::BudgetId]) -> Vec<Option<models::BudgetManager>> {
+            Repository::get_many(&self.inner, ids)
+        }
+
+        async fn delete(&self, id: &models::BudgetId) {
+            Repository::delete(&self.inner, id)
+        }
+    }
+
+    #[test]
+    fn user_can_create_budget() {
+        // Given
+        let cmd = CreateBudget::new(budget_name(), budget_max()).unwrap();
+        let repo = InMemoryRepository::new();
+        AddIncome::new(String::from("paycheck"), 1000.00_f64).unwrap().run(&repo, &RandomIds).unwrap();
+
+        // When
+        let bdg = cmd.run(&repo, &RandomIds).unwrap();
+
+        // Then
+        // The budget was created with the expected name and total
+        assert_eq!(bdg.name(), budget_name());
+        assert_eq!(bdg.available_funds(), budget_max());
+
+        // We can retrieve the budget from the repository
+        // and the value is equal to the value return from `run()`
+        assert_eq!(repo.get(bdg.id()), bdg);
+
+        // The total came out of the income pool
+        assert_eq!(repo.get_income_pool().ready_to_assign(), 1000.00_f64 - budget_max());
+    }
+
+    #[test]
+    fn budget_total_must_come_from_income() {
+        // Given
+        let cmd = CreateBudget::new(budget_name(), budget_max()).unwrap();
+        let repo = InMemoryRepository::new();
+        AddIncome::new(String::from("paycheck"), 100.00_f64).unwrap().run(&repo, &RandomIds).unwrap();
+
+        // When
+        let result = cmd.run(&repo, &RandomIds);
+
+        // Then
+        let Err(CommandError::Invalid(e)) = result else {
+            panic!("expected a validation error, got {:?}", result);
+        };
+        assert_eq!(e.errors[0].field, "total");
+        assert!(repository::ViewRepository::list_budgets(&repo).is_empty());
+        assert!(repo.get_income_pool().assignments().is_empty());
+    }
+
+    #[test]
+    fn budget_can_be_created_without_income() {
+        // Given
+        let cmd = CreateBudget::new(budget_name(), 0_f64).unwrap();
+        let repo = InMemoryRepository::new();
+
+        // When
+        let bdg = cmd.run(&repo, &RandomIds).unwrap();
+
+        // Then
+        assert_eq!(repo.get(bdg.id()).available_funds(), 0_f64);
+        assert!(repo.get_income_pool().assignments().is_empty());
+    }
+
+    #[test]
+    fn user_can_add_transaction() {
+        // Given
+        // Set up data required to run the test
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+
+        // Set up the command
+        let cmd = AddTransaction::new(
+            *budget_manager.id(),
+            String::from("cheeseborger"),
+            9.99_f64,
+        ).unwrap();
+
+        // When
+        cmd.run(&repo, &RandomIds, &SystemClock).unwrap();
+
+        // Then
+        let bm = repo.get(budget_manager.id());
+        assert_eq!(bm.available_funds(), 190.01_f64);
+    }
+
+    #[test]
+    fn user_can_remove_transaction() {
+        // Given
+        // Set up data required to run the test
+        let mut budget_manager = make_empty_budget_manager();
+        let transaction_id = budget_manager.add_tx(&RandomIds, String::from("cheeseborger"), 3.99_f64, SystemClock.today());
+
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+
+        // Set up the command we're going to test!
+        let cmd =
+            RemoveTransaction::new(*budget_manager.id(), transaction_id).unwrap();
+
+        // When
+        cmd.run(&repo).unwrap();
+
+        // Then
+        let bm = repo.get(budget_manager.id());
+        assert_eq!(bm.available_funds(), budget_max());
+    }
+
+    #[test]
+    fn user_can_update_transaction() {
+        // Given
+        // Set up data required to run the test
+        let mut budget_manager = make_empty_budget_manager();
+        let transaction_id = budget_manager.add_tx(&RandomIds, String::from("cheeseborger"), 3.99_f64, SystemClock.today());
+
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+
+        // Set up the command we're going to test
+        let cmd = UpdateTransaction::new(
+            *budget_manager.id(),
+            transaction_id,
+            4.99_f64,
+        ).unwrap();
+
+        // When
+        cmd.run(&repo, &SystemClock).unwrap();
+
+        // Then
+        let bm = repo.get(budget_manager.id());
+        assert_eq!(bm.available_funds(), 195.01_f64);
+    }
+
+    #[test]
+    fn user_can_create_payee() {
+        // Given
+        let cmd = CreatePayee::new(
+            String::from("Cheeseburger Palace"),
+            vec![String::from("cheeseborger")],
+        ).unwrap();
+        let repo = InMemoryRepository::new();
+
+        // When
+        let payee = cmd.run(&repo, &RandomIds).unwrap();
+
+        // Then
+        assert_eq!(payee.name(), "Cheeseburger Palace");
+        assert_eq!(repo.get_payee(payee.id()), Some(payee));
+    }
+
+    #[test]
+    fn user_can_add_alias_to_payee() {
+        // Given
+        let repo = InMemoryRepository::new();
+        let payee = CreatePayee::new(String::from("Cheeseburger Palace"), vec![]).unwrap().run(&repo, &RandomIds).unwrap();
+
+        let cmd = AddPayeeAlias::new(payee.id().to_string(), String::from("cheeseborger")).unwrap();
+
+        // When
+        cmd.run(&repo).unwrap();
+
+        // Then
+        let payee = repo.get_payee(payee.id()).unwrap();
+        assert!(payee.matches("cheeseborger"));
+    }
+
+    #[test]
+    fn payee_names_and_aliases_must_be_unique() {
+        // Given
+        let repo = InMemoryRepository::new();
+        CreatePayee::new(String::from("Cheeseburger Palace"), vec![String::from("cheeseborger")])
+            .unwrap()
+            .run(&repo, &RandomIds)
+            .unwrap();
+
+        let cmd = CreatePayee::new(String::from("cheeseburger palace"), vec![String::from("Cheeseborger")]).unwrap();
+
+        // When
+        let result = cmd.run(&repo, &RandomIds);
+
+        // Then
+        let fields: Vec<&str> = result.unwrap_err().errors.iter().map(|e| e.field).collect();
+        assert_eq!(fields, vec!["name", "alias"]);
+        assert_eq!(repo.list_payees().len(), 1);
+    }
+
+    #[test]
+    fn alias_of_another_payee_is_rejected() {
+        // Given
+        let repo = InMemoryRepository::new();
+        CreatePayee::new(String::from("Cheeseburger Palace"), vec![String::from("cheeseborger")])
+            .unwrap()
+            .run(&repo, &RandomIds)
+            .unwrap();
+        let payee = CreatePayee::new(String::from("Pizza Planet"), vec![]).unwrap().run(&repo, &RandomIds).unwrap();
+
+        let cmd = AddPayeeAlias::new(payee.id().to_string(), String::from("CHEESEBORGER")).unwrap();
+
+        // When
+        let result = cmd.run(&repo);
+
+        // Then
+        assert!(result.is_err());
+        assert_eq!(repo.get_payee(payee.id()), Some(payee));
+    }
+
+    #[test]
+    fn adding_transaction_resolves_payee_alias() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+
+        let payee = CreatePayee::new(
+            String::from("Cheeseburger Palace"),
+            vec![String::from("cheeseborger")],
+        ).unwrap()
+        .run(&repo, &RandomIds)
+        .unwrap();
+
+        let cmd = AddTransaction::new(
+            *budget_manager.id(),
+            String::from("Cheeseborger"),
+            9.99_f64,
+        ).unwrap();
+
+        // When
+        let tx_id = cmd.run(&repo, &RandomIds, &SystemClock).unwrap().transaction_id;
+
+        // Then
+        let bm = repo.get(budget_manager.id());
+        let txs = bm.transactions().borrow();
+        let tx = txs.iter().find(|tx| *tx.id() == tx_id).unwrap();
+
+        assert_eq!(tx.payee_id(), Some(payee.id()));
+    }
+
+    #[test]
+    fn adding_transaction_without_known_payee_leaves_it_unassigned() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+
+        let cmd = AddTransaction::new(
+            *budget_manager.id(),
+            String::from("cheeseborger"),
+            9.99_f64,
+        ).unwrap();
+
+        // When
+        cmd.run(&repo, &RandomIds, &SystemClock).unwrap();
+
+        // Then
+        let bm = repo.get(budget_manager.id());
+        assert_eq!(bm.transactions().borrow()[0].payee_id(), None);
+    }
+
+    fn make_account(repo: &InMemoryRepository) -> models::Account {
+        CreateAccount::new(String::from("checking"), models::AccountKind::Checking, 100.00_f64).unwrap().run(repo, &RandomIds)
+    }
+
+    #[test]
+    fn user_can_add_transaction_paid_from_account() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+        let account = make_account(&repo);
+
+        let cmd = AddTransaction::new(
+            *budget_manager.id(),
+            String::from("cheeseborger"),
+            9.99_f64,
+        ).unwrap()
+        .with_account(Some(account.id().to_string()));
+
+        // When
+        cmd.run(&repo, &RandomIds, &SystemClock).unwrap();
+
+        // Then
+        let bm = repo.get(budget_manager.id());
+        let tx = &bm.transactions().borrow()[0];
+        assert_eq!(tx.account_id(), Some(account.id()));
+        assert_eq!(tx.status(), models::TransactionStatus::Pending);
+    }
+
+    #[test]
+    fn reconcile_marks_cleared_transactions_when_statement_matches() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+        let account = make_account(&repo);
+
+        let add = |name: &str, value: f64| {
+            AddTransaction::new(*budget_manager.id(), String::from(name), value).unwrap()
+                .with_account(Some(account.id().to_string()))
+                .run(&repo, &RandomIds, &SystemClock).unwrap()
+                .transaction_id
+        };
+        let cleared_id = add("cheeseborger", 9.99_f64);
+        add("fries", 2.49_f64);
+
+        SetTransactionStatus::new(
+            *budget_manager.id(),
+            cleared_id,
+            models::TransactionStatus::Cleared,
+        ).unwrap()
+        .run(&repo).unwrap();
+
+        let cmd = ReconcileAccount::new(account.id().to_string(), 90.01_f64).unwrap();
+
+        // When
+        let result = cmd.run(&repo).unwrap();
+
+        // Then
+        assert_eq!(result.difference, 0_f64);
+        assert_eq!(result.reconciled, vec![cleared_id]);
+
+        let bm = repo.get(budget_manager.id());
+        let idx = bm.find_tx_index(&cleared_id).unwrap();
+        assert_eq!(
+            bm.transactions().borrow()[idx].status(),
+            models::TransactionStatus::Reconciled
+        );
+    }
+
+    #[test]
+    fn reconcile_reports_difference_without_reconciling() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+        let account = make_account(&repo);
+
+        let tx_id = AddTransaction::new(
+            *budget_manager.id(),
+            String::from("cheeseborger"),
+            9.99_f64,
+        ).unwrap()
+        .with_account(Some(account.id().to_string()))
+        .run(&repo, &RandomIds, &SystemClock).unwrap()
+        .transaction_id;
+
+        SetTransactionStatus::new(
+            *budget_manager.id(),
+            tx_id,
+            models::TransactionStatus::Cleared,
+        ).unwrap()
+        .run(&repo).unwrap();
+
+        let cmd = ReconcileAccount::new(account.id().to_string(), 80.01_f64).unwrap();
+
+        // When
+        let result = cmd.run(&repo).unwrap();
+
+        // Then
+        assert_eq!(result.cleared_balance, 90.01_f64);
+        assert_eq!(result.difference, -10.00_f64);
+        assert!(result.reconciled.is_empty());
+    }
+
+    #[test]
+    fn user_can_add_income() {
+        // Given
+        let repo = InMemoryRepository::new();
+        let cmd = AddIncome::new(String::from("paycheck"), 1000.00_f64).unwrap();
+
+        // When
+        cmd.run(&repo, &RandomIds).unwrap();
+
+        // Then
+        assert_eq!(repo.get_income_pool().ready_to_assign(), 1000.00_f64);
+    }
+
+    #[test]
+    fn user_can_assign_income_to_budget() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+        AddIncome::new(String::from("paycheck"), 1000.00_f64).unwrap().run(&repo, &RandomIds).unwrap();
+
+        let cmd = AssignFunds::new(*budget_manager.id(), 300.00_f64).unwrap();
+
+        // When
+        cmd.run(&repo, &RandomIds).unwrap();
+
+        // Then
+        let bm = repo.get(budget_manager.id());
+        assert_eq!(bm.available_funds(), 500.00_f64);
+        assert_eq!(repo.get_income_pool().ready_to_assign(), 700.00_f64);
+    }
+
+    #[test]
+    fn user_cant_assign_more_than_ready_to_assign() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+        AddIncome::new(String::from("paycheck"), 100.00_f64).unwrap().run(&repo, &RandomIds).unwrap();
+
+        let cmd = AssignFunds::new(*budget_manager.id(), 300.00_f64).unwrap();
+
+        // When
+        let result = cmd.run(&repo, &RandomIds);
+
+        // Then
+        assert!(matches!(result, Err(CommandError::Invalid(_))));
+        assert_eq!(repo.get(budget_manager.id()), budget_manager);
+        assert_eq!(repo.get_income_pool().ready_to_assign(), 100.00_f64);
+    }
+
+    #[test]
+    fn adding_transaction_reports_crossed_thresholds_once() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+        SetAlertThresholds::new(*budget_manager.id(), vec![50, 80]).unwrap().run(&repo).unwrap();
+
+        let add = |value: f64| {
+            AddTransaction::new(*budget_manager.id(), String::from("groceries"), value).unwrap()
+                .run(&repo, &RandomIds, &SystemClock).unwrap()
+        };
+
+        // When
+        let first = add(120.00_f64);
+        let second = add(10.00_f64);
+
+        // Then
+        let fired: Vec<u32> = first.alerts.iter().map(|a| a.threshold).collect();
+        assert_eq!(fired, vec![50]);
+        assert!(second.alerts.is_empty());
+    }
+
+    #[test]
+    fn updating_transaction_reports_crossed_thresholds() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+        SetAlertThresholds::new(*budget_manager.id(), vec![50, 80]).unwrap().run(&repo).unwrap();
+
+        let tx_id = AddTransaction::new(
+            *budget_manager.id(),
+            String::from("groceries"),
+            20.00_f64,
+        ).unwrap()
+        .run(&repo, &RandomIds, &SystemClock).unwrap()
+        .transaction_id;
+
+        let cmd = UpdateTransaction::new(*budget_manager.id(), tx_id, 170.00_f64).unwrap();
+
+        // When
+        let alerts = cmd.run(&repo, &SystemClock).unwrap();
+
+        // Then
+        let fired: Vec<u32> = alerts.iter().map(|a| a.threshold).collect();
+        assert_eq!(fired, vec![50, 80]);
+    }
+
+    #[test]
+    fn user_can_split_transaction_on_shared_budget() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+        SetBudgetMembers::new(
+            *budget_manager.id(),
+            vec![String::from("ana"), String::from("ben")],
+        ).unwrap()
+        .run(&repo).unwrap();
+
+        let cmd = AddTransaction::new(
+            *budget_manager.id(),
+            String::from("internet"),
+            60.00_f64,
+        ).unwrap()
+        .with_split(Some(String::from("ana")), None);
+
+        // When
+        cmd.run(&repo, &RandomIds, &SystemClock).unwrap();
+
+        // Then
+        let settle_up = repo.get(budget_manager.id()).settle_up();
+        assert_eq!(
+            settle_up.payments,
+            vec![models::Payment {
+                from: String::from("ben"),
+                to: String::from("ana"),
+                amount: 30.00_f64,
+            }]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn split_transaction_needs_member_who_paid() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+
+        let cmd = AddTransaction::new(
+            *budget_manager.id(),
+            String::from("internet"),
+            60.00_f64,
+        ).unwrap()
+        .with_split(Some(String::from("ana")), None);
+
+        // When
+        cmd.run(&repo, &RandomIds, &SystemClock).unwrap();
+    }
+
+    #[test]
+    fn same_commands_produce_same_ids_and_dates() {
+        // Given
+        let today = NaiveDate::from_ymd_opt(2024, 3, 15).unwrap();
+        let replay = || {
+            let repo = InMemoryRepository::new();
+            let ids = SequentialIds::new();
+            let clock = FixedClock::new(today);
+            AddIncome::new(String::from("paycheck"), 1000.00_f64).unwrap().run(&repo, &RandomIds).unwrap();
+
+            let budget_manager = CreateBudget::new(budget_name(), budget_max()).unwrap().run(&repo, &ids).unwrap();
+            AddTransaction::new(*budget_manager.id(), String::from("groceries"), 20.00_f64)
+                .unwrap()
+                .run(&repo, &ids, &clock).unwrap();
+
+            repo.get(budget_manager.id())
+        };
+
+        // When
+        let first = replay();
+        let second = replay();
+
+        // Then
+        assert_eq!(first, second);
+        assert_eq!(first.id().to_string(), "00000000-0000-0000-0000-000000000001");
+        assert_eq!(first.transactions().borrow()[0].date(), today);
+    }
+
+    #[test]
+    fn save_fails_when_budget_changed_since_it_was_loaded() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = RacingRepository::new();
+        repo.add(&budget_manager).unwrap();
+        repo.races.store(1, Ordering::SeqCst);
+
+        let cmd = SetBudgetMembers::new(*budget_manager.id(), vec![String::from("ana")]).unwrap();
+
+        // When
+        let result = cmd.run(&repo);
+
+        // Then
+        assert_eq!(
+            result,
+            Err(ConcurrencyConflict {
+                budget_id: Some(*budget_manager.id()),
+                expected: 1,
+                found: 2,
+            })
+        );
+        assert!(repo.get(budget_manager.id()).budget().members().is_empty());
+    }
+
+    #[test]
+    fn save_is_retried_from_a_fresh_read() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = RacingRepository::new();
+        repo.add(&budget_manager).unwrap();
+        repo.races.store(2, Ordering::SeqCst);
+
+        let cmd = AddTransaction::new(*budget_manager.id(), String::from("cheeseborger"), 9.99_f64)
+            .unwrap()
+            .with_retries(2);
+
+        // When
+        cmd.run(&repo, &RandomIds, &SystemClock).unwrap();
 
         // Then
         let bm = repo.get(budget_manager.id());
-        assert_eq!(bm.available_funds(), 195.01_f64);
+        assert_eq!(bm.version(), 4);
+        assert_eq!(bm.transactions().borrow().len(), 1);
+        assert_eq!(bm.available_funds(), 190.01_f64);
+    }
+
+    #[tokio::test]
+    async fn async_run_gives_same_result_as_blocking_run() {
+        // Given
+        let repo = InMemoryRepository::new();
+        let clock = FixedClock::new(NaiveDate::from_ymd_opt(2023, 9, 14).unwrap());
+        AddIncome::new(String::from("paycheck"), 1000.00_f64)
+            .unwrap()
+            .run_async(&repo, &RandomIds)
+            .await
+            .unwrap();
+        let budget_manager = CreateBudget::new(budget_name(), budget_max())
+            .unwrap()
+            .run_async(&repo, &RandomIds)
+            .await
+            .unwrap();
+
+        let cmd = AddTransaction::new(*budget_manager.id(), String::from("cheeseborger"), 9.99_f64).unwrap();
+
+        // When
+        let async_added = cmd.run_async(&repo, &SequentialIds::new(), &clock).await.unwrap();
+        let added = cmd.run(&repo, &SequentialIds::starting_at(2), &clock).unwrap();
+
+        // Then
+        let bm = repo.get(budget_manager.id());
+        assert_eq!(bm.version(), 3);
+        assert_eq!(bm.transactions().borrow().len(), 2);
+        assert_eq!(async_added.transaction_id.to_string(), "00000000-0000-0000-0000-000000000001");
+        assert_eq!(added.transaction_id.to_string(), "00000000-0000-0000-0000-000000000002");
+    }
+
+    #[tokio::test]
+    async fn async_save_is_retried_from_a_fresh_read() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = RacingRepository::new();
+        repo.add(&budget_manager).unwrap();
+        repo.races.store(1, Ordering::SeqCst);
+
+        let cmd = SetBudgetMembers::new(*budget_manager.id(), vec![String::from("ann"), String::from("bob")]).unwrap();
+
+        // When
+        let conflict = cmd.run_async(&repo).await;
+        let saved = cmd.with_retries(1).run_async(&repo).await.unwrap();
+
+        // Then
+        assert!(conflict.is_err());
+        assert_eq!(saved.version(), 3);
+        assert_eq!(repo.get(budget_manager.id()).budget().members().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn async_reconcile_saves_reconciled_budgets() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+        let account = CreateAccount::new(String::from("checking"), models::AccountKind::Checking, 100.00_f64)
+            .unwrap()
+            .run_async(&repo, &RandomIds)
+            .await;
+
+        let added = AddTransaction::new(*budget_manager.id(), String::from("cheeseborger"), 9.99_f64)
+            .unwrap()
+            .with_account(Some(account.id().to_string()))
+            .run_async(&repo, &RandomIds, &SystemClock)
+            .await
+            .unwrap();
+        SetTransactionStatus::new(*budget_manager.id(), added.transaction_id, models::TransactionStatus::Cleared)
+            .unwrap()
+            .run_async(&repo)
+            .await
+            .unwrap();
+
+        // When
+        let result = ReconcileAccount::new(account.id().to_string(), 90.01_f64)
+            .unwrap()
+            .run_async(&repo)
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(result.reconciled, vec![added.transaction_id]);
+        let bm = repo.get(budget_manager.id());
+        assert_eq!(bm.transactions().borrow()[0].status(), models::TransactionStatus::Reconciled);
+    }
+
+    #[test]
+    fn import_transactions_adds_every_transaction_in_one_save() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+        let payee = CreatePayee::new(String::from("Grocer"), vec![String::from("apples")])
+            .unwrap()
+            .run(&repo, &RandomIds)
+            .unwrap();
+        let transactions = vec![
+            AddTransaction::new(*budget_manager.id(), String::from("apples"), 3.50_f64).unwrap(),
+            AddTransaction::new(*budget_manager.id(), String::from("pears"), 2.00_f64).unwrap(),
+        ];
+
+        // When
+        let added = ImportTransactions::new(*budget_manager.id(), transactions)
+            .run(&repo, &RandomIds, &SystemClock)
+            .unwrap();
+
+        // Then
+        let bm = repo.get(budget_manager.id());
+        assert_eq!(added.len(), 2);
+        assert_eq!(bm.version(), 2);
+        assert_eq!(bm.transactions().borrow()[0].payee_id(), Some(payee.id()));
+        assert_eq!(bm.transactions().borrow()[1].id(), &added[1].transaction_id);
+    }
+
+    #[test]
+    fn import_transactions_adds_nothing_when_one_cant_be_added() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+        let transactions = vec![
+            AddTransaction::new(*budget_manager.id(), String::from("rent"), 150.00_f64).unwrap(),
+            AddTransaction::new(*budget_manager.id(), String::from("tv"), 100.00_f64).unwrap(),
+        ];
+
+        // When
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            ImportTransactions::new(*budget_manager.id(), transactions).run(&repo, &RandomIds, &SystemClock)
+        }));
+
+        // Then
+        assert!(result.is_err());
+        assert!(repo.get(budget_manager.id()).transactions().borrow().is_empty());
+    }
+
+    #[test]
+    fn import_transactions_leaves_out_transactions_imported_before() {
+        // Given
+        let budget_manager = make_empty_budget_manager();
+        let repo = InMemoryRepository::new();
+        repo.add(&budget_manager).unwrap();
+        let statement = |ids: &[&str]| -> Vec<AddTransaction> {
+            ids.iter()
+                .map(|id| {
+                    AddTransaction::new(*budget_manager.id(), format!("tx {}", id), 1.00_f64)
+                        .unwrap()
+                        .with_external_id(Some(id.to_string()))
+                })
+                .collect()
+        };
+        ImportTransactions::new(*budget_manager.id(), statement(&["1", "2"]))
+            .run(&repo, &RandomIds, &SystemClock)
+            .unwrap();
+
+        // When
+        let added = ImportTransactions::new(*budget_manager.id(), statement(&["2", "3", "3"]))
+            .run(&repo, &RandomIds, &SystemClock)
+            .unwrap();
+
+        // Then
+        let bm = repo.get(budget_manager.id());
+        let external_ids: Vec<String> = bm
+            .transactions()
+            .borrow()
+            .iter()
+            .filter_map(|tx| tx.external_id().map(String::from))
+            .collect();
+        assert_eq!(added.len(), 1);
+        assert_eq!(external_ids, vec!["1", "2", "3"]);
+    }
+
+    #[test]
+    fn import_transactions_leaves_out_what_another_budget_imported_from_the_same_account() {
+        // Given
+        let (groceries, rent) = (make_empty_budget_manager(), make_empty_budget_manager());
+        let repo = InMemoryRepository::new();
+        repo.add(&groceries).unwrap();
+        repo.add(&rent).unwrap();
+        let checking = make_account(&repo);
+        let statement = |budget_id: models::BudgetId, account: Option<&models::Account>| -> Vec<AddTransaction> {
+            vec![AddTransaction::new(budget_id, String::from("grocer"), 1.00_f64)
+                .unwrap()
+                .with_account(account.map(|a| a.id().to_string()))
+                .with_external_id(Some(String::from("fitid-1")))]
+        };
+        ImportTransactions::new(*groceries.id(), statement(*groceries.id(), Some(&checking)))
+            .run(&repo, &RandomIds, &SystemClock)
+            .unwrap();
+
+        // When
+        let same_account = ImportTransactions::new(*rent.id(), statement(*rent.id(), Some(&checking)))
+            .run(&repo, &RandomIds, &SystemClock)
+            .unwrap();
+        let no_account = ImportTransactions::new(*rent.id(), statement(*rent.id(), None))
+            .run(&repo, &RandomIds, &SystemClock)
+            .unwrap();
+
+        // Then
+        assert_eq!(same_account.len(), 0);
+        assert_eq!(no_account.len(), 1);
+    }
+
+    /// Transactions for the budget named like the category, with external ids made from their place and amount
+    fn categorized(category: &str, amounts: &[f64]) -> (String, Vec<AddTransaction>) {
+        let transactions = amounts
+            .iter()
+            .enumerate()
+            .map(|(n, amount)| {
+                AddTransaction::new(models::BudgetId::new(), format!("{} {}", category, n), *amount)
+                    .unwrap()
+                    .with_external_id(Some(format!("{}-{}", n, amount)))
+            })
+            .collect();
+
+        (category.to_string(), transactions)
+    }
+
+    #[test]
+    fn import_by_category_adds_to_stored_budgets_and_creates_missing_ones() {
+        // Given
+        let repo = InMemoryRepository::new();
+        AddIncome::new(String::from("paycheck"), 100.00_f64).unwrap().run(&repo, &RandomIds).unwrap();
+        let stored = CreateBudget::new(budget_name(), 20.00_f64).unwrap().run(&repo, &RandomIds).unwrap();
+        let categories = vec![categorized("My-Budget", &[5.00_f64]), categorized("Groceries", &[3.50_f64, 2.00_f64])];
+
+        // When
+        let imported = ImportByCategory::new(categories).run(&repo, &RandomIds, &SystemClock).unwrap();
+
+        // Then
+        assert_eq!(imported[0].budget_id, *stored.id());
+        assert_eq!(imported[0].created_with, None);
+        assert_eq!(repo.get(stored.id()).transactions().borrow().len(), 1);
+
+        let created = repo.get(&imported[1].budget_id);
+        assert_eq!(imported[1].created_with, Some(6.00_f64));
+        assert_eq!(created.name(), "Groceries");
+        assert_eq!(created.available_funds(), 6.00_f64 - 5.50_f64);
+        assert_eq!(created.transactions().borrow()[1].id(), &imported[1].added[1].transaction_id);
+        assert_eq!(repo.get_income_pool().ready_to_assign(), 100.00_f64 - 20.00_f64 - 6.00_f64);
+    }
+
+    #[test]
+    fn import_by_category_saves_nothing_when_the_pool_cant_fund_a_new_budget() {
+        // Given
+        let repo = InMemoryRepository::new();
+        AddIncome::new(String::from("paycheck"), 25.00_f64).unwrap().run(&repo, &RandomIds).unwrap();
+        let stored = CreateBudget::new(budget_name(), 20.00_f64).unwrap().run(&repo, &RandomIds).unwrap();
+        let categories = vec![categorized(&budget_name(), &[5.00_f64]), categorized("Groceries", &[9.50_f64])];
+
+        // When
+        let result = ImportByCategory::new(categories).run(&repo, &RandomIds, &SystemClock);
+
+        // Then
+        assert_eq!(
+            result,
+            Err(CommandError::Invalid(ValidationError {
+                errors: vec![FieldError::new("total", "cannot assign 10.00, only 5.00 is ready to assign")],
+            }))
+        );
+        assert_eq!(repository::ViewRepository::list_budgets(&repo).len(), 1);
+        assert!(repo.get(stored.id()).transactions().borrow().is_empty());
+        assert_eq!(repo.get_income_pool().ready_to_assign(), 5.00_f64);
+    }
+
+    #[test]
+    fn import_by_category_leaves_out_transactions_imported_before() {
+        // Given
+        let repo = InMemoryRepository::new();
+        AddIncome::new(String::from("paycheck"), 100.00_f64).unwrap().run(&repo, &RandomIds).unwrap();
+        ImportByCategory::new(vec![categorized("Groceries", &[3.50_f64])])
+            .run(&repo, &RandomIds, &SystemClock)
+            .unwrap();
+
+        // When
+        let imported = ImportByCategory::new(vec![categorized("groceries", &[3.50_f64, 0.25_f64])])
+            .run(&repo, &RandomIds, &SystemClock)
+            .unwrap();
+
+        // Then
+        assert_eq!(imported[0].created_with, None);
+        assert_eq!(imported[0].added.len(), 1);
+        assert_eq!(repo.get(&imported[0].budget_id).transactions().borrow().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn async_import_by_category_gives_same_result_as_blocking_import() {
+        // Given
+        let (blocking, non_blocking) = (InMemoryRepository::new(), InMemoryRepository::new());
+        for repo in [&blocking, &non_blocking] {
+            AddIncome::new(String::from("paycheck"), 100.00_f64)
+                .unwrap()
+                .run(repo, &SequentialIds::new())
+                .unwrap();
+        }
+        let clock = FixedClock::new(NaiveDate::from_ymd_opt(2023, 9, 14).unwrap());
+        let categories = || vec![categorized("Groceries", &[3.50_f64]), categorized("Rent", &[50.00_f64])];
+
+        // When
+        let expected = ImportByCategory::new(categories())
+            .run(&blocking, &SequentialIds::starting_at(100), &clock)
+            .unwrap();
+        let result = ImportByCategory::new(categories())
+            .run_async(&non_blocking, &SequentialIds::starting_at(100), &clock)
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(result, expected);
+        assert_eq!(non_blocking.snapshot(), blocking.snapshot());
+    }
+
+    /// A budget with a transaction at a payee, and some of the income assigned to it
+    fn make_exported_repository() -> InMemoryRepository {
+        let repo = InMemoryRepository::new();
+        let ids = SequentialIds::new();
+        let budget_manager = make_empty_budget_manager();
+        repo.add(&budget_manager).unwrap();
+
+        CreatePayee::new(String::from("Grocer"), vec![String::from("apples")]).unwrap().run(&repo, &ids).unwrap();
+        AddTransaction::new(*budget_manager.id(), String::from("apples"), 3.50_f64)
+            .unwrap()
+            .run(&repo, &ids, &SystemClock)
+            .unwrap();
+        AddIncome::new(String::from("salary"), 100.00_f64).unwrap().run(&repo, &ids).unwrap();
+        AssignFunds::new(*budget_manager.id(), 40.00_f64).unwrap().run(&repo, &ids).unwrap();
+
+        repo
+    }
+
+    #[test]
+    fn import_into_empty_repository_copies_everything() {
+        // Given
+        let source = make_exported_repository();
+        let target = InMemoryRepository::new();
+
+        // When
+        let summary = ImportSnapshot::new(source.snapshot()).run(&target, &RandomIds).unwrap();
+
+        // Then
+        assert_eq!(summary.budgets_added, 1);
+        assert_eq!(summary.payees_added, 1);
+
+        // Versions count the saves to a repository, so they start over
+        let (imported, exported) = (target.snapshot(), source.snapshot());
+        assert_eq!(imported.budgets[0].version(), 1);
+        assert_eq!(imported.budgets[0].clone().with_version(exported.budgets[0].version()), exported.budgets[0]);
+        assert_eq!(imported.payees, exported.payees);
+        assert_eq!(imported.income_pool.income(), exported.income_pool.income());
+        assert_eq!(imported.income_pool.assignments(), exported.income_pool.assignments());
+    }
+
+    #[test]
+    fn import_skips_or_replaces_budgets_that_are_already_stored() {
+        // Given
+        let source = make_exported_repository();
+        let target = InMemoryRepository::from_snapshot(source.snapshot());
+        let mut budget_manager = source.snapshot().budgets.remove(0);
+        budget_manager.add_tx(&RandomIds, String::from("pears"), 2.00_f64, SystemClock.today());
+        source.add(&budget_manager).unwrap();
+
+        // When
+        let skipped = ImportSnapshot::new(source.snapshot()).run(&target, &RandomIds).unwrap();
+        let kept = target.get(budget_manager.id());
+        let replaced = ImportSnapshot::new(source.snapshot())
+            .with_on_conflict(OnConflict::Replace)
+            .run(&target, &RandomIds)
+            .unwrap();
+
+        // Then
+        assert_eq!(skipped.budgets_skipped, 1);
+        assert_eq!(kept.transactions().borrow().len(), 1);
+        assert_eq!(replaced.budgets_replaced, 1);
+        assert_eq!(target.get(budget_manager.id()).transactions().borrow().len(), 2);
+        assert_eq!(target.get_income_pool().income(), source.get_income_pool().income());
+        assert_eq!(target.get_income_pool().assignments(), source.get_income_pool().assignments());
+    }
+
+    #[test]
+    fn import_with_new_ids_adds_a_copy_and_matches_payees_by_name() {
+        // Given
+        let source = make_exported_repository();
+        let target = InMemoryRepository::from_snapshot(source.snapshot());
+        let stored_payee = target.list_payees().remove(0);
+        target.add_payee(&models::Payee::load(String::from("renamed"), String::from("Renamed"), vec![]));
+        let mut snapshot = source.snapshot();
+        snapshot.payees = vec![models::Payee::load(String::from("other-id"), String::from("Grocer"), vec![])];
+        for tx in snapshot.budgets[0].transactions().borrow_mut().iter_mut() {
+            *tx = tx.clone().with_payee(Some(String::from("other-id")));
+        }
+
+        // When
+        let summary = ImportSnapshot::new(snapshot)
+            .with_on_conflict(OnConflict::NewIds)
+            .run(&target, &SequentialIds::starting_at(100))
+            .unwrap();
+
+        // Then
+        assert_eq!(summary.budgets_added, 1);
+        assert_eq!(summary.payees_added, 0);
+
+        let budgets = target.snapshot().budgets;
+        assert_eq!(budgets.len(), 2);
+        assert_ne!(budgets[0].id(), budgets[1].id());
+        assert_eq!(budgets[1].transactions().borrow()[0].payee_id(), Some(stored_payee.id()));
+
+        let pool = target.get_income_pool();
+        assert_eq!(pool.total_income(), 200.00_f64);
+        assert_eq!(pool.assignments()[1].budget_id(), budgets[1].id());
+    }
+
+    #[test]
+    fn import_saves_nothing_when_the_pool_was_saved_meanwhile() {
+        // Given
+        let source = make_exported_repository();
+        let target = RacingRepository::new();
+        target.races.store(1, Ordering::SeqCst);
+
+        // When
+        let result = ImportSnapshot::new(source.snapshot()).run(&target, &RandomIds);
+
+        // Then
+        assert_eq!(
+            result,
+            Err(ConcurrencyConflict {
+                budget_id: None,
+                expected: 0,
+                found: 1,
+            })
+        );
+        assert_eq!(target.inner.snapshot().budgets.len(), 0);
+        assert!(target.list_payees().is_empty());
+        assert_eq!(target.get_income_pool().total_income(), 0_f64);
+    }
+
+    #[test]
+    fn import_replacing_a_payee_keeps_names_unique() {
+        // Given
+        let source = make_exported_repository();
+        let target = InMemoryRepository::from_snapshot(source.snapshot());
+        let stored_payee = target.list_payees().remove(0);
+        target.add_payee(&models::Payee::load(String::from("other-id"), String::from("Corner Grocer"), vec![]));
+        let mut snapshot = source.snapshot();
+        snapshot.payees = vec![models::Payee::load(
+            stored_payee.id().to_string(),
+            String::from("Corner Grocer"),
+            vec![],
+        )];
+
+        // When
+        let summary = ImportSnapshot::new(snapshot)
+            .with_on_conflict(OnConflict::Replace)
+            .run(&target, &RandomIds)
+            .unwrap();
+
+        // Then
+        assert_eq!(summary.payees_added, 0);
+        assert_eq!(target.get_payee(stored_payee.id()), Some(stored_payee));
+    }
+
+    #[tokio::test]
+    async fn async_import_gives_same_result_as_blocking_import() {
+        // Given
+        let source = make_exported_repository();
+        let blocking = InMemoryRepository::new();
+        let non_blocking = InMemoryRepository::new();
+        let cmd = ImportSnapshot::new(source.snapshot());
+
+        // When
+        let expected = cmd.run(&blocking, &RandomIds).unwrap();
+        let result = cmd.run_async(&non_blocking, &RandomIds).await.unwrap();
+
+        // Then
+        assert_eq!(result, expected);
+        assert_eq!(non_blocking.snapshot(), blocking.snapshot());
+    }
+
+    #[test]
+    fn create_budget_rejects_empty_name_and_invalid_total() {
+        let err = CreateBudget::new(String::from("  "), f64::NAN).err().unwrap();
+
+        assert_eq!(
+            err.errors,
+            vec![
+                validation::FieldError::new("name", "must not be empty"),
+                validation::FieldError::new("total", "must be a number"),
+            ]
+        );
+        assert!(CreateBudget::new(budget_name(), -1.00_f64).is_err());
+    }
+
+    #[test]
+    fn add_transaction_rejects_negative_amount() {
+        let err = AddTransaction::new(
+            models::BudgetId::new(),
+            String::from("refund"),
+            -9.99_f64,
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            err.errors,
+            vec![validation::FieldError::new("amount", "must be greater than zero")]
+        );
     }
 }
@@ -0,0 +1,176 @@
+//! Checks for the input handed to the handlers, shared with the CLI's argument parsers
+//! so both report the same message for the same mistake
+
+use std::{error, fmt};
+
+use serde::Serialize;
+
+const MAX_NAME_LENGTH: usize = 100;
+
+/// A problem with a single input field
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        FieldError {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Every problem found with a command's input
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidationError {
+    pub errors: Vec<FieldError>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<String> = self.errors.iter().map(|e| e.to_string()).collect();
+
+        write!(f, "invalid input, {}", errors.join(", "))
+    }
+}
+
+impl error::Error for ValidationError {}
+
+/// Collects field errors so a command reports all of its problems at once
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Validator::default()
+    }
+
+    pub fn check(mut self, result: Result<(), FieldError>) -> Self {
+        if let Err(e) = result {
+            self.errors.push(e);
+        }
+
+        self
+    }
+
+    pub fn finish(self) -> Result<(), ValidationError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError {
+                errors: self.errors,
+            })
+        }
+    }
+}
+
+pub fn name(field: &'static str, value: &str) -> Result<(), FieldError> {
+    if value.trim().is_empty() {
+        return Err(FieldError::new(field, "must not be empty"));
+    }
+
+    if value.chars().count() > MAX_NAME_LENGTH {
+        return Err(FieldError::new(
+            field,
+            format!("must be at most {} characters", MAX_NAME_LENGTH),
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn id(field: &'static str, value: &str) -> Result<(), FieldError> {
+    if value.trim().is_empty() {
+        return Err(FieldError::new(field, "must not be empty"));
+    }
+
+    Ok(())
+}
+
+/// Any real number, e.g., a balance that may be overdrawn
+pub fn finite(field: &'static str, value: f64) -> Result<(), FieldError> {
+    if !value.is_finite() {
+        return Err(FieldError::new(field, "must be a number"));
+    }
+
+    Ok(())
+}
+
+/// Zero or more, e.g., the total of a budget that is funded later
+pub fn non_negative(field: &'static str, value: f64) -> Result<(), FieldError> {
+    finite(field, value)?;
+
+    if value < 0_f64 {
+        return Err(FieldError::new(field, "must not be negative"));
+    }
+
+    Ok(())
+}
+
+/// More than zero, e.g., the amount of a transaction
+pub fn positive(field: &'static str, value: f64) -> Result<(), FieldError> {
+    finite(field, value)?;
+
+    if value <= 0_f64 {
+        return Err(FieldError::new(field, "must be greater than zero"));
+    }
+
+    Ok(())
+}
+
+pub fn percent(field: &'static str, value: u32) -> Result<(), FieldError> {
+    if value == 0 {
+        return Err(FieldError::new(field, "must be greater than zero"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_must_have_content() {
+        assert!(name("name", "groceries").is_ok());
+        assert_eq!(
+            name("name", "   "),
+            Err(FieldError::new("name", "must not be empty"))
+        );
+        assert!(name("name", &"a".repeat(101)).is_err());
+    }
+
+    #[test]
+    fn amounts_must_be_real_numbers() {
+        assert!(finite("total", -1.00_f64).is_ok());
+        assert!(finite("total", f64::NAN).is_err());
+        assert!(non_negative("total", f64::INFINITY).is_err());
+        assert!(non_negative("total", 0_f64).is_ok());
+        assert!(non_negative("total", -0.01_f64).is_err());
+        assert!(positive("amount", 0_f64).is_err());
+        assert!(positive("amount", 0.01_f64).is_ok());
+    }
+
+    #[test]
+    fn validator_collects_every_error() {
+        let result = Validator::new()
+            .check(name("name", ""))
+            .check(positive("amount", 1.00_f64))
+            .check(non_negative("total", f64::NAN))
+            .finish();
+
+        let fields: Vec<&str> = result.unwrap_err().errors.iter().map(|e| e.field).collect();
+        assert_eq!(fields, vec!["name", "total"]);
+    }
+}
@@ -1,44 +1,219 @@
 use crate::{adapters::repository, domain::models};
-use rusqlite::params;
+use crate::domain::models::Snapshot;
+use chrono::NaiveDate;
+use repository::{AccountRepository, IncomeRepository, PayeeRepository, Repository};
+use serde::Serialize;
 
-pub fn list_budgets(repo: &repository::SQLiteRepository) -> Vec<models::Budget> {
-    let conn = repo.conn.borrow();
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PayeeSpend {
+    pub payee_id: String,
+    pub name: String,
+    pub total: f64,
+    pub transactions: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountBalance {
+    #[serde(flatten)]
+    pub account: models::Account,
+    pub cleared_balance: f64,
+    pub working_balance: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BudgetForecast {
+    #[serde(flatten)]
+    pub budget: models::Budget,
+    pub forecast: models::Forecast,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IncomeSummary {
+    pub total_income: f64,
+    pub total_assigned: f64,
+    pub ready_to_assign: f64,
+}
+
+/// Everything in the repository, e.g., to export it
+pub fn snapshot<T: repository::Storage>(repo: &T) -> Snapshot {
+    let ids: Vec<models::BudgetId> = repo.list_budgets().iter().map(|budget| *budget.id()).collect();
+
+    Snapshot {
+        budgets: repo.get_many(&ids).into_iter().flatten().collect(),
+        payees: repo.list_payees(),
+        accounts: repo.list_accounts(),
+        income_pool: repo.get_income_pool(),
+    }
+}
+
+pub fn list_budgets<T: repository::ViewRepository>(repo: &T) -> Vec<models::Budget> {
+    repo.list_budgets()
+}
+
+/// Every budget next to its spending pace as of `today`, oldest first
+pub fn list_budgets_with_forecast<T>(today: NaiveDate, repo: &T) -> Vec<BudgetForecast>
+where
+    T: Repository + repository::ViewRepository,
+{
+    let budgets = repo.list_budgets();
+    let ids: Vec<models::BudgetId> = budgets.iter().map(|budget| *budget.id()).collect();
+
+    budgets
+        .into_iter()
+        .zip(repo.get_many(&ids))
+        .filter_map(|(budget, budget_manager)| {
+            // Left out when it was deleted after being listed
+            Some(BudgetForecast {
+                budget,
+                forecast: budget_manager?.forecast(today),
+            })
+        })
+        .collect()
+}
+
+/// Spending pace for a budget as of `today`
+pub fn forecast<T: Repository>(budget_id: &models::BudgetId, today: NaiveDate, repo: &T) -> models::Forecast {
+    repo.get(budget_id).forecast(today)
+}
+
+/// Who owes whom in a shared budget
+pub fn settle_up<T: Repository>(budget_id: &models::BudgetId, repo: &T) -> models::SettleUp {
+    repo.get(budget_id).settle_up()
+}
+
+pub fn list_transactions<T: repository::ViewRepository>(budget_id: &models::BudgetId, repo: &T) -> Vec<models::Transaction> {
+    repo.list_transactions(budget_id)
+}
+
+pub fn list_payees<T: PayeeRepository>(repo: &T) -> Vec<models::Payee> {
+    repo.list_payees()
+}
 
-    let mut stmt = conn.prepare("SELECT * from budgets").unwrap();
-    let budget_itr = stmt.query_map(params![], |row| {
-        Ok(models::Budget::load(
-            row.get(0)?,
-            row.get(1)?,
-            row.get(2)?,
-        ))
-    }).unwrap();
+pub fn list_accounts<T>(repo: &T) -> Vec<AccountBalance>
+where
+    T: AccountRepository + repository::ViewRepository,
+{
+    repo.list_accounts()
+        .into_iter()
+        .map(|account| {
+            // Pending transactions are already spent even though the bank hasn't seen them yet
+            let (cleared, pending) = repo.account_spend(account.id());
+
+            AccountBalance {
+                cleared_balance: models::round_cents(account.opening_balance() - cleared),
+                working_balance: models::round_cents(account.opening_balance() - cleared - pending),
+                account,
+            }
+        })
+        .collect()
+}
+
+pub fn list_income<T: IncomeRepository>(repo: &T) -> Vec<models::Income> {
+    repo.get_income_pool().income().to_vec()
+}
 
-    let loaded_budgets = budget_itr.map(|f| f.unwrap()).collect();
+/// How much income there is and how much of it still needs to be assigned to a budget
+pub fn income_summary<T: IncomeRepository>(repo: &T) -> IncomeSummary {
+    let pool = repo.get_income_pool();
 
-    loaded_budgets
+    IncomeSummary {
+        total_income: pool.total_income(),
+        total_assigned: pool.total_assigned(),
+        ready_to_assign: pool.ready_to_assign(),
+    }
 }
 
-pub fn list_transactions(budget_id: &str, repo: &repository::SQLiteRepository) -> Vec<models::Transaction> {
-    let conn = repo.conn.borrow();
+/// Total spend per payee across every budget, largest first
+pub fn payee_totals<T>(repo: &T) -> Vec<PayeeSpend>
+where
+    T: PayeeRepository + repository::ViewRepository,
+{
+    let mut spend = repo.payee_spend();
 
-    let mut stmt = conn.prepare("SELECT * FROM transactions WHERE budget_id = ?1").unwrap();
+    // Payees come sorted by name, the stable sort keeps that order for equal totals
+    let mut totals: Vec<PayeeSpend> = repo
+        .list_payees()
+        .into_iter()
+        .map(|payee| {
+            let (total, transactions) = spend.remove(payee.id()).unwrap_or_default();
 
-    let tx_iter = stmt.query_map(params![budget_id], |row| {
-        Ok(models::Transaction::load(
-            row.get(0)?,
-            row.get(1)?,
-            row.get(2)?,
-            row.get(3)?,
-        ))
-    }).unwrap();
+            PayeeSpend {
+                payee_id: payee.id().to_string(),
+                name: payee.name().to_string(),
+                total,
+                transactions,
+            }
+        })
+        .collect();
 
-    let tx: Vec<models::Transaction> = tx_iter.map(|f| f.unwrap()).collect();
+    totals.sort_by(|a, b| b.total.total_cmp(&a.total));
 
-    tx
+    totals
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::clock::{Clock, SystemClock};
+    use crate::domain::ids::RandomIds;
+    use crate::adapters::in_memory_repository::InMemoryRepository;
+    use std::cell::RefCell;
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-// }
+    #[test]
+    fn budgets_are_listed_with_their_forecast() {
+        // Given
+        let repo = InMemoryRepository::new();
+        let today = NaiveDate::from_ymd_opt(2024, 4, 10).unwrap();
+
+        let groceries = models::Budget::new(&RandomIds, String::from("Groceries"), 300.00_f64);
+        let tx = models::Transaction::new(&RandomIds, String::from("apples"), 100.00_f64, *groceries.id(), SystemClock.today())
+            .with_date(NaiveDate::from_ymd_opt(2024, 4, 1).unwrap());
+        let mut groceries = models::BudgetManager::new(groceries, RefCell::new(vec![]));
+        groceries.add_transaction(tx);
+        repo.add(&groceries).unwrap();
+
+        let rent = models::BudgetManager::new(models::Budget::new(&RandomIds, String::from("Rent"), 50.00_f64), RefCell::new(vec![]));
+        repo.add(&rent).unwrap();
+
+        // When
+        let listed = list_budgets_with_forecast(today, &repo);
+
+        // Then
+        let names: Vec<&str> = listed.iter().map(|b| b.budget.name()).collect();
+        assert_eq!(names, vec!["Groceries", "Rent"]);
+        assert_eq!(listed[0].forecast, groceries.forecast(today));
+        assert_eq!(listed[1].forecast.daily_burn_rate, 0_f64);
+    }
+
+    #[test]
+    fn payee_totals_are_largest_first_and_include_payees_without_spend() {
+        // Given
+        let repo = InMemoryRepository::new();
+        let grocer = models::Payee::new(&RandomIds, String::from("Grocer"), vec![]);
+        let bakery = models::Payee::new(&RandomIds, String::from("Bakery"), vec![]);
+        let cinema = models::Payee::new(&RandomIds, String::from("Cinema"), vec![]);
+        for payee in [&grocer, &bakery, &cinema] {
+            repo.add_payee(payee);
+        }
+
+        let budget = models::Budget::new(&RandomIds, String::from("Groceries"), 200.00_f64);
+        let id = *budget.id();
+        let txs = vec![
+            models::Transaction::new(&RandomIds, String::from("bread"), 2.50_f64, id, SystemClock.today()).with_payee(Some(bakery.id().to_string())),
+            models::Transaction::new(&RandomIds, String::from("apples"), 3.00_f64, id, SystemClock.today()).with_payee(Some(grocer.id().to_string())),
+            models::Transaction::new(&RandomIds, String::from("milk"), 1.50_f64, id, SystemClock.today()).with_payee(Some(grocer.id().to_string())),
+            models::Transaction::new(&RandomIds, String::from("market"), 9.00_f64, id, SystemClock.today()),
+        ];
+        repo.add(&models::BudgetManager::new(budget, RefCell::new(txs))).unwrap();
+
+        // When
+        let totals = payee_totals(&repo);
+
+        // Then
+        let totals: Vec<(&str, f64, i64)> = totals.iter().map(|p| (p.name.as_str(), p.total, p.transactions)).collect();
+        assert_eq!(
+            totals,
+            vec![("Grocer", 4.50_f64, 2), ("Bakery", 2.50_f64, 1), ("Cinema", 0_f64, 0)]
+        );
+    }
+}